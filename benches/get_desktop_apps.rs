@@ -3,11 +3,14 @@ use criterion::{Criterion, criterion_group, criterion_main};
 use pprof::criterion::{Output, PProfProfiler};
 use pprof::flamegraph;
 
-use lumin::apps::{OSAppSearcher, desktop_entry::LinuxAppSearcher};
+use lumin::apps::{OSAppSearcher, desktop_entry, desktop_entry::LinuxAppSearcher};
 
 pub fn criterion_benchmark(c: &mut Criterion) {
     let searcher = LinuxAppSearcher::default();
     c.bench_function("get apps", |b| b.iter(|| searcher.get_apps()));
+    c.bench_function("get apps uncached", |b| {
+        b.iter(desktop_entry::load_desktop_entries_uncached)
+    });
 }
 
 criterion_group! {
@@ -0,0 +1,38 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+
+use pprof::criterion::{Output, PProfProfiler};
+use pprof::flamegraph;
+
+use lumin::serworse;
+
+/// Roughly the shape of a big .desktop file, with lots of translated keys and actions
+fn make_input() -> String {
+    let mut input =
+        String::from("# Generated for benchmarking\n[Desktop Entry]\nType=Application\n");
+    for i in 0..200 {
+        input += &format!("Name[lang{i}]=Translated name {i}\n");
+        input += &format!("Comment[lang{i}] = A longer translated comment {i}\n");
+    }
+    input += "Exec=app %U\nCategories=Utility;TextEditor;\n";
+    for i in 0..20 {
+        input += &format!("\n[Desktop Action action{i}]\nName=Action {i}\nExec=app --action {i}\n");
+    }
+    input
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let input = make_input();
+    c.bench_function("parse ini", |b| {
+        b.iter(|| serworse::parse_ini_format(std::hint::black_box(&input)))
+    });
+    c.bench_function("tokenize ini", |b| {
+        b.iter(|| serworse::IniTokenizer::new(std::hint::black_box(&input)).count())
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().with_profiler(PProfProfiler::new(100, Output::Flamegraph(Some(flamegraph::Options::default()))));
+    targets = criterion_benchmark
+}
+criterion_main!(benches);
@@ -3,57 +3,23 @@
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
-    sync::LazyLock,
     vec::Vec,
 };
 
-use icon;
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
+use super::index_cache::IndexCache;
 use super::{App, Icon};
-use crate::constants;
+use crate::launch;
 use crate::serworse;
+use crate::util;
 
 #[derive(Default)]
-pub struct LinuxAppSearcher {
-    icon_searcher: icon::Icons,
-}
-
-static ICON_THEME_NAME: LazyLock<String> = LazyLock::new(|| {
-    let get_gtk3 = || -> Option<String> {
-        Some(
-            serworse::parse_ini_format(
-                &std::fs::read_to_string(
-                    constants::HOME_DIR.clone() + "/.config/gtk-3.0/settings.ini",
-                )
-                .ok()?,
-            )
-            .ok()?
-            .get("Settings")?
-            .get("gtk-icon-theme-name")?
-            .to_string(),
-        )
-    };
-
-    let get_gtk2 = || -> Option<String> {
-        Some(
-            serworse::parse_xsv::<String>(
-                &std::fs::read_to_string(constants::HOME_DIR.clone() + "/.gtkrc-2.0").ok()?,
-                '=',
-            )
-            .ok()?
-            .get("gtk-icon-theme-name")
-            .expect("asdf")
-            .to_string(),
-        )
-    };
+pub struct LinuxAppSearcher {}
 
-    let theme = get_gtk3()
-        .or_else(get_gtk2)
-        .unwrap_or("Adwaita".to_string());
-    log::warn!("Icon theme picked was: {theme}");
-    theme
-});
+/// Size in logical pixels to look up app icons at
+const APP_ICON_SIZE: u32 = crate::widglets::list::ICON_SIZE;
 
 impl super::OSAppSearcher for LinuxAppSearcher {
     fn get_apps(&self) -> Vec<App> {
@@ -65,38 +31,22 @@ impl super::OSAppSearcher for LinuxAppSearcher {
     }
 
     fn load_icon_path(&self, s: String) -> Option<PathBuf> {
-        if s.starts_with('/') {
-            return Some(PathBuf::from(s));
-        }
-
-        self.icon_searcher
-            .find_icon(s.as_str(), 64, 1, &ICON_THEME_NAME)
-            .map(|i| i.path().to_path_buf())
+        crate::icons::find_icon(&s, APP_ICON_SIZE)
     }
 
     fn load_icon_image(&self, path: &Path) -> Option<iced::widget::image::Handle> {
-        if path.extension() == Some(std::ffi::OsStr::new("svg")) {
-            match crate::widglets::svg_path_to_handle(path.to_path_buf()) {
-                Ok(image) => Some(image),
-                Err(e) => {
-                    log::warn!("Failed to load SVG at {path:?} with error: {e}");
-                    None
-                }
-            }
-        } else {
-            Some(iced::widget::image::Handle::from_path(path))
-        }
+        crate::icons::load_image(path, APP_ICON_SIZE)
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Action {
     pub name: String,
     pub icon_path: Option<String>,
     pub exec: Option<String>,
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum EntryType {
     Application,
     Link,
@@ -104,8 +54,10 @@ pub enum EntryType {
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DesktopEntry {
+    /// Desktop file ID, e.g `org.gnome.Nautilus.desktop`
+    pub id: String,
     pub entry_type: EntryType,
     pub version: Option<String>,
     pub name: String,
@@ -118,6 +70,8 @@ pub struct DesktopEntry {
     pub exec: String, // Techicially optional, nuh uh.
     pub working_dir: Option<String>,
     pub terminal: bool,
+    pub startup_notify: bool,
+    pub startup_wm_class: Option<String>,
     pub action_list: Vec<Action>,
     pub categories: Vec<String>,
     pub keywords: Vec<String>,
@@ -127,6 +81,7 @@ pub struct DesktopEntry {
 impl std::default::Default for DesktopEntry {
     fn default() -> DesktopEntry {
         DesktopEntry {
+            id: String::new(),
             entry_type: EntryType::Application,
             version: None,
             name: String::new(),
@@ -139,6 +94,8 @@ impl std::default::Default for DesktopEntry {
             exec: String::new(),
             working_dir: None,
             terminal: false,
+            startup_notify: false,
+            startup_wm_class: None,
             action_list: Vec::new(),
             categories: Vec::new(),
             keywords: Vec::new(),
@@ -156,67 +113,106 @@ pub enum ParseError {
     UnknownApplicationType,
     NoDisplayTrue,
     ActionMissingName,
-    MissingDataDirsEviromentVariables,
     #[allow(dead_code)]
     SerWorseError(serworse::ParseError),
 }
 
-fn get_data_dirs() -> Result<Vec<String>, ParseError> {
-    let splitter = |s: Option<String>| -> Vec<String> {
-        s.unwrap_or_default()
-            .split(":")
-            .map(|s| s.to_owned())
-            .collect()
-    };
+/// In order of priority, so the users own entries come first
+pub(super) fn get_applications_dirs() -> Vec<String> {
+    util::data_dirs()
+        .into_iter()
+        .map(|dir| dir + "/applications/")
+        .collect()
+}
 
-    let mut data_dirs = splitter(std::env::var("XDG_DATA_DIRS").ok());
-    data_dirs.extend(splitter(std::env::var("XDG_DATA_HOME").ok()));
+/// Loads all desktop entries, using the on disk cache for directories that haven't changed
+pub fn load_desktop_entries() -> Result<Vec<DesktopEntry>, ParseError> {
+    let mut cache = IndexCache::load();
+    let (entries, changed) = cache.refresh(&get_applications_dirs(), parse_applications_dir);
 
-    if data_dirs.is_empty() {
-        return Err(ParseError::MissingDataDirsEviromentVariables);
+    if changed {
+        log::info!("App index changed. Writing new cache");
+        cache.save();
     }
 
-    Ok(data_dirs)
+    Ok(entries)
 }
 
-pub fn load_desktop_entries() -> Result<Vec<DesktopEntry>, ParseError> {
-    let mut entries = Vec::new();
+/// Loads all desktop entries from disk, ignoring the cache
+#[allow(dead_code)] // used in benchmarking
+pub fn load_desktop_entries_uncached() -> Result<Vec<DesktopEntry>, ParseError> {
+    Ok(get_applications_dirs()
+        .iter()
+        .flat_map(|dir| parse_applications_dir(dir))
+        .collect())
+}
 
-    let mut dir_count = 0;
+fn parse_applications_dir(applications_dir: &str) -> Vec<DesktopEntry> {
+    let mut entries = Vec::new();
+    let mut file_count = 0;
 
-    for dir in get_data_dirs()? {
-        dir_count += 1;
+    for entry in WalkDir::new(applications_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        file_count += 1;
 
-        let mut file_count = 0;
+        log::trace!("{}", entry.path().display());
 
-        for entry in WalkDir::new(dir.to_owned() + "/applications/")
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            file_count += 1;
+        entries.push(
+            parse_desktop_file(Path::new(applications_dir), entry.path()).map_err(|e| {
+                log::trace!("error parsing file {:#?} with error: {:?}", entry.path(), e)
+            }),
+        );
+    }
 
-            log::trace!("{}", entry.path().display());
+    log::debug!("file_count for dir: {applications_dir}, {file_count}");
 
-            entries.push(parse_from_file(entry.path()).map_err(|e| {
-                log::trace!("error parsing file {:#?} with error: {:?}", entry.path(), e)
-            }));
-        }
+    entries.into_iter().filter_map(|a| a.ok()).collect()
+}
 
-        log::debug!("file_count for dir: {dir}, {file_count}");
-    }
+/// Parses the file at `file_path`, which is inside `applications_dir`
+pub(super) fn parse_desktop_file(
+    applications_dir: &Path,
+    file_path: &Path,
+) -> Result<DesktopEntry, ParseError> {
+    let mut desktop_entry = parse_from_file(file_path)?;
+    desktop_entry.id = desktop_file_id(applications_dir, file_path);
+    Ok(desktop_entry)
+}
 
-    log::debug!("{dir_count:#?}");
+/// The path relative to the `applications` dir, with `/` replaced by `-`.
+/// e.g `applications/foo/bar.desktop` has the id `foo-bar.desktop`
+pub(super) fn desktop_file_id(applications_dir: &Path, file_path: &Path) -> String {
+    file_path
+        .strip_prefix(applications_dir)
+        .unwrap_or(file_path)
+        .to_string_lossy()
+        .replace('/', "-")
+}
 
-    Ok(entries.into_iter().filter_map(|a| a.ok()).collect())
+#[test]
+fn can_make_desktop_file_id() {
+    assert_eq!(
+        desktop_file_id(
+            Path::new("/usr/share/applications/"),
+            Path::new("/usr/share/applications/foo/bar.desktop")
+        ),
+        "foo-bar.desktop"
+    );
 }
 
 fn parse_from_file(file_path: &std::path::Path) -> Result<DesktopEntry, ParseError> {
     let contents = std::fs::read_to_string(file_path).map_err(|_| ParseError::CouldNotLoadFile)?;
 
-    parse_from_hashmap(serworse::parse_ini_format(&contents).map_err(ParseError::SerWorseError)?)
+    let (map, errors) = serworse::parse_ini_with_errors(&contents);
+    for e in errors {
+        log::warn!("{}:{e}", file_path.display());
+    }
+    parse_from_hashmap(map)
 }
 
-fn parse_from_hashmap<'a>(
+pub(super) fn parse_from_hashmap<'a>(
     input: HashMap<&'a str, HashMap<&'a str, &'a str>>,
 ) -> Result<DesktopEntry, ParseError> {
     let Some(entry_keys) = input.get("Desktop Entry") else {
@@ -237,32 +233,35 @@ fn parse_from_hashmap<'a>(
         None => return Err(ParseError::MissingRequiredField),
     };
 
+    let get = |key: &str| entry_keys.get(key).map(|s| unescape_value(s));
+
     let entry = DesktopEntry {
+        // Filled in by the caller, since it depends on where the file is
+        id: String::new(),
         entry_type,
-        version: entry_keys.get("Version").map(|s| s.to_string()),
-        name: entry_keys // TODO. handle different languages
-            .get("Name")
-            .ok_or(ParseError::MissingRequiredField)?
-            .to_string(),
-        try_exec: entry_keys.get("TryExec").map(|s| s.to_string()),
+        version: get("Version"),
+        name: get("Name").ok_or(ParseError::MissingRequiredField)?, // TODO. handle different languages
+        try_exec: get("TryExec"),
         exec: parse_exec_key(
-            entry_keys
-                .get("Exec")
-                .ok_or(ParseError::MissingRequiredField)?,
-            entry_keys.get("Icon").copied(),
-            entry_keys.get("Name").copied(),
+            &get("Exec").ok_or(ParseError::MissingRequiredField)?,
+            get("Icon").as_deref(),
+            get("Name").as_deref(),
         ),
-        generic_name: entry_keys.get("GenericName").map(|s| s.to_string()),
-        comment: entry_keys.get("Comment").map(|s| s.to_string()),
-        icon: { entry_keys.get("Icon").map(|s| s.to_string()) },
+        generic_name: get("GenericName"),
+        comment: get("Comment"),
+        icon: get("Icon"),
         only_show_in: parse_string_list(entry_keys.get("OnlyShowIn").copied()),
         not_show_in: parse_string_list(entry_keys.get("NotShowIn").copied()),
-        working_dir: entry_keys.get("Path").map(|s| s.to_string()),
+        working_dir: get("Path"),
         terminal: entry_keys.get("Terminal").is_some_and(|b| *b == "true"),
+        startup_notify: entry_keys
+            .get("StartupNotify")
+            .is_some_and(|b| *b == "true"),
+        startup_wm_class: get("StartupWMClass"),
         categories: parse_string_list(entry_keys.get("Categories").copied()),
         keywords: parse_string_list(entry_keys.get("Keywords").copied()),
-        url: match entry_keys.get("URL") {
-            Some(s) => Some(s.to_string()),
+        url: match get("URL") {
+            Some(s) => Some(s),
             _ if entry_type == EntryType::Link => return Err(ParseError::MissingRequiredField),
             _ => None,
         },
@@ -273,13 +272,11 @@ fn parse_from_hashmap<'a>(
                 let section = input
                     .get(formatted_name.as_str())
                     .ok_or(ParseError::BadGroupHeader)?;
+                let get = |key: &str| section.get(key).map(|s| unescape_value(s));
                 Ok::<Action, ParseError>(Action {
-                    name: section
-                        .get("Name")
-                        .ok_or(ParseError::ActionMissingName)?
-                        .to_string(),
-                    exec: section.get("Exec").map(|s| s.to_string()),
-                    icon_path: section.get("Icon").map(|s| s.to_string()),
+                    name: get("Name").ok_or(ParseError::ActionMissingName)?,
+                    exec: get("Exec"),
+                    icon_path: get("Icon"),
                 })
             })
             .filter_map(|a| {
@@ -296,39 +293,415 @@ fn parse_from_hashmap<'a>(
     Ok(entry)
 }
 
+impl DesktopEntry {
+    /// Writes the entry in the .desktop format. The opposite of `parse_from_hashmap`, so keys
+    /// are written in the same order they are read there.
+    /// Actions don't keep their group name when parsed, so new ones are made from their names.
+    pub fn to_file_contents(&self) -> String {
+        let mut out = String::from("[Desktop Entry]\n");
+        let mut push = |key: &str, value: &str| {
+            out += &format!("{key}={value}\n");
+        };
+
+        push(
+            "Type",
+            match self.entry_type {
+                EntryType::Application => "Application",
+                EntryType::Link => "Link",
+                EntryType::Directory => "Directory",
+            },
+        );
+        if let Some(version) = &self.version {
+            push("Version", &escape_value(version));
+        }
+        push("Name", &escape_value(&self.name));
+        if let Some(try_exec) = &self.try_exec {
+            push("TryExec", &escape_value(try_exec));
+        }
+        push("Exec", &escape_value(&escape_exec(&self.exec)));
+        if let Some(generic_name) = &self.generic_name {
+            push("GenericName", &escape_value(generic_name));
+        }
+        if let Some(comment) = &self.comment {
+            push("Comment", &escape_value(comment));
+        }
+        if let Some(icon) = &self.icon {
+            push("Icon", &escape_value(icon));
+        }
+        if !self.only_show_in.is_empty() {
+            push("OnlyShowIn", &to_string_list(&self.only_show_in));
+        }
+        if !self.not_show_in.is_empty() {
+            push("NotShowIn", &to_string_list(&self.not_show_in));
+        }
+        if let Some(working_dir) = &self.working_dir {
+            push("Path", &escape_value(working_dir));
+        }
+        push("Terminal", if self.terminal { "true" } else { "false" });
+        if self.startup_notify {
+            push("StartupNotify", "true");
+        }
+        if let Some(wm_class) = &self.startup_wm_class {
+            push("StartupWMClass", &escape_value(wm_class));
+        }
+        if !self.categories.is_empty() {
+            push("Categories", &to_string_list(&self.categories));
+        }
+        if !self.keywords.is_empty() {
+            push("Keywords", &to_string_list(&self.keywords));
+        }
+        if let Some(url) = &self.url {
+            push("URL", &escape_value(url));
+        }
+
+        let action_ids = action_ids(&self.action_list);
+        if !action_ids.is_empty() {
+            push("Actions", &to_string_list(&action_ids));
+        }
+
+        for (id, action) in action_ids.iter().zip(&self.action_list) {
+            out += &format!("\n[Desktop Action {id}]\n");
+            out += &format!("Name={}\n", escape_value(&action.name));
+            if let Some(exec) = &action.exec {
+                out += &format!("Exec={}\n", escape_value(exec));
+            }
+            if let Some(icon) = &action.icon_path {
+                out += &format!("Icon={}\n", escape_value(icon));
+            }
+        }
+
+        out
+    }
+}
+
+/// Group names for actions, made from their names. Only letters, numbers and `-` are allowed.
+fn action_ids(actions: &[Action]) -> Vec<String> {
+    let mut ids: Vec<String> = Vec::new();
+    for action in actions {
+        let base: String = action
+            .name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect();
+        let base = if base.is_empty() {
+            "action".to_string()
+        } else {
+            base
+        };
+
+        let mut id = base.clone();
+        let mut n = 2;
+        while ids.contains(&id) {
+            id = format!("{base}{n}");
+            n += 1;
+        }
+        ids.push(id);
+    }
+    ids
+}
+
+/// The opposite of `unescape_value`. Newlines would end the value early, and a leading space
+/// would be trimmed, so they are written as escapes too
+fn escape_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    match escaped.strip_prefix(' ') {
+        Some(rest) => format!("\\s{rest}"),
+        None => escaped,
+    }
+}
+
+/// Replaces the escapes the spec allows in string values. Anything else is left as it is, as
+/// lists and the Exec key have their own escapes
+pub(super) fn unescape_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// The opposite of `parse_exec_key`. Escapes inside quotes are already there
+fn escape_exec(exec: &str) -> String {
+    let mut out = String::with_capacity(exec.len());
+    let mut quoted = false;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => out += "%%",
+            '"' => {
+                quoted = !quoted;
+                out.push(c);
+            }
+            '\\' if quoted => {
+                out.push(c);
+                out.extend(chars.next());
+            }
+            '\\' => out += "\\\\",
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Characters that must have a backslash before them inside quotes
+const QUOTED_ESCAPES: [char; 4] = ['"', '`', '$', '\\'];
+
+/// Puts `arg` in double quotes if it has characters the spec reserves, like spaces.
+/// `split_exec` takes them off again
+pub fn quote_exec_arg(arg: &str) -> String {
+    const RESERVED: &str = " \t\n\"'\\><~|&;$*?#()`";
+    if !arg.contains(|c| RESERVED.contains(c)) {
+        return arg.to_string();
+    }
+    let mut out = String::from('"');
+    for c in arg.chars() {
+        if QUOTED_ESCAPES.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+/// Splits a parsed Exec key into the program and its arguments.
+/// Quoted arguments can have spaces in them, like `"/opt/My App.AppImage" --flag`
+fn split_exec(exec: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            '\\' if quoted => current.extend(chars.next()),
+            ' ' | '\t' | '\n' if !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            _ => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+/// The opposite of `parse_string_list`
+fn to_string_list(list: &[String]) -> String {
+    list.iter()
+        .map(|item| escape_value(item).replace(';', "\\;") + ";")
+        .collect()
+}
+
+/// `$XDG_DATA_HOME/applications`, where the users own entries go
+pub fn user_applications_dir() -> PathBuf {
+    PathBuf::from(util::data_home()).join("applications")
+}
+
+/// Writes `entry` as a new file in the users applications dir, named after the entry.
+/// Never overwrites an existing file.
+pub fn write_user_entry(entry: &DesktopEntry) -> std::io::Result<PathBuf> {
+    let dir = user_applications_dir();
+    std::fs::create_dir_all(&dir)?;
+
+    let stem: String = entry
+        .name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let stem = stem.trim_matches('-');
+    let stem = if stem.is_empty() { "launcher" } else { stem };
+
+    let mut path = dir.join(format!("{stem}.desktop"));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{stem}-{n}.desktop"));
+        n += 1;
+    }
+
+    std::fs::write(&path, entry.to_file_contents())?;
+    log::info!("Wrote desktop entry to {path:?}");
+    Ok(path)
+}
+
+/// Copies the entry with the desktop file ID `id` into the users applications dir, so it can be
+/// edited and override the system one. Returns the path of the users copy.
+pub fn copy_to_user_dir(id: &str) -> std::io::Result<PathBuf> {
+    let user_dir = user_applications_dir();
+    let target = user_dir.join(id);
+    if target.exists() {
+        return Ok(target);
+    }
+
+    let source = get_applications_dirs()
+        .iter()
+        .find_map(|dir| {
+            let dir = Path::new(dir);
+            WalkDir::new(dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .find(|e| desktop_file_id(dir, e.path()) == id)
+                .map(|e| e.path().to_path_buf())
+        })
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No desktop file with the ID {id}"),
+            )
+        })?;
+
+    std::fs::create_dir_all(&user_dir)?;
+    std::fs::copy(&source, &target)?;
+    log::info!("Copied {source:?} to {target:?} for editing");
+    Ok(target)
+}
+
+#[test]
+fn desktop_entry_round_trips() {
+    let entry = DesktopEntry {
+        entry_type: EntryType::Application,
+        version: Some("1.5".to_string()),
+        name: "My Script".to_string(),
+        comment: Some("Runs\nthings".to_string()),
+        generic_name: Some(r" Back\slash\tand tab".to_string()),
+        icon: Some("/home/me/icon.png".to_string()),
+        only_show_in: vec!["GNOME".to_string(), "KDE".to_string()],
+        try_exec: Some("/home/me/script.sh".to_string()),
+        exec: r"/home/me/script.sh --percent 50% --path C:\dir".to_string(),
+        working_dir: Some("/home/me".to_string()),
+        terminal: true,
+        startup_notify: true,
+        startup_wm_class: Some("Script".to_string()),
+        categories: vec!["Utility".to_string(), "Semi;colon".to_string()],
+        keywords: vec!["script".to_string()],
+        action_list: vec![
+            Action {
+                name: "Run fast!".to_string(),
+                icon_path: None,
+                exec: Some("/home/me/script.sh --fast".to_string()),
+            },
+            Action {
+                name: "Run fast".to_string(),
+                icon_path: Some("fast".to_string()),
+                exec: None,
+            },
+        ],
+        ..Default::default()
+    };
+
+    let contents = entry.to_file_contents();
+    assert!(contents.contains("Actions=Runfast;Runfast2;\n"));
+    assert!(contents.contains("Categories=Utility;Semi\\;colon;\n"));
+
+    assert!(contents.contains("Comment=Runs\\nthings\n"));
+
+    let parsed = parse_from_hashmap(serworse::parse_ini_format(&contents)).unwrap();
+    assert_eq!(parsed, entry);
+
+    let entry = DesktopEntry {
+        exec: format!("{} --fast", quote_exec_arg("/home/me/my 100% script.sh")),
+        ..Default::default()
+    };
+    let contents = entry.to_file_contents();
+    assert!(contents.contains("Exec=\"/home/me/my 100%% script.sh\" --fast\n"));
+    let parsed = parse_from_hashmap(serworse::parse_ini_format(&contents)).unwrap();
+    let app = App::from(parsed);
+    assert_eq!(app.cmd, "/home/me/my 100% script.sh");
+    assert_eq!(app.args, ["--fast"]);
+
+    // Quotes, backslashes and dollars inside quotes are escaped
+    let program = r#"/opt/say "hi" to $USER `now` C:\dir.AppImage"#;
+    let entry = DesktopEntry {
+        exec: format!(
+            "{} --path {}",
+            quote_exec_arg(program),
+            quote_exec_arg("a b")
+        ),
+        ..Default::default()
+    };
+    let contents = entry.to_file_contents();
+    assert!(contents.contains(
+        r#"Exec="/opt/say \\"hi\\" to \\$USER \\`now\\` C:\\\\dir.AppImage" --path "a b""#
+    ));
+    let parsed = parse_from_hashmap(serworse::parse_ini_format(&contents)).unwrap();
+    assert_eq!(parsed.exec, entry.exec);
+    assert_eq!(split_exec(&parsed.exec), [program, "--path", "a b"]);
+}
+
+#[test]
+fn minimal_link_round_trips() {
+    let entry = DesktopEntry {
+        entry_type: EntryType::Link,
+        name: "Docs".to_string(),
+        exec: "xdg-open".to_string(),
+        url: Some("https://example.com/a;b".to_string()),
+        ..Default::default()
+    };
+    let parsed = parse_from_hashmap(serworse::parse_ini_format(&entry.to_file_contents())).unwrap();
+    assert_eq!(parsed, entry);
+}
+
 impl From<DesktopEntry> for App {
     fn from(desktop_entry: DesktopEntry) -> Self {
         // https://docs.iced.rs/iced/advanced/image/index.html
         log::trace!("{}", desktop_entry.exec.replace(' ', "*"));
-        let (cmd, args) = match desktop_entry.exec.split_once(' ') {
-            Some((cmd, args)) => {
-                let mut arg: Vec<String> = args
-                    .split(" ")
-                    .map(|s| s.to_string())
-                    .filter(|x| !x.is_empty())
-                    .collect();
-
-                log::trace!("arg is: {:#?}", arg);
-
-                if arg == vec![String::new()] {
-                    log::trace!("ARGS LEN 0");
-                    arg.clear();
-                }
-
-                (cmd.to_string(), arg)
-            }
-            None => (desktop_entry.exec, vec![String::new()]),
-        };
+        let mut words = split_exec(&desktop_entry.exec).into_iter();
+        let cmd = words.next().unwrap_or_default();
+        let args: Vec<String> = words.collect();
+        log::trace!("arg is: {:#?}", args);
 
         let working_dir = desktop_entry.working_dir;
 
+        let startup = desktop_entry.startup_notify.then(|| launch::StartupInfo {
+            name: desktop_entry.name.clone(),
+            bin: cmd.clone(),
+            icon: desktop_entry.icon.clone(),
+            wm_class: desktop_entry.startup_wm_class,
+        });
+
         App {
+            id: Some(desktop_entry.id),
             name: desktop_entry.name,
             cmd,
             args,
             working_dir,
             subname: desktop_entry.generic_name,
             icon: desktop_entry.icon.map(Icon::NotFoundYet),
+            terminal: desktop_entry.terminal,
+            startup,
         }
     }
 }
@@ -342,6 +715,7 @@ fn can_parse_app_from_desktop_entry() {
         ..Default::default()
     };
     let app = App {
+        id: Some(String::new()),
         name: "anki".to_string(),
         cmd: "/usr/bin/flatpak".to_string(),
         args: ["run", "--branch=stable", "net.ankiweb.Anki", "@@", "@@"]
@@ -351,34 +725,40 @@ fn can_parse_app_from_desktop_entry() {
         working_dir: Some("/".to_string()),
         subname: None,
         icon: None,
+        terminal: false,
+        startup: None,
     };
 
     assert_eq!(app, App::from(entry));
 }
 
-fn parse_string_list(input: Option<&str>) -> Vec<String> {
+pub(super) fn parse_string_list(input: Option<&str>) -> Vec<String> {
     // input is like blah;thing2;thing3
     let mut result = Vec::new();
     let mut current = String::new();
-    let chars = input.unwrap_or("").chars().peekable();
+    let mut chars = input.unwrap_or("").chars();
 
-    for c in chars {
+    while let Some(c) = chars.next() {
         match c {
-            ';' => {
-                if current.ends_with("\\") {
-                    current.pop();
+            // Other escapes are left for `unescape_value`
+            '\\' => match chars.next() {
+                Some(';') => current.push(';'),
+                Some(n) => {
                     current.push(c);
-                } else {
-                    result.push(current.clone());
-                    current.clear();
+                    current.push(n);
                 }
+                None => current.push(c),
+            },
+            ';' => {
+                result.push(unescape_value(&current));
+                current.clear();
             }
             _ => current.push(c),
         }
     }
 
     if !current.is_empty() {
-        result.push(current);
+        result.push(unescape_value(&current));
     }
     result
 }
@@ -388,13 +768,54 @@ fn can_parse_string_list() {
     let input = Some("t1;t2;t\\;3;");
     let output = parse_string_list(input);
     println!("{output:#?}");
-    debug_assert!(output == vec!["t1".to_string(), "t2".to_string(), "t;3".to_string()])
+    debug_assert!(output == vec!["t1".to_string(), "t2".to_string(), "t;3".to_string()]);
+
+    let output = parse_string_list(Some(r"back\\;new\nline;"));
+    assert_eq!(output, ["back\\", "new\nline"]);
+}
+
+/// Something wrong with an Exec key. `parse_exec_key` skips over these
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecProblem {
+    TrailingBackslash,
+    UnknownEscape(char),
+    TrailingPercent,
+    UnknownFieldCode(char),
+    /// Field codes the spec says to remove, but are still allowed
+    DeprecatedFieldCode(char),
+}
+
+impl std::fmt::Display for ExecProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TrailingBackslash => write!(f, "No character after backslash"),
+            Self::UnknownEscape(c) => write!(f, "Unknown escape sequence \\{c}"),
+            Self::TrailingPercent => write!(f, "No character after percentage"),
+            Self::UnknownFieldCode(c) => write!(f, "Unknown field code %{c}"),
+            Self::DeprecatedFieldCode(c) => write!(f, "Deprecated field code %{c}"),
+        }
+    }
 }
 
 fn parse_exec_key(input: &str, icon: Option<&str>, name: Option<&str>) -> String {
+    let (result, problems) = check_exec_key(input, icon, name);
+    for problem in problems {
+        log::info!("{problem} in Exec key {input}. Ignoring.");
+    }
+    result
+}
+
+/// `parse_exec_key`, but also returns everything it had to skip over
+pub fn check_exec_key(
+    input: &str,
+    icon: Option<&str>,
+    name: Option<&str>,
+) -> (String, Vec<ExecProblem>) {
     // https://specifications.freedesktop.org/desktop-entry-spec/latest/exec-variables.html
     let mut escaped_result = String::new();
+    let mut problems = Vec::new();
     let mut chars = input.chars().peekable();
+    let mut quoted = false;
 
     // Lots of nesting here..
     while let Some(c) = chars.next() {
@@ -402,22 +823,27 @@ fn parse_exec_key(input: &str, icon: Option<&str>, name: Option<&str>) -> String
             // literal \
             '\\' => {
                 let Some(n) = chars.next() else {
-                    log::warn!("No character after backslash in escape sequence {}", input);
+                    problems.push(ExecProblem::TrailingBackslash);
                     continue;
                 };
 
                 match n {
                     // Quoting must be done by enclosing the argument between double quotes
                     // and escaping the double quote character , ("`"), ("$"), ("\") by preceding it with an additional backslash character
+                    // These stay escaped inside quotes, so `split_exec` can tell them apart
+                    n if quoted && QUOTED_ESCAPES.contains(&n) => {
+                        escaped_result.push(c);
+                        escaped_result.push(n);
+                    }
                     '\\' | '`' | '"' | '$' | '%' => escaped_result.push(n),
 
-                    s => log::info!("unknown escape sequence \\{}. Ignoring.", s),
+                    s => problems.push(ExecProblem::UnknownEscape(s)),
                 }
             }
             // a literal % is escaped as %%
             '%' => {
                 let Some(n) = chars.next() else {
-                    log::warn!("No character after percentage in escape sequence {}", input);
+                    problems.push(ExecProblem::TrailingPercent);
                     continue;
                 };
                 match n {
@@ -438,14 +864,23 @@ fn parse_exec_key(input: &str, icon: Option<&str>, name: Option<&str>) -> String
                             escaped_result += name;
                         }
                     }
-                    _ => {} // do nothing
+                    // Files and URLs. Nothing is passed in, so they are removed
+                    'f' | 'F' | 'u' | 'U' | 'k' => {}
+                    'd' | 'D' | 'n' | 'N' | 'v' | 'm' => {
+                        problems.push(ExecProblem::DeprecatedFieldCode(n))
+                    }
+                    s => problems.push(ExecProblem::UnknownFieldCode(s)),
                 }
             }
+            '"' => {
+                quoted = !quoted;
+                escaped_result.push(c);
+            }
             _ => escaped_result.push(c),
         }
     }
 
-    escaped_result
+    (escaped_result, problems)
 }
 
 #[test]
@@ -484,7 +919,7 @@ Name=New Terminal
 Exec=testaction
     "#;
 
-    let entry = parse_from_hashmap(serworse::parse_ini_format(test).unwrap()).unwrap();
+    let entry = parse_from_hashmap(serworse::parse_ini_format(test)).unwrap();
 
     assert_eq!(entry.name, "Test Name");
     assert_eq!(entry.entry_type, EntryType::Application);
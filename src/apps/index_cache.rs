@@ -0,0 +1,223 @@
+// Parsing every .desktop file is most of the startup time, so the parsed entries are kept in
+// `CACHE_DIR` along with the newest mtime of the directory they came from and its files.
+// Only directories which have changed since the cache was written get parsed again.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use super::desktop_entry::DesktopEntry;
+use crate::{constants, store};
+
+const APP_INDEX_RELPATH: &str = "app_index.json";
+
+/// Bump this whenever `DesktopEntry` changes so old caches are thrown away
+const CACHE_VERSION: u32 = 2;
+
+static APP_INDEX_FILE_PATH: LazyLock<String> =
+    LazyLock::new(|| constants::CACHE_DIR.to_owned() + APP_INDEX_RELPATH);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexCache {
+    version: u32,
+    /// Keyed by the path of the `applications` dir
+    dirs: HashMap<String, CachedDir>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedDir {
+    /// Nanoseconds since the unix epoch
+    mtime: u64,
+    entries: Vec<DesktopEntry>,
+}
+
+impl Default for IndexCache {
+    fn default() -> Self {
+        IndexCache {
+            version: CACHE_VERSION,
+            dirs: HashMap::new(),
+        }
+    }
+}
+
+impl IndexCache {
+    pub fn load() -> Self {
+        let Ok(data) = std::fs::read(APP_INDEX_FILE_PATH.as_str()) else {
+            log::info!("No app index cache found at {APP_INDEX_FILE_PATH:?}");
+            return Self::default();
+        };
+
+        match serde_json::from_slice::<IndexCache>(&data) {
+            Ok(cache) if cache.version == CACHE_VERSION => cache,
+            Ok(cache) => {
+                log::info!(
+                    "App index cache is version {}, expected {CACHE_VERSION}. Ignoring it",
+                    cache.version
+                );
+                Self::default()
+            }
+            Err(e) => {
+                log::warn!("Could not parse app index cache. Ignoring it. E: {e}");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string(self) {
+            Ok(data) => {
+                if let Err(e) = store::write_atomic(Path::new(APP_INDEX_FILE_PATH.as_str()), &data)
+                {
+                    log::error!("Could not write app index cache to file!! e: {e}");
+                }
+            }
+            Err(e) => log::error!("Could not serialise app index cache. e: {e}"),
+        }
+    }
+
+    /// Returns the entries for every dir, parsing only the ones that have changed.
+    /// Only the first entry with each desktop file ID is kept, so entries in earlier dirs
+    /// override later ones.
+    /// The second value is true if anything was parsed, and the cache should be saved.
+    pub fn refresh<F>(
+        &mut self,
+        applications_dirs: &[String],
+        parse_dir: F,
+    ) -> (Vec<DesktopEntry>, bool)
+    where
+        F: Fn(&str) -> Vec<DesktopEntry>,
+    {
+        let mut changed = false;
+        let mut new_dirs = HashMap::new();
+
+        for dir in applications_dirs {
+            let Some(mtime) = dir_mtime(Path::new(dir)) else {
+                continue;
+            };
+
+            let cached = match self.dirs.remove(dir) {
+                Some(cached) if cached.mtime == mtime => {
+                    log::debug!("App index cache hit for {dir}");
+                    cached
+                }
+                _ => {
+                    log::debug!("App index cache miss for {dir}");
+                    changed = true;
+                    CachedDir {
+                        mtime,
+                        entries: parse_dir(dir),
+                    }
+                }
+            };
+            new_dirs.insert(dir.clone(), cached);
+        }
+
+        // Dirs that are no longer in $XDG_DATA_DIRS
+        changed |= !self.dirs.is_empty();
+        self.dirs = new_dirs;
+
+        let mut ids = HashSet::new();
+        let entries = applications_dirs
+            .iter()
+            .filter_map(|dir| self.dirs.get(dir))
+            .flat_map(|cached| cached.entries.iter())
+            .filter(|entry| ids.insert(entry.id.as_str()))
+            .cloned()
+            .collect();
+
+        (entries, changed)
+    }
+}
+
+/// The newest mtime of `dir` and everything inside of it.
+/// A directories mtime changes when a file is added, removed or renamed inside it, which is
+/// how package managers install .desktop files. Files edited in place only change their own.
+fn dir_mtime(dir: &Path) -> Option<u64> {
+    WalkDir::new(dir)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok()?.modified().ok())
+        .filter_map(|time| time.duration_since(UNIX_EPOCH).ok())
+        .filter_map(|d| u64::try_from(d.as_nanos()).ok())
+        .max()
+}
+
+#[test]
+fn only_changed_dirs_are_parsed() {
+    use std::cell::Cell;
+
+    let root = std::env::temp_dir().join(format!("lumin_index_test_{}", std::process::id()));
+    let first = root.join("first");
+    let second = root.join("second");
+    std::fs::create_dir_all(&first).unwrap();
+    std::fs::create_dir_all(&second).unwrap();
+    let dirs = vec![
+        first.to_string_lossy().to_string(),
+        second.to_string_lossy().to_string(),
+        root.join("missing").to_string_lossy().to_string(),
+    ];
+
+    let parse_count = Cell::new(0);
+    let parse_dir = |dir: &str| {
+        parse_count.set(parse_count.get() + 1);
+        vec![
+            DesktopEntry {
+                id: dir.to_string(),
+                name: dir.to_string(),
+                ..Default::default()
+            },
+            DesktopEntry {
+                id: "shared.desktop".to_string(),
+                name: dir.to_string(),
+                ..Default::default()
+            },
+        ]
+    };
+
+    let mut cache = IndexCache::default();
+    let (entries, changed) = cache.refresh(&dirs, parse_dir);
+    assert!(changed);
+    assert_eq!(parse_count.get(), 2);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].name, dirs[0]);
+    // The same ID in a later dir is overridden by the first one
+    let shared: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry.id == "shared.desktop")
+        .collect();
+    assert_eq!(shared.len(), 1);
+    assert_eq!(shared[0].name, dirs[0]);
+
+    let (entries, changed) = cache.refresh(&dirs, parse_dir);
+    assert!(!changed);
+    assert_eq!(parse_count.get(), 2);
+    assert_eq!(entries.len(), 3);
+
+    // Pretend the second dir was modified since the cache was written
+    cache.dirs.get_mut(&dirs[1]).unwrap().mtime = 0;
+    let (entries, changed) = cache.refresh(&dirs, parse_dir);
+    assert!(changed);
+    assert_eq!(parse_count.get(), 3);
+    assert_eq!(entries[2].name, dirs[1]);
+
+    // Editing a file in place doesn't change the mtime of its dir
+    let file = std::fs::File::create(second.join("app.desktop")).unwrap();
+    cache.refresh(&dirs, parse_dir);
+    let count = parse_count.get();
+    file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+        .unwrap();
+    let (_, changed) = cache.refresh(&dirs, parse_dir);
+    assert!(changed);
+    assert_eq!(parse_count.get(), count + 1);
+
+    let round_tripped: IndexCache =
+        serde_json::from_slice(&serde_json::to_vec(&cache).unwrap()).unwrap();
+    assert_eq!(round_tripped.dirs.len(), 2);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
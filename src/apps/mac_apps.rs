@@ -18,12 +18,15 @@ impl OSAppSearcher for MacOsAppSearcher {
         m_apps
             .iter()
             .map(|a| App {
+                id: None,
                 name: a.name.clone(),
                 icon: Some(Icon::NotFoundYet(a.path.clone())),
                 cmd: "open".into(),
                 args: vec![a.path.clone()],
                 working_dir: None,
                 subname: None,
+                terminal: false,
+                startup: None,
             })
             .collect()
     }
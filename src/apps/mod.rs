@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Mul;
 use std::path::Path;
 use std::path::PathBuf;
@@ -9,24 +9,36 @@ use iced::Task;
 use iced::widget;
 
 pub mod desktop_entry;
+mod index_cache;
 pub mod mac_apps;
+pub mod validate;
+mod visibility;
+#[cfg(target_os = "linux")]
+pub mod watcher;
 
 use crate::config;
 use crate::constants;
+use crate::launch;
 use crate::module::{Module, ModuleMessage};
-use crate::serworse;
 use crate::sorting;
+use crate::store::Store;
 use crate::util;
 use crate::widglets;
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct App {
+    /// Desktop file ID, if the app came from a `.desktop` file
+    id: Option<String>,
     cmd: String,
     args: Vec<String>,
     working_dir: Option<String>,
     name: String,
     subname: Option<String>,
     icon: Option<Icon>,
+    /// Should the app be ran inside of a terminal emulator
+    terminal: bool,
+    /// Set if the app supports startup notification
+    startup: Option<launch::StartupInfo>,
 }
 
 pub trait OSAppSearcher: Sync + Send {
@@ -47,6 +59,24 @@ pub enum Icon {
 pub enum AppMessage {
     /// Fetched an icon from disk. Path is given so it can be used in the `ICON_CACHE`
     IconLoaded(String, Option<(String, iced::widget::image::Handle)>),
+    /// Pin the selected app to the top of the list, or unpin it if it already is
+    TogglePinSelected,
+    /// Never show the selected app again
+    HideSelected,
+    /// Copy the selected app's .desktop file to the users applications dir and open it in an editor
+    EditSelected,
+    /// .desktop files were added, changed or removed while lumin was open
+    EntriesChanged(Vec<EntryChange>),
+    /// Icons were installed or removed, so every icon needs looking up again
+    IconThemeChanged,
+}
+
+#[derive(Clone, Debug)]
+pub enum EntryChange {
+    /// A .desktop file was added or changed
+    Updated(Box<App>),
+    /// Desktop file ID of an app that no longer exists, or is now `NoDisplay`
+    Removed(String),
 }
 
 pub struct AppModule {
@@ -57,6 +87,9 @@ pub struct AppModule {
     have_started_icon_search: bool,
     /// Highlighted index to launch
     selected_index: usize,
+    visibility: visibility::Visibility,
+    /// Last text input. Used to re-sort after pinning
+    input: String,
 }
 
 static APP_SEARCHER: LazyLock<Box<dyn OSAppSearcher>> = LazyLock::new(|| {
@@ -92,38 +125,14 @@ impl Default for AppModule {
 impl AppModule {
     // Duplicated logic betweeen new and open_app which is sad. should fix this
     pub fn new() -> Self {
-        // attempt to load hashmap from disk
-        let mut freq_map: HashMap<String, u32> = HashMap::new();
-        if let Ok(data) = std::fs::read_to_string(APP_FREQUENCY_FILE_PATH.clone()) {
-            match serworse::parse_csv::<u32>(&data) {
-                Ok(map1) => freq_map = map1,
-                Err(e) => log::error!("Could not read app_frequencies to hashmap. E: {e:#?}"),
-            }
-        } else {
-            log::warn!(
-                "Could not read app_frequencies to string.\
-                Once any app is launched for the first time, \
-                this warning should go away as the hashmap should have been written"
-            );
-        };
+        // attempt to load hashmaps from disk
+        let freq_map: HashMap<String, u32> = Store::new(APP_FREQUENCY_FILE_PATH.as_str()).load();
 
-        if let Ok(data) = std::fs::read_to_string(ICON_CACHE_FILE_PATH.clone()) {
-            match serworse::parse_csv::<String>(&data) {
-                Ok(disk_cache) => {
-                    let mut main_map = ICON_CACHE.lock().expect("can get ICON_CACHE");
-                    for (key, val) in disk_cache.iter() {
-                        main_map.insert(key.to_string(), val.to_string());
-                    }
-                }
-                Err(e) => log::error!("Could not read icon_cache to hashmap. E: {e:#?}"),
-            }
-        } else {
-            log::warn!(
-                "Could not read Icon to string.\
-                Once any app is launched for the first time, \
-                this warning should go away as the hashmap should have been written"
-            );
-        };
+        let disk_cache: HashMap<String, String> = Store::new(ICON_CACHE_FILE_PATH.as_str()).load();
+        ICON_CACHE
+            .lock()
+            .expect("can get ICON_CACHE")
+            .extend(disk_cache);
 
         // let mut icon_map: HashMap<String, widget::image::Handle> = HashMap::new();
 
@@ -132,54 +141,43 @@ impl AppModule {
             app_frequencies: freq_map,
             have_started_icon_search: false,
             selected_index: 0,
+            visibility: visibility::Visibility::load(),
+            input: String::new(),
         }
     }
 
-    fn run_app_at_index(&self, index: usize) {
+    fn run_app_at_index(&self, index: usize) -> Task<ModuleMessage> {
         let Some(first) = self.app_list.get(index) else {
-            return;
+            return iced::exit();
         };
 
         // Increment app frequency hashmap
-        let mut map = self.app_frequencies.clone();
-        if let Some(old_val) = map.get(&first.name) {
-            map.insert(first.name.clone(), *old_val + 1);
-        } else {
-            map.insert(first.name.clone(), 1);
+        let res = Store::new(APP_FREQUENCY_FILE_PATH.as_str()).update(|map| {
+            *map.entry(first.name.clone()).or_insert(0u32) += 1;
+        });
+        if let Err(e) = res {
+            log::error!("Could not write new app frequency hashmap to file!! e: {e}");
         }
 
-        log::debug!("New app_frequencies hashmap is {map:#?}");
-
-        if let Err(e) = std::fs::write(
-            APP_FREQUENCY_FILE_PATH.clone(),
-            serworse::hash_map_to_csv(map),
-        ) {
-            log::error!(
-                "Could not write new app frequency hashmap to file!! e: {e}\nHashmap is: {e:#?}"
-            );
-        } else {
-            log::debug!("Successfully wrote to path: {APP_FREQUENCY_FILE_PATH:?}");
-        };
-
-        // Write icon_cache to disk
-
+        // Write icon_cache to disk. It started as the disk copy, and stale entries may have been
+        // removed since, so it replaces what is there
         let cache_map = ICON_CACHE.lock().expect("not poisoned").clone();
+        if let Err(e) = Store::new(ICON_CACHE_FILE_PATH.as_str()).update(|map| *map = cache_map) {
+            log::error!("Could not write icon_cache to file!! e: {e}");
+        }
 
-        if let Err(e) = std::fs::write(
-            ICON_CACHE_FILE_PATH.clone(),
-            serworse::hash_map_to_csv(cache_map),
-        ) {
-            log::error!("Could not write icon_cache to file!! e: {e}\nHashmap is: {e:#?}");
+        let (cmd, args) = if first.terminal {
+            util::wrap_in_terminal(first.cmd.clone(), first.args.clone())
         } else {
-            log::debug!("Successfully wrote to path: {ICON_CACHE_FILE_PATH:?}");
+            (first.cmd.clone(), first.args.clone())
         };
 
-        util::execute_command_detached(
-            first.cmd.clone(),
-            first.args.clone(),
-            first.working_dir.clone(),
-        )
-        .expect("Can execute_command_detached");
+        launch::Launch::new(cmd)
+            .args(args)
+            .working_dir(first.working_dir.clone())
+            .app_id(first.id.clone())
+            .startup(first.startup.clone())
+            .spawn()
     }
 
     fn handle_text_change(&mut self, input: String) -> Task<ModuleMessage> {
@@ -189,6 +187,26 @@ impl AppModule {
             log::trace!("Generating app_list");
             let start = std::time::Instant::now();
             self.app_list = APP_SEARCHER.get_apps();
+            let mut custom_names = HashSet::new();
+            self.app_list.extend(
+                config::SETTINGS
+                    .lock()
+                    .expect("mutex")
+                    .custom_entries
+                    .iter()
+                    .filter(|entry| {
+                        let first = custom_names.insert(entry.name.clone());
+                        if !first {
+                            log::warn!(
+                                "Custom entry {} is in the config twice. Skipping",
+                                entry.name
+                            );
+                        }
+                        first
+                    })
+                    .filter_map(|entry| App::try_from(entry.clone()).ok()),
+            );
+            self.app_list.retain(|app| !self.visibility.is_hidden(app));
             log::info!(
                 "Time to get #{} apps: {:#?}",
                 self.app_list.len(),
@@ -196,19 +214,37 @@ impl AppModule {
             )
         }
 
+        self.sort_apps(&input);
+        self.input = input;
+
+        if config::SETTINGS.lock().expect("mutex").show_icons {
+            self.do_icon_lookup()
+        } else {
+            Task::none()
+        }
+    }
+
+    fn sort_apps(&mut self, input: &str) {
         let start = std::time::Instant::now();
         // Cached_key seems to be much faster which is interesting since text_value is
         // always changing
         // let input = &input.to_lowercase();
         self.app_list.sort_by_cached_key(|app| {
-            let mut score = sorting::score_element(&input, &app.name);
+            // Pinned apps only go to the top when nothing has been typed
+            let pin_rank = if input.is_empty() {
+                self.visibility.pin_rank(app).unwrap_or(usize::MAX)
+            } else {
+                usize::MAX
+            };
+
+            let mut score = sorting::score_element(input, &app.name);
 
             if let Some(raw_freq) = self.app_frequencies.get(&app.name) {
                 // Preview: https://www.desmos.com/calculator/vyac5ua1as
                 score += (*raw_freq as f32).ln().mul(0.5).max(0.0).floor() as i32;
             }
 
-            -score
+            (pin_rank, -score)
         });
 
         log::debug!(
@@ -216,14 +252,120 @@ impl AppModule {
             self.app_list.len(),
             start.elapsed()
         );
+    }
+
+    fn toggle_pin_selected(&mut self) -> Task<ModuleMessage> {
+        if let Some(app) = self.app_list.get(self.selected_index) {
+            self.visibility.toggle_pin(app);
+            let input = self.input.clone();
+            self.sort_apps(&input);
+        }
+        Task::none()
+    }
+
+    fn hide_selected(&mut self) -> Task<ModuleMessage> {
+        if self.selected_index < self.app_list.len() {
+            let app = self.app_list.remove(self.selected_index);
+            self.visibility.hide(&app);
+            self.selected_index = self
+                .selected_index
+                .min(self.app_list.len().saturating_sub(1));
+        }
+        Task::none()
+    }
+
+    fn handle_entries_changed(&mut self, changes: Vec<EntryChange>) -> Task<ModuleMessage> {
+        // Not loaded yet, so it will be read fresh anyway
+        if self.app_list.is_empty() {
+            return Task::none();
+        }
+
+        for change in changes {
+            match change {
+                EntryChange::Updated(app) => {
+                    self.app_list.retain(|a| a.id != app.id);
+                    if !self.visibility.is_hidden(&app) {
+                        self.app_list.push(*app);
+                    }
+                }
+                EntryChange::Removed(id) => {
+                    self.app_list.retain(|a| a.id.as_ref() != Some(&id));
+                }
+            }
+        }
+
+        let input = self.input.clone();
+        self.sort_apps(&input);
+        self.selected_index = self
+            .selected_index
+            .min(self.app_list.len().saturating_sub(1));
 
         if config::SETTINGS.lock().expect("mutex").show_icons {
+            self.have_started_icon_search = false;
             self.do_icon_lookup()
         } else {
             Task::none()
         }
     }
 
+    fn handle_icon_theme_changed(&mut self) -> Task<ModuleMessage> {
+        ICON_CACHE.lock().expect("not poisoned").clear();
+        if self.app_list.is_empty() {
+            return Task::none();
+        }
+
+        // Loaded icons don't keep their name, so load the apps again to look them all up
+        let selected_index = self.selected_index;
+        self.app_list.clear();
+        self.have_started_icon_search = false;
+        let task = self.handle_text_change(self.input.clone());
+        self.selected_index = selected_index.min(self.app_list.len().saturating_sub(1));
+        task
+    }
+
+    #[cfg(target_os = "linux")]
+    fn edit_selected(&self) -> Task<ModuleMessage> {
+        let Some(app) = self.app_list.get(self.selected_index) else {
+            return Task::none();
+        };
+        let Some(id) = &app.id else {
+            return Task::done(ModuleMessage::Error(format!(
+                "{} has no desktop file. Edit it in the config file instead",
+                app.name
+            )));
+        };
+
+        let path = match desktop_entry::copy_to_user_dir(id) {
+            Ok(path) => path.to_string_lossy().to_string(),
+            Err(e) => {
+                log::error!("Could not copy {id} for editing. E: {e}");
+                return Task::done(ModuleMessage::Error(format!("Could not copy {id}: {e}")));
+            }
+        };
+
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_default();
+        let mut editor = editor.split_whitespace().map(|s| s.to_string());
+        let (cmd, args) = match editor.next() {
+            Some(cmd) => {
+                let mut args: Vec<String> = editor.collect();
+                args.push(path);
+                // Usually a terminal editor like vim or nano
+                util::wrap_in_terminal(cmd, args)
+            }
+            None => ("xdg-open".to_string(), vec![path]),
+        };
+        launch::Launch::new(cmd).args(args).spawn()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn edit_selected(&self) -> Task<ModuleMessage> {
+        Task::done(ModuleMessage::Error(
+            "Editing apps is only supported on linux".to_string(),
+        ))
+    }
+
     fn do_icon_lookup(&mut self) -> Task<ModuleMessage> {
         let start = std::time::Instant::now();
 
@@ -340,10 +482,16 @@ impl Module for AppModule {
             ModuleMessage::AppMessage(AppMessage::IconLoaded(key, res)) => {
                 Self::handle_icon_loaded(self, key, res)
             }
-            ModuleMessage::ActivatedIndex(i) => {
-                Self::run_app_at_index(self, i);
-                iced::exit()
+            ModuleMessage::AppMessage(AppMessage::TogglePinSelected) => self.toggle_pin_selected(),
+            ModuleMessage::AppMessage(AppMessage::HideSelected) => self.hide_selected(),
+            ModuleMessage::AppMessage(AppMessage::EditSelected) => self.edit_selected(),
+            ModuleMessage::AppMessage(AppMessage::EntriesChanged(changes)) => {
+                self.handle_entries_changed(changes)
+            }
+            ModuleMessage::AppMessage(AppMessage::IconThemeChanged) => {
+                self.handle_icon_theme_changed()
             }
+            ModuleMessage::ActivatedIndex(i) => Self::run_app_at_index(self, i),
             ModuleMessage::SelectionUp => {
                 if self.selected_index >= 1 {
                     self.selected_index -= 1
@@ -364,11 +512,70 @@ impl Module for AppModule {
     }
 
     fn run(&self) -> Task<crate::message::Message> {
-        Self::run_app_at_index(self, self.selected_index);
-        iced::exit()
+        Self::run_app_at_index(self, self.selected_index)
+            .map(crate::message::Message::PluginMessage)
     }
 }
 
+impl TryFrom<config::CustomEntry> for App {
+    type Error = ();
+
+    fn try_from(entry: config::CustomEntry) -> Result<Self, Self::Error> {
+        let mut command = entry.command.into_iter();
+        let Some(cmd) = command.next() else {
+            log::warn!("Custom entry {} has an empty command. Skipping", entry.name);
+            return Err(());
+        };
+
+        Ok(App {
+            id: None,
+            cmd: util::expand_tilde(&cmd),
+            args: command.collect(),
+            working_dir: entry.working_dir.as_deref().map(util::expand_tilde),
+            name: entry.name,
+            subname: entry.subtext,
+            icon: entry
+                .icon
+                .as_deref()
+                .map(|i| Icon::NotFoundYet(util::expand_tilde(i))),
+            terminal: entry.terminal,
+            startup: None,
+        })
+    }
+}
+
+#[test]
+fn can_make_app_from_custom_entry() {
+    let entry = config::CustomEntry {
+        name: "Deploy".to_string(),
+        subtext: None,
+        icon: Some("/opt/deploy.png".to_string()),
+        command: vec!["/opt/deploy".to_string(), "--fast".to_string()],
+        working_dir: None,
+        terminal: true,
+    };
+    assert_eq!(
+        App::try_from(entry.clone()),
+        Ok(App {
+            id: None,
+            cmd: "/opt/deploy".to_string(),
+            args: vec!["--fast".to_string()],
+            working_dir: None,
+            name: "Deploy".to_string(),
+            subname: None,
+            icon: Some(Icon::NotFoundYet("/opt/deploy.png".to_string())),
+            terminal: true,
+            startup: None,
+        })
+    );
+
+    let empty = config::CustomEntry {
+        command: Vec::new(),
+        ..entry
+    };
+    assert!(App::try_from(empty).is_err());
+}
+
 async fn get_icon(icon_name: String) -> Option<(String, iced::widget::image::Handle)> {
     let start = iced::debug::time("GetIconTime");
 
@@ -0,0 +1,581 @@
+// `lumin validate-desktop <file|dir>`. Checks .desktop files against the spec, and against what
+// lumin can actually load, similar to `desktop-file-validate`.
+// https://specifications.freedesktop.org/desktop-entry-spec/latest/
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use super::desktop_entry::{self, ExecProblem, ParseError};
+use crate::serworse::{self, IniToken, IniTokenizer};
+
+const KNOWN_KEYS: [&str; 25] = [
+    "Type",
+    "Version",
+    "Name",
+    "GenericName",
+    "NoDisplay",
+    "Comment",
+    "Icon",
+    "Hidden",
+    "OnlyShowIn",
+    "NotShowIn",
+    "DBusActivatable",
+    "TryExec",
+    "Exec",
+    "Path",
+    "Terminal",
+    "Actions",
+    "MimeType",
+    "Categories",
+    "Implements",
+    "Keywords",
+    "StartupNotify",
+    "StartupWMClass",
+    "URL",
+    "PrefersNonDefaultGPU",
+    "SingleMainWindow",
+];
+
+/// Keys from older versions of the spec. Still seen in the wild
+const DEPRECATED_KEYS: [&str; 11] = [
+    "Encoding",
+    "MiniIcon",
+    "TerminalOptions",
+    "Protocols",
+    "Extensions",
+    "BinaryPattern",
+    "MapNotify",
+    "SwallowTitle",
+    "SwallowExec",
+    "SortOrder",
+    "FilePattern",
+];
+
+const ACTION_KEYS: [&str; 3] = ["Name", "Icon", "Exec"];
+
+const BOOLEAN_KEYS: [&str; 7] = [
+    "NoDisplay",
+    "Hidden",
+    "DBusActivatable",
+    "Terminal",
+    "StartupNotify",
+    "PrefersNonDefaultGPU",
+    "SingleMainWindow",
+];
+
+const LIST_KEYS: [&str; 7] = [
+    "OnlyShowIn",
+    "NotShowIn",
+    "Actions",
+    "MimeType",
+    "Categories",
+    "Implements",
+    "Keywords",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// 1 based. 0 if it is about the whole file
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {severity}: {}", self.line, self.message)
+    }
+}
+
+/// A key and its value, with the line it was on
+struct Line<'a> {
+    number: usize,
+    value: &'a str,
+}
+
+#[derive(Default)]
+struct Validator<'a> {
+    problems: Vec<Problem>,
+    /// Group name -> (line of the header, keys without a locale)
+    groups: HashMap<&'a str, (usize, HashMap<&'a str, Line<'a>>)>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, line: usize, message: impl ToString) {
+        self.problems.push(Problem {
+            line,
+            severity: Severity::Error,
+            message: message.to_string(),
+        });
+    }
+
+    fn warning(&mut self, line: usize, message: impl ToString) {
+        self.problems.push(Problem {
+            line,
+            severity: Severity::Warning,
+            message: message.to_string(),
+        });
+    }
+
+    /// Splits the file into groups, checking the syntax of each line on the way
+    fn read_lines(&mut self, contents: &'a str) {
+        let mut current_group: Option<&'a str> = None;
+        let mut seen_keys: HashSet<&'a str> = HashSet::new();
+
+        for token in IniTokenizer::new(contents) {
+            let (position, token) = match token {
+                Ok(token) => token,
+                Err(e) => {
+                    self.error(e.position.line, e.kind);
+                    continue;
+                }
+            };
+            let number = position.line;
+
+            let (key, value) = match token {
+                IniToken::Group(name) => {
+                    if current_group.is_none() && name != "Desktop Entry" {
+                        self.error(number, "The first group must be [Desktop Entry]");
+                    }
+                    if self.groups.contains_key(name) {
+                        self.error(number, format!("Group [{name}] is defined more than once"));
+                    } else if name != "Desktop Entry"
+                        && !name.starts_with("Desktop Action ")
+                        && !name.starts_with("X-")
+                    {
+                        self.error(number, format!("Unknown group [{name}]"));
+                    }
+                    self.groups
+                        .entry(name)
+                        .or_insert_with(|| (number, HashMap::new()));
+                    current_group = Some(name);
+                    seen_keys.clear();
+                    continue;
+                }
+                IniToken::Entry { key, value } => (key, value),
+            };
+
+            let Some(group) = current_group else {
+                self.error(number, format!("Key {key:?} is not inside a group"));
+                continue;
+            };
+
+            if !seen_keys.insert(key) {
+                self.error(
+                    number,
+                    format!("Key {key:?} is set more than once in [{group}]"),
+                );
+                continue;
+            }
+
+            let Some((base_key, locale)) = split_locale(key) else {
+                self.error(number, format!("Bad key name {key:?}"));
+                continue;
+            };
+            if !base_key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+            {
+                self.error(
+                    number,
+                    format!("Key {key:?} can only contain A-Z, a-z, 0-9 and -"),
+                );
+                continue;
+            }
+
+            if locale.is_none()
+                && let Some((_, keys)) = self.groups.get_mut(group)
+            {
+                keys.insert(base_key, Line { number, value });
+            }
+
+            self.check_key(group, base_key, number, value);
+        }
+    }
+
+    fn check_key(&mut self, group: &str, key: &str, number: usize, value: &str) {
+        if key.starts_with("X-") {
+            return;
+        }
+
+        if group.starts_with("Desktop Action ") {
+            if !ACTION_KEYS.contains(&key) {
+                self.error(number, format!("Unknown key {key:?} in [{group}]"));
+            }
+        } else if group == "Desktop Entry" {
+            if DEPRECATED_KEYS.contains(&key) {
+                self.warning(number, format!("Key {key:?} is deprecated"));
+                return;
+            }
+            if !KNOWN_KEYS.contains(&key) {
+                self.error(number, format!("Unknown key {key:?}"));
+                return;
+            }
+        } else {
+            // Only extension groups get here, which can contain anything
+            return;
+        }
+
+        if BOOLEAN_KEYS.contains(&key) {
+            if !matches!(value, "true" | "false") {
+                self.error(
+                    number,
+                    format!("Key {key:?} must be true or false, not {value:?}"),
+                );
+            }
+        } else if LIST_KEYS.contains(&key) {
+            for problem in check_list(value) {
+                self.error(number, format!("{problem} in list {key:?}"));
+            }
+        } else if key == "Exec" {
+            for problem in
+                desktop_entry::check_exec_key(&desktop_entry::unescape_value(value), None, None).1
+            {
+                match problem {
+                    ExecProblem::DeprecatedFieldCode(_) => {
+                        self.warning(number, format!("{problem} in Exec"))
+                    }
+                    _ => self.error(number, format!("{problem} in Exec")),
+                }
+            }
+        } else if let Some(problem) = bad_escape(value) {
+            self.error(number, format!("{problem} in {key:?}"));
+        }
+    }
+
+    /// Checks for missing keys and groups, after every line has been read
+    fn check_groups(&mut self) {
+        let Some((header_line, keys)) = self.groups.get("Desktop Entry") else {
+            self.error(0, "Missing the [Desktop Entry] group");
+            return;
+        };
+        let header_line = *header_line;
+        let get = |key: &str| keys.get(key).map(|l| (l.number, l.value));
+
+        let mut missing = Vec::new();
+        let mut unknown_type = None;
+        match get("Type") {
+            Some((_, "Application")) => {
+                let dbus = get("DBusActivatable").is_some_and(|(_, v)| v == "true");
+                if get("Exec").is_none() && !dbus {
+                    missing.push("Exec");
+                }
+            }
+            Some((_, "Link")) if get("URL").is_none() => missing.push("URL"),
+            Some((_, "Link" | "Directory")) => {}
+            Some(other) => unknown_type = Some(other),
+            None => missing.push("Type"),
+        }
+        if get("Name").is_none() {
+            missing.push("Name");
+        }
+        let actions = get("Actions")
+            .map(|(line, value)| (line, desktop_entry::parse_string_list(Some(value))));
+
+        if let Some((line, value)) = unknown_type {
+            self.error(line, format!("Unknown Type {value:?}"));
+        }
+        for key in missing {
+            self.error(header_line, format!("Missing required key {key:?}"));
+        }
+
+        let (actions_line, listed_actions) = actions.unwrap_or_default();
+        for action in &listed_actions {
+            if !self
+                .groups
+                .contains_key(format!("Desktop Action {action}").as_str())
+            {
+                self.error(
+                    actions_line,
+                    format!("Action {action:?} has no [Desktop Action {action}] group"),
+                );
+            }
+        }
+
+        let mut action_groups: Vec<(String, usize, bool)> = self
+            .groups
+            .iter()
+            .filter_map(|(name, (line, keys))| {
+                let action = name.strip_prefix("Desktop Action ")?;
+                Some((action.to_string(), *line, keys.contains_key("Name")))
+            })
+            .collect();
+        action_groups.sort_by_key(|(_, line, _)| *line);
+        for (action, line, has_name) in action_groups {
+            if !has_name {
+                self.error(
+                    line,
+                    format!("[Desktop Action {action}] is missing \"Name\""),
+                );
+            }
+            if !listed_actions.contains(&action) {
+                self.warning(
+                    line,
+                    format!("[Desktop Action {action}] is not listed in \"Actions\""),
+                );
+            }
+        }
+    }
+}
+
+/// Splits `Name[de_DE]` into `("Name", Some("de_DE"))`. `None` if the brackets are wrong
+fn split_locale(key: &str) -> Option<(&str, Option<&str>)> {
+    match key.split_once('[') {
+        Some((base, rest)) => {
+            let locale = rest.strip_suffix(']')?;
+            if locale.is_empty() || locale.contains(['[', ']']) {
+                return None;
+            }
+            Some((base, Some(locale)))
+        }
+        None if key.contains(']') => None,
+        None => Some((key, None)),
+    }
+}
+
+/// The first bad escape sequence in a string value
+fn bad_escape(value: &str) -> Option<String> {
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('s' | 'n' | 't' | 'r' | '\\') => {}
+                Some(c) => return Some(format!("Unknown escape sequence \\{c}")),
+                None => return Some("No character after backslash".to_string()),
+            }
+        }
+    }
+    None
+}
+
+/// Everything wrong with a `;` separated list
+fn check_list(value: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut chars = value.chars();
+    let mut item_len = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(';' | 's' | 'n' | 't' | 'r' | '\\') => item_len += 1,
+                Some(c) => problems.push(format!("Unknown escape sequence \\{c}")),
+                None => problems.push("No character after backslash".to_string()),
+            },
+            ';' if item_len == 0 => problems.push("Empty item".to_string()),
+            ';' => item_len = 0,
+            _ => item_len += 1,
+        }
+    }
+    problems
+}
+
+/// Every problem with the .desktop file `contents`, in line order
+pub fn validate(contents: &str) -> Vec<Problem> {
+    let mut validator = Validator::default();
+    validator.read_lines(contents);
+    validator.check_groups();
+
+    let has_errors = validator
+        .problems
+        .iter()
+        .any(|p| p.severity == Severity::Error);
+    // Anything lumin refuses to load should have been caught above, but just in case
+    if !has_errors
+        && let Err(e) = desktop_entry::parse_from_hashmap(serworse::parse_ini_format(contents))
+        && !matches!(e, ParseError::NoDisplayTrue)
+    {
+        validator.error(0, format!("Lumin could not load this entry. E: {e:?}"));
+    }
+
+    validator.problems.sort_by_key(|p| p.line);
+    validator.problems
+}
+
+/// Every .desktop file in `path`, or just `path` if it is a file
+fn desktop_files(path: &Path) -> Vec<PathBuf> {
+    if !path.is_dir() {
+        return vec![path.to_path_buf()];
+    }
+    let mut files: Vec<PathBuf> = WalkDir::new(path)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|p| p.extension().is_some_and(|e| e == "desktop"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Prints every problem in `path` to stdout, like `desktop-file-validate`.
+/// Errors if any file has an error. Warnings alone are fine.
+pub fn run(path: &Path) -> Result<(), String> {
+    let files = desktop_files(path);
+    if files.is_empty() {
+        return Err(format!("No .desktop files found in {}", path.display()));
+    }
+
+    let mut error_count = 0;
+    for file in &files {
+        let contents = match std::fs::read_to_string(file) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("{}: error: Could not read file: {e}", file.display());
+                error_count += 1;
+                continue;
+            }
+        };
+        for problem in validate(&contents) {
+            if problem.severity == Severity::Error {
+                error_count += 1;
+            }
+            println!("{}:{problem}", file.display());
+        }
+    }
+
+    if error_count > 0 {
+        return Err(format!(
+            "{error_count} errors in {} files checked",
+            files.len()
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn valid_entry_has_no_problems() {
+    let contents = r#"# A comment
+[Desktop Entry]
+Type=Application
+Name=Foo
+Name[de]=Fuu
+Exec=foo --new %U "a \\$b"
+Categories=Utility;Text\;Editor;
+Comment=Line one\nline two
+X-KDE-Thing=whatever
+Actions=new-window;
+
+[Desktop Action new-window]
+Name=New Window
+Exec=foo --new-window
+
+[X-Extension Group]
+Anything=goes
+"#;
+    assert_eq!(validate(contents), vec![]);
+}
+
+#[test]
+fn problems_have_line_numbers() {
+    let contents = r#"[Desktop Entry]
+Type=Application
+Name=Foo
+Exec=foo \q %z %d
+Categories=;Utility\
+Terminal=yes
+Colour=red
+Encoding=UTF-8
+Name=Duplicate
+Actions=missing;
+what is this line
+
+[Desktop Action unused]
+Exec=bar
+[Not A Real Group]
+"#;
+    let problems: Vec<(usize, Severity, String)> = validate(contents)
+        .into_iter()
+        .map(|p| (p.line, p.severity, p.message))
+        .collect();
+
+    let expected = [
+        (4, Severity::Error, "Unknown escape sequence \\q in Exec"),
+        (4, Severity::Error, "Unknown field code %z in Exec"),
+        (4, Severity::Warning, "Deprecated field code %d in Exec"),
+        (5, Severity::Error, "Empty item in list \"Categories\""),
+        (
+            5,
+            Severity::Error,
+            "No character after backslash in list \"Categories\"",
+        ),
+        (
+            6,
+            Severity::Error,
+            "Key \"Terminal\" must be true or false, not \"yes\"",
+        ),
+        (7, Severity::Error, "Unknown key \"Colour\""),
+        (8, Severity::Warning, "Key \"Encoding\" is deprecated"),
+        (
+            9,
+            Severity::Error,
+            "Key \"Name\" is set more than once in [Desktop Entry]",
+        ),
+        (
+            10,
+            Severity::Error,
+            "Action \"missing\" has no [Desktop Action missing] group",
+        ),
+        (11, Severity::Error, "Expected key=value, group or comment"),
+        (
+            13,
+            Severity::Error,
+            "[Desktop Action unused] is missing \"Name\"",
+        ),
+        (
+            13,
+            Severity::Warning,
+            "[Desktop Action unused] is not listed in \"Actions\"",
+        ),
+        (15, Severity::Error, "Unknown group [Not A Real Group]"),
+    ];
+    assert_eq!(
+        problems,
+        expected
+            .iter()
+            .map(|(l, s, m)| (*l, *s, m.to_string()))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn missing_keys_are_reported() {
+    let problems = validate("[Desktop Entry]\nType=Link\nComment=a\\\n");
+    let messages: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+    assert_eq!(
+        messages,
+        [
+            "1: error: Missing required key \"URL\"",
+            "1: error: Missing required key \"Name\"",
+            "3: error: No character after backslash in \"Comment\"",
+        ]
+    );
+
+    let problems = validate("Name=Foo\n[Other]\n");
+    assert!(
+        problems
+            .iter()
+            .any(|p| p.line == 1 && p.message == "Key \"Name\" is not inside a group")
+    );
+    assert!(
+        problems
+            .iter()
+            .any(|p| p.line == 2 && p.message == "The first group must be [Desktop Entry]")
+    );
+    assert!(
+        problems
+            .iter()
+            .any(|p| p.message == "Missing the [Desktop Entry] group")
+    );
+}
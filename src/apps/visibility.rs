@@ -0,0 +1,179 @@
+// Pinned and hidden apps.
+// The lists in the config are the base, and changes made with hotkeys are stored in `DATA_DIR`
+// so they override the config.
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use super::App;
+use crate::config;
+use crate::constants;
+use crate::serworse;
+use crate::store::Store;
+
+const APP_VISIBILITY_RELPATH: &str = "app_visibility";
+
+static APP_VISIBILITY_FILE_PATH: LazyLock<String> =
+    LazyLock::new(|| constants::DATA_DIR.to_owned() + APP_VISIBILITY_RELPATH);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Pinned,
+    /// Explicitly unpinned, so a pin from the config is ignored
+    Unpinned,
+    Hidden,
+}
+
+impl std::str::FromStr for State {
+    type Err = serworse::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pinned" => Ok(State::Pinned),
+            "unpinned" => Ok(State::Unpinned),
+            "hidden" => Ok(State::Hidden),
+            _ => Err(serworse::ParseError::IntoFailure),
+        }
+    }
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            State::Pinned => "pinned",
+            State::Unpinned => "unpinned",
+            State::Hidden => "hidden",
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Visibility {
+    config_pinned: Vec<String>,
+    config_hidden: Vec<String>,
+    /// Changes made from inside lumin. Keyed by desktop file ID, or name if the app has no ID.
+    overrides: HashMap<String, State>,
+}
+
+impl Visibility {
+    pub fn load() -> Self {
+        let settings = config::SETTINGS.lock().expect("mutex").app_settings.clone();
+
+        Visibility {
+            config_pinned: settings.pinned,
+            config_hidden: settings.hidden,
+            overrides: Store::new(APP_VISIBILITY_FILE_PATH.as_str()).load(),
+        }
+    }
+
+    fn override_for(&self, app: &App) -> Option<State> {
+        self.overrides
+            .iter()
+            .find(|(key, _)| matches(key, app))
+            .map(|(_, state)| *state)
+    }
+
+    pub fn is_hidden(&self, app: &App) -> bool {
+        match self.override_for(app) {
+            Some(state) => state == State::Hidden,
+            None => self.config_hidden.iter().any(|key| matches(key, app)),
+        }
+    }
+
+    /// Position of the app in the pinned list, or `None` if it is not pinned.
+    /// Pins from the config come first, in order, then ones added with the hotkey.
+    pub fn pin_rank(&self, app: &App) -> Option<usize> {
+        match self.override_for(app) {
+            Some(State::Pinned) => Some(self.config_pinned.len()),
+            Some(_) => None,
+            None => self.config_pinned.iter().position(|key| matches(key, app)),
+        }
+    }
+
+    pub fn toggle_pin(&mut self, app: &App) {
+        let state = if self.pin_rank(app).is_some() {
+            State::Unpinned
+        } else {
+            State::Pinned
+        };
+        log::info!("Setting app {} to {state}", app.name);
+        self.set(app, state);
+    }
+
+    pub fn hide(&mut self, app: &App) {
+        log::info!("Hiding app {}", app.name);
+        self.set(app, State::Hidden);
+    }
+
+    fn set(&mut self, app: &App, state: State) {
+        let change = |overrides: &mut HashMap<String, State>| {
+            overrides.retain(|key, _| !matches(key, app));
+            overrides.insert(key_for(app), state);
+        };
+        change(&mut self.overrides);
+        // Another instance may have changed it since it was loaded
+        match Store::new(APP_VISIBILITY_FILE_PATH.as_str()).update(change) {
+            Ok(overrides) => self.overrides = overrides,
+            Err(e) => log::error!("Could not write app_visibility to file!! e: {e}"),
+        }
+    }
+}
+
+fn key_for(app: &App) -> String {
+    app.id.clone().unwrap_or_else(|| app.name.clone())
+}
+
+/// Keys can be the desktop file ID, with or without `.desktop`, or the apps name
+fn matches(key: &str, app: &App) -> bool {
+    if let Some(id) = &app.id
+        && (id == key || id.strip_suffix(".desktop") == Some(key))
+    {
+        return true;
+    }
+    app.name == key
+}
+
+#[cfg(test)]
+fn test_app(id: Option<&str>, name: &str) -> App {
+    App {
+        id: id.map(|s| s.to_string()),
+        cmd: String::new(),
+        args: Vec::new(),
+        working_dir: None,
+        name: name.to_string(),
+        subname: None,
+        icon: None,
+        terminal: false,
+        startup: None,
+    }
+}
+
+#[test]
+fn matches_by_id_or_name() {
+    let app = test_app(Some("org.mozilla.firefox.desktop"), "Firefox");
+    assert!(matches("org.mozilla.firefox.desktop", &app));
+    assert!(matches("org.mozilla.firefox", &app));
+    assert!(matches("Firefox", &app));
+    assert!(!matches("firefox", &app));
+}
+
+#[test]
+fn overrides_beat_config() {
+    let firefox = test_app(Some("firefox.desktop"), "Firefox");
+    let avahi = test_app(Some("bssh.desktop"), "Avahi SSH Server Browser");
+    let mut vis = Visibility {
+        config_pinned: vec!["firefox".to_string()],
+        config_hidden: vec!["Avahi SSH Server Browser".to_string()],
+        overrides: HashMap::new(),
+    };
+
+    assert_eq!(vis.pin_rank(&firefox), Some(0));
+    assert!(vis.is_hidden(&avahi));
+    assert_eq!(vis.pin_rank(&avahi), None);
+
+    vis.overrides.insert(key_for(&firefox), State::Unpinned);
+    vis.overrides.insert(key_for(&avahi), State::Pinned);
+    assert_eq!(vis.pin_rank(&firefox), None);
+    assert!(!vis.is_hidden(&avahi));
+    assert_eq!(vis.pin_rank(&avahi), Some(1));
+}
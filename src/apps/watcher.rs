@@ -0,0 +1,336 @@
+// Watches the `applications` dirs and icon theme dirs with inotify, so apps and icons that are
+// installed or removed while lumin is open show up without restarting it.
+// Only the .desktop files that changed are parsed again.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures::SinkExt;
+use futures::channel::mpsc;
+use walkdir::WalkDir;
+
+use super::{App, AppMessage, EntryChange, desktop_entry};
+use crate::icons;
+
+/// Package managers write many files at once, so changes are collected for this long before
+/// being sent
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF;
+
+#[derive(Debug, Clone, PartialEq)]
+enum WatchKind {
+    /// Inside the `applications` dir at `root`
+    Applications {
+        root: PathBuf,
+    },
+    Icons,
+}
+
+#[derive(Debug, Default)]
+struct Batch {
+    /// (applications dir, path to the .desktop file)
+    desktop_files: HashSet<(PathBuf, PathBuf)>,
+    icons_changed: bool,
+}
+
+struct Inotify {
+    fd: OwnedFd,
+    watches: HashMap<i32, (PathBuf, WatchKind)>,
+}
+
+impl Inotify {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Inotify {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            watches: HashMap::new(),
+        })
+    }
+
+    fn add(&mut self, path: &Path, kind: WatchKind) {
+        let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+            return;
+        };
+        let wd =
+            unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), WATCH_MASK) };
+        if wd == -1 {
+            log::warn!(
+                "Could not watch {path:?}. E: {}",
+                io::Error::last_os_error()
+            );
+            return;
+        }
+        self.watches.insert(wd, (path.to_path_buf(), kind));
+    }
+
+    fn add_recursive(&mut self, root: &Path, kind: &WatchKind) {
+        for entry in WalkDir::new(root)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_dir())
+        {
+            self.add(entry.path(), kind.clone());
+        }
+    }
+
+    /// Waits up to `timeout` for events. `None` waits forever.
+    /// Returns false if nothing happened in time.
+    fn wait(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.map_or(-1, |t| t.as_millis() as i32);
+        let res = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        match res {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    /// Reads the pending events into `batch`
+    fn read_into(&mut self, batch: &mut Batch) -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        let len = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if len == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let header_len = std::mem::size_of::<libc::inotify_event>();
+        let mut offset = 0;
+        while offset + header_len <= len as usize {
+            let event = unsafe {
+                std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const libc::inotify_event)
+            };
+            let name_bytes = &buf[offset + header_len..offset + header_len + event.len as usize];
+            let name = OsStr::from_bytes(name_bytes.split(|b| *b == 0).next().unwrap_or_default());
+            offset += header_len + event.len as usize;
+
+            if event.mask & libc::IN_IGNORED != 0 {
+                self.watches.remove(&event.wd);
+                continue;
+            }
+            let Some((dir, kind)) = self.watches.get(&event.wd).cloned() else {
+                continue;
+            };
+            let path = dir.join(name);
+
+            if event.mask & libc::IN_ISDIR != 0 {
+                if event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                    self.add_recursive(&path, &kind);
+                }
+                if let WatchKind::Applications { root } = &kind {
+                    // Everything inside a moved or new dir changed
+                    for entry in WalkDir::new(&path).into_iter().filter_map(|e| e.ok()) {
+                        batch
+                            .desktop_files
+                            .insert((root.clone(), entry.path().to_path_buf()));
+                    }
+                }
+            }
+
+            match kind {
+                WatchKind::Icons => batch.icons_changed = true,
+                WatchKind::Applications { root } => {
+                    if path.extension() == Some(OsStr::new("desktop")) {
+                        batch.desktop_files.insert((root, path));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Blocks until something changes, then collects everything that changes within `DEBOUNCE`
+    fn next_batch(&mut self) -> io::Result<Batch> {
+        let mut batch = Batch::default();
+        self.wait(None)?;
+        self.read_into(&mut batch)?;
+        while self.wait(Some(DEBOUNCE))? {
+            self.read_into(&mut batch)?;
+        }
+        Ok(batch)
+    }
+}
+
+/// Works out what happened to each changed .desktop file.
+/// Removing a file can uncover one with the same ID in a lower priority dir.
+fn resolve_changes(
+    desktop_files: HashSet<(PathBuf, PathBuf)>,
+    applications_dirs: &[PathBuf],
+) -> Vec<EntryChange> {
+    let mut ids = HashSet::new();
+    let mut changes = Vec::new();
+
+    for (root, path) in desktop_files {
+        if path.extension() != Some(OsStr::new("desktop")) {
+            continue;
+        }
+        let id = desktop_entry::desktop_file_id(&root, &path);
+        if !ids.insert(id.clone()) {
+            continue;
+        }
+
+        let relative = path.strip_prefix(&root).unwrap_or(&path);
+        let existing = applications_dirs
+            .iter()
+            .map(|dir| (dir, dir.join(relative)))
+            .find(|(_, path)| path.is_file());
+
+        let change = match existing {
+            Some((dir, path)) => match desktop_entry::parse_desktop_file(dir, &path) {
+                Ok(entry) => EntryChange::Updated(Box::new(App::from(entry))),
+                Err(e) => {
+                    log::debug!("Changed desktop file {path:?} is not shown. E: {e:?}");
+                    EntryChange::Removed(id)
+                }
+            },
+            None => EntryChange::Removed(id),
+        };
+        changes.push(change);
+    }
+    changes
+}
+
+fn run(mut output: mpsc::Sender<AppMessage>) -> io::Result<()> {
+    let mut inotify = Inotify::new()?;
+
+    let applications_dirs: Vec<PathBuf> = desktop_entry::get_applications_dirs()
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_dir())
+        .collect();
+    for dir in &applications_dirs {
+        inotify.add_recursive(dir, &WatchKind::Applications { root: dir.clone() });
+    }
+    // Loose icons, and new themes. Dirs created inside any watched dir get watched too
+    for dir in icons::theme::base_dirs().iter().filter(|dir| dir.is_dir()) {
+        inotify.add(dir, WatchKind::Icons);
+    }
+    // Icons can be in dirs index.theme doesn't list, so all of them are watched
+    for dir in icons::theme_dirs() {
+        inotify.add_recursive(&dir, &WatchKind::Icons);
+    }
+    log::info!("Watching {} dirs for app changes", inotify.watches.len());
+
+    loop {
+        let batch = inotify.next_batch()?;
+
+        if batch.icons_changed {
+            log::info!("Icon themes changed. Reloading them");
+            icons::reload_themes();
+            if futures::executor::block_on(output.send(AppMessage::IconThemeChanged)).is_err() {
+                return Ok(());
+            }
+        }
+
+        let changes = resolve_changes(batch.desktop_files, &applications_dirs);
+        if !changes.is_empty() {
+            log::info!("{} desktop entries changed", changes.len());
+            if futures::executor::block_on(output.send(AppMessage::EntriesChanged(changes)))
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn watch() -> impl iced::futures::Stream<Item = AppMessage> {
+    iced::stream::channel(16, async |output| {
+        let res = tokio::task::spawn_blocking(move || run(output)).await;
+        match res {
+            Ok(Err(e)) => log::error!("App watcher stopped. E: {e}"),
+            Err(e) => log::error!("App watcher panicked. E: {e}"),
+            Ok(Ok(())) => {}
+        }
+    })
+}
+
+pub fn subscription() -> iced::Subscription<AppMessage> {
+    iced::Subscription::run(watch)
+}
+
+#[test]
+fn changes_are_read_from_events() {
+    let root = std::env::temp_dir().join(format!("lumin_watch_test_{}", std::process::id()));
+    let system = root.join("system/applications");
+    let user = root.join("user/applications");
+    std::fs::create_dir_all(&system).unwrap();
+    std::fs::create_dir_all(&user).unwrap();
+
+    let mut inotify = Inotify::new().unwrap();
+    for dir in [&user, &system] {
+        inotify.add_recursive(dir, &WatchKind::Applications { root: dir.clone() });
+    }
+
+    let entry = "[Desktop Entry]\nType=Application\nName=Foo\nExec=foo\n";
+    std::fs::write(system.join("foo.desktop"), entry).unwrap();
+    std::fs::write(user.join("foo.desktop"), entry.replace("Foo", "User Foo")).unwrap();
+    std::fs::create_dir(system.join("sub")).unwrap();
+    std::fs::write(system.join("sub/bar.desktop"), entry.replace("Foo", "Bar")).unwrap();
+
+    let batch = inotify.next_batch().unwrap();
+    assert!(!batch.icons_changed);
+    let changes = resolve_changes(batch.desktop_files, &[user.clone(), system.clone()]);
+    let mut names: Vec<String> = changes
+        .iter()
+        .map(|c| match c {
+            EntryChange::Updated(app) => app.name.clone(),
+            EntryChange::Removed(id) => id.clone(),
+        })
+        .collect();
+    names.sort();
+    assert_eq!(names, ["Bar", "User Foo"]);
+
+    // Removing the users copy uncovers the system one
+    std::fs::remove_file(user.join("foo.desktop")).unwrap();
+    std::fs::remove_file(system.join("sub/bar.desktop")).unwrap();
+    let batch = inotify.next_batch().unwrap();
+    let mut changes = resolve_changes(batch.desktop_files, &[user.clone(), system.clone()]);
+    changes.sort_by_key(|c| format!("{c:?}"));
+    match &changes[..] {
+        [EntryChange::Removed(id), EntryChange::Updated(app)] => {
+            assert_eq!(id, "sub-bar.desktop");
+            assert_eq!(app.name, "Foo");
+        }
+        other => panic!("Unexpected changes {other:?}"),
+    }
+
+    // New dirs inside icon themes are watched too
+    let theme = root.join("icons/Theme");
+    std::fs::create_dir_all(&theme).unwrap();
+    inotify.add_recursive(&theme, &WatchKind::Icons);
+    std::fs::create_dir(theme.join("64x64")).unwrap();
+    let batch = inotify.next_batch().unwrap();
+    assert!(batch.icons_changed);
+    std::fs::write(theme.join("64x64/new.png"), "").unwrap();
+    let batch = inotify.next_batch().unwrap();
+    assert!(batch.icons_changed);
+    assert!(batch.desktop_files.is_empty());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
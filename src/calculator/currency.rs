@@ -0,0 +1,230 @@
+// Currency conversion, like `100 USD in EUR`.
+// Rates are read from `currency_rates.json` or `currency_rates.csv` in `DATA_DIR`, which are
+// managed by the user. `lumin update-rates` downloads a new json file from `currency_rates_url`
+// in the config. Nothing is downloaded while calculating, so the rates can be old, and their date
+// is shown under any answer that uses them.
+//
+// The json is in the format used by https://frankfurter.dev
+// {"base": "EUR", "date": "2024-05-01", "rates": {"USD": 1.07, "GBP": 0.85}}
+//
+// The csv has one `CODE,rate` per line, plus `base` and `date` lines
+// base,EUR
+// date,2024-05-01
+// USD,1.07
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use serde::Deserialize;
+
+use super::units::{self, Unit};
+use crate::{config, constants, store};
+
+const JSON_RELPATH: &str = "currency_rates.json";
+const CSV_RELPATH: &str = "currency_rates.csv";
+
+/// Read the first time a calculation needs them. `None` if there is no rates file
+pub static RATES: LazyLock<Option<Rates>> = LazyLock::new(load);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Rates {
+    pub base: String,
+    /// When the rates are from, like 2024-05-01
+    pub date: String,
+    /// How much of each currency one `base` is worth
+    pub rates: HashMap<String, f64>,
+}
+
+impl Rates {
+    pub fn from_json(data: &str) -> Result<Rates, String> {
+        serde_json::from_str::<Rates>(data)
+            .map_err(|e| e.to_string())?
+            .checked()
+    }
+
+    pub fn from_csv(data: &str) -> Result<Rates, String> {
+        let mut base = None;
+        let mut date = None;
+        let mut rates = HashMap::new();
+
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(',') else {
+                return Err(format!("Line {} has no comma", i + 1));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "base" => base = Some(value.to_string()),
+                "date" => date = Some(value.to_string()),
+                code => {
+                    let rate = value
+                        .parse()
+                        .map_err(|e| format!("Line {} has a bad rate. E: {e}", i + 1))?;
+                    rates.insert(code.to_string(), rate);
+                }
+            }
+        }
+
+        Rates {
+            base: base.ok_or("Missing base line")?,
+            date: date.ok_or("Missing date line")?,
+            rates,
+        }
+        .checked()
+    }
+
+    /// Makes the codes uppercase, and adds `base` as 1
+    fn checked(mut self) -> Result<Rates, String> {
+        if let Some((code, rate)) = self
+            .rates
+            .iter()
+            .find(|(_, rate)| !rate.is_finite() || **rate <= 0.0)
+        {
+            return Err(format!("{code} has a rate of {rate}"));
+        }
+        self.base = self.base.to_ascii_uppercase();
+        self.rates = self
+            .rates
+            .into_iter()
+            .map(|(code, rate)| (code.to_ascii_uppercase(), rate))
+            .collect();
+        self.rates.insert(self.base.clone(), 1.0);
+        Ok(self)
+    }
+
+    /// Codes can be any case, like `usd`
+    pub fn lookup(&self, name: &str) -> Option<Unit> {
+        if name.len() != 3 {
+            return None;
+        }
+        let code = name.to_ascii_uppercase();
+        let rate = self.rates.get(&code)?;
+        Some(units::currency(code, 1.0 / rate))
+    }
+}
+
+fn load() -> Option<Rates> {
+    for relpath in [JSON_RELPATH, CSV_RELPATH] {
+        let path = constants::DATA_DIR.to_owned() + relpath;
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                log::error!("Could not read {path}. E: {e}");
+                continue;
+            }
+        };
+        let parsed = match relpath {
+            JSON_RELPATH => Rates::from_json(&data),
+            _ => Rates::from_csv(&data),
+        };
+        match parsed {
+            Ok(rates) => {
+                log::debug!("Loaded {} currency rates from {path}", rates.rates.len());
+                return Some(rates);
+            }
+            Err(e) => log::error!("Could not read currency rates in {path}. E: {e}"),
+        }
+    }
+    None
+}
+
+/// `lumin update-rates`
+pub fn update() -> Result<(), String> {
+    let url = config::SETTINGS
+        .lock()
+        .expect("mutex")
+        .calculator_settings
+        .currency_rates_url
+        .clone();
+    let Some(url) = url else {
+        return Err(
+            "Set currency_rates_url under [calculator_settings] in the config first".to_string(),
+        );
+    };
+
+    let path = PathBuf::from(constants::DATA_DIR.to_owned() + JSON_RELPATH);
+    let rates = download(&url, &path)?;
+    println!(
+        "Saved {} rates from {} to {}",
+        rates.rates.len(),
+        rates.date,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Only replaces the file at `path` if the download is valid rates json
+fn download(url: &str, path: &Path) -> Result<Rates, String> {
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+    let body = runtime
+        .block_on(async { reqwest::get(url).await?.error_for_status()?.text().await })
+        .map_err(|e| format!("Could not download rates from {url}. E: {e}"))?;
+
+    let rates = Rates::from_json(&body).map_err(|e| format!("{url} did not send rates. E: {e}"))?;
+    store::write_atomic(path, &body)
+        .map_err(|e| format!("Could not save rates to {}. E: {e}", path.display()))?;
+    Ok(rates)
+}
+
+#[test]
+fn rates_can_be_read() {
+    let json = r#"{"amount": 1.0, "base": "EUR", "date": "2024-05-01", "rates": {"USD": 1.25, "gbp": 0.8}}"#;
+    let rates = Rates::from_json(json).unwrap();
+    assert_eq!(rates.date, "2024-05-01");
+    assert_eq!(rates.rates["EUR"], 1.0);
+    assert_eq!(rates.rates["GBP"], 0.8);
+
+    let csv = "# From the bank\nbase,EUR\ndate,2024-05-01\nUSD, 1.25\nGBP,0.8\n";
+    assert_eq!(Rates::from_csv(csv).unwrap(), rates);
+
+    assert!(rates.lookup("usd").is_some());
+    assert!(rates.lookup("XYZ").is_none());
+
+    assert!(Rates::from_csv("base,EUR\nUSD,1.1\n").is_err());
+    assert!(Rates::from_csv("base,EUR\ndate,2024-05-01\nUSD,-1\n").is_err());
+    assert!(Rates::from_json("{}").is_err());
+}
+
+#[test]
+fn rates_can_be_downloaded() {
+    use std::io::{Read, Write};
+
+    let body = r#"{"base": "EUR", "date": "2024-05-01", "rates": {"USD": 1.25}}"#;
+    // Stands in for the rates server
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        for response in [body.to_string(), "not json".to_string()] {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                response.len()
+            )
+            .unwrap();
+        }
+    });
+
+    let dir = std::env::temp_dir().join(format!("lumin_rates_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(JSON_RELPATH);
+    let url = format!("http://127.0.0.1:{port}/latest");
+
+    let rates = download(&url, &path).unwrap();
+    assert_eq!(rates.rates["USD"], 1.25);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), body);
+
+    // A bad download leaves the old rates alone
+    assert!(download(&url, &path).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), body);
+
+    server.join().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,188 @@
+// Dates, times and durations, like `now + 3 days`, `2026-12-25 - today` or `10:30 in Tokyo`.
+// Durations are quantities in units of time, and dates are `jiff::Zoned`.
+// Time zones are read from the tzdata in /usr/share/zoneinfo.
+
+use jiff::tz::{self, TimeZone};
+use jiff::{SignedDuration, Timestamp, Zoned, civil};
+
+use super::Value;
+use super::parse::{BinOp, Node, NodeKind};
+use super::units::{self, Quantity};
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Differences of less than a day are shown in the biggest of these that fits, or seconds
+const DIFFERENCE_UNITS: [(&str, f64); 2] = [("hours", 3_600.0), ("minutes", 60.0)];
+
+/// Adding these keeps the time of day
+const CALENDAR_UNITS: [&str; 6] = ["d", "day", "days", "wk", "week", "weeks"];
+
+pub fn seconds() -> Quantity {
+    Quantity::of_unit(units::lookup("s").expect("s is a unit"))
+}
+
+/// `date` in the time zone of `now`
+pub fn at(now: &Zoned, date: civil::DateTime) -> Result<Zoned, String> {
+    date.to_zoned(now.time_zone().clone())
+        .map_err(|e| e.to_string())
+}
+
+/// `+` and `-` with dates. Either side of `+` can be the date
+pub fn combine(op: BinOp, lhs: Value, rhs: Value) -> Result<Value, String> {
+    match (op, lhs, rhs) {
+        (BinOp::Add, Value::Moment(moment), Value::Quantity(duration))
+        | (BinOp::Add, Value::Quantity(duration), Value::Moment(moment)) => {
+            add(&moment, &duration, 1.0)
+        }
+        (BinOp::Subtract, Value::Moment(moment), Value::Quantity(duration)) => {
+            add(&moment, &duration, -1.0)
+        }
+        (BinOp::Subtract, Value::Moment(end), Value::Moment(start)) => {
+            Ok(Value::Quantity(difference(&end, &start)))
+        }
+        (op, _, _) => Err(format!("Dates can't be used with {op}")),
+    }
+}
+
+/// Days and weeks keep the time of day, even when daylight saving starts or ends.
+/// Anything else, like `24 h`, is the time that really passes
+fn add(moment: &Zoned, duration: &Quantity, sign: f64) -> Result<Value, String> {
+    let seconds = duration
+        .value_in(&self::seconds(), "add")
+        .map_err(|_| "Only times like 3 days can be added to or taken from dates".to_string())?;
+    let calendar = duration
+        .unit
+        .as_ref()
+        .and_then(|unit| unit.name())
+        .is_some_and(|name| CALENDAR_UNITS.contains(&name));
+    let duration = SignedDuration::try_from_secs_f64(sign * seconds).map_err(|e| e.to_string())?;
+    let moment = if calendar {
+        moment
+            .datetime()
+            .checked_add(duration)
+            .and_then(|date| date.to_zoned(moment.time_zone().clone()))
+    } else {
+        moment.checked_add(duration)
+    };
+    moment.map(Value::Moment).map_err(|e| e.to_string())
+}
+
+/// Days are by the clock if they are in the same time zone, so 2 midnights are a whole number of
+/// days apart. Anything shorter is the time that really passed
+fn difference(end: &Zoned, start: &Zoned) -> Quantity {
+    let seconds = end.duration_since(start).as_secs_f64();
+    let clock_seconds = match end.time_zone() == start.time_zone() {
+        true => end
+            .datetime()
+            .duration_since(start.datetime())
+            .as_secs_f64(),
+        false => seconds,
+    };
+    if clock_seconds.abs() >= SECONDS_PER_DAY {
+        return Quantity {
+            value: clock_seconds / SECONDS_PER_DAY,
+            unit: units::lookup("days"),
+        };
+    }
+    let Some((name, size)) = DIFFERENCE_UNITS
+        .into_iter()
+        .find(|(_, size)| seconds.abs() >= *size)
+    else {
+        return Quantity {
+            value: seconds,
+            ..self::seconds()
+        };
+    };
+    Quantity {
+        value: seconds / size,
+        unit: units::lookup(name),
+    }
+}
+
+/// `x in date`, `x in unix`, or a date in a time zone like `now in Asia/Tokyo`.
+/// `None` if it isn't a conversion with dates
+pub fn convert(value: &Value, target: &Node, now: &Zoned) -> Option<Result<Value, String>> {
+    let target_name = match &target.kind {
+        NodeKind::Variable(name) => Some(name.as_str()),
+        _ => None,
+    };
+    Some(match (value, target_name) {
+        (Value::Quantity(seconds), Some("date")) => seconds
+            .plain()
+            .and_then(|seconds| from_unix(seconds, now.time_zone()))
+            .map(Value::Moment),
+        (Value::Quantity(_), Some("unix")) => {
+            Err("Only dates can be converted to unix time".to_string())
+        }
+        (Value::Quantity(_), _) => return None,
+        (Value::Moment(moment), Some("unix")) => {
+            Ok(Value::Quantity(Quantity::number(to_unix(moment))))
+        }
+        (Value::Moment(moment), Some("date")) => Ok(Value::Moment(
+            moment.with_time_zone(now.time_zone().clone()),
+        )),
+        (Value::Moment(moment), _) => zone_name(target)
+            .ok_or_else(|| {
+                "Dates can only be converted to a time zone, like Europe/Paris".to_string()
+            })
+            .and_then(|name| {
+                time_zone(&name, now).ok_or_else(|| format!("Unknown time zone {name}"))
+            })
+            .map(|zone| Value::Moment(moment.with_time_zone(zone))),
+    })
+}
+
+/// Seconds since 1970, which is how computers usually store dates
+pub fn to_unix(moment: &Zoned) -> f64 {
+    moment.timestamp().as_duration().as_secs_f64()
+}
+
+fn from_unix(seconds: f64, time_zone: &TimeZone) -> Result<Zoned, String> {
+    let duration = SignedDuration::try_from_secs_f64(seconds).map_err(|e| e.to_string())?;
+    Timestamp::from_duration(duration)
+        .map(|timestamp| timestamp.to_zoned(time_zone.clone()))
+        .map_err(|e| e.to_string())
+}
+
+/// Names like `Asia/Tokyo` are parsed as division, and `New York` as multiplication
+fn zone_name(node: &Node) -> Option<String> {
+    match &node.kind {
+        NodeKind::Variable(name) => Some(name.clone()),
+        NodeKind::Binary(op, lhs, rhs) => {
+            let separator = match op {
+                BinOp::Divide => "/",
+                BinOp::Multiply => "_",
+                BinOp::Subtract => "-",
+                _ => return None,
+            };
+            Some(format!("{}{separator}{}", zone_name(lhs)?, zone_name(rhs)?))
+        }
+        _ => None,
+    }
+}
+
+/// Any case, and the city on its own works too, like `tokyo` for Asia/Tokyo
+fn time_zone(name: &str, now: &Zoned) -> Option<TimeZone> {
+    match name.to_ascii_lowercase().as_str() {
+        "local" => return Some(now.time_zone().clone()),
+        "utc" | "gmt" => return Some(TimeZone::UTC),
+        _ => {}
+    }
+    if let Ok(zone) = TimeZone::get(name) {
+        return Some(zone);
+    }
+    let city = format!("/{}", name.to_ascii_lowercase());
+    let full_name = tz::db()
+        .available()
+        .find(|zone| zone.as_str().to_ascii_lowercase().ends_with(&city))?;
+    TimeZone::get(full_name.as_str()).ok()
+}
+
+/// Like `Fri 2026-12-25 10:30 AEDT`. Seconds are only shown if there are some
+pub fn text(moment: &Zoned) -> String {
+    let format = match moment.second() == 0 && moment.subsec_nanosecond() == 0 {
+        true => "%a %Y-%m-%d %H:%M %Z",
+        false => "%a %Y-%m-%d %H:%M:%S %Z",
+    };
+    moment.strftime(format).to_string()
+}
@@ -0,0 +1,516 @@
+// Exact mode. Calculates with fractions of big integers, so `0.1 + 0.2` is exactly 0.3 and
+// `2^100` has every digit. Irrational things like `sqrt(2)`, `pi` and `sin(1)` are worked out to
+// a few more digits than are shown, and the answer is marked as not exact.
+// Anything this can't do, like units, is left to the f64 evaluator.
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
+
+use super::Memory;
+use super::format::{self, power_of_ten};
+use super::parse::{BinOp, Node, NodeKind};
+use crate::config::AngleUnit;
+
+/// Extra digits worked out, so rounding errors don't reach the digits shown
+const GUARD_DIGITS: usize = 10;
+/// Bigger powers and factorials take too long, and are left to the f64 evaluator
+const MAX_BITS: u64 = 1 << 16;
+/// Tiny numbers and big angles need more digits. Any more than this take too long, and are left
+/// to the f64 evaluator
+const MAX_DIGITS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Real {
+    pub value: BigRational,
+    /// False if it was rounded on the way
+    pub exact: bool,
+}
+
+impl Real {
+    fn exact(value: BigRational) -> Self {
+        Real { value, exact: true }
+    }
+
+    pub(super) fn integer(value: impl Into<BigInt>) -> Self {
+        Real::exact(BigRational::from_integer(value.into()))
+    }
+}
+
+pub struct Exact<'a> {
+    /// Numbers are read from here, since their f64 isn't exact
+    pub source: &'a str,
+    pub memory: &'a Memory,
+    pub angle_unit: AngleUnit,
+    /// Significant digits that will be shown
+    pub precision: usize,
+}
+
+impl Exact<'_> {
+    /// `None` if it can't be done exactly
+    pub fn evaluate(&self, node: &Node) -> Option<Real> {
+        let eval = |node: &Node| self.evaluate(node);
+
+        Some(match &node.kind {
+            NodeKind::Number(_) => {
+                let text: String = self
+                    .source
+                    .get(node.span.start..node.span.end)?
+                    .chars()
+                    .filter(|c| *c != '_')
+                    .collect();
+                Real::exact(format::decimal(&text)?)
+            }
+            NodeKind::Integer(n) => Real::integer(*n),
+            NodeKind::Variable(name) => self.variable(name)?,
+            NodeKind::Negate(inner) => {
+                let inner = eval(inner)?;
+                Real {
+                    value: -inner.value,
+                    ..inner
+                }
+            }
+            NodeKind::Factorial(inner) => {
+                let inner = eval(inner)?;
+                let n = whole(&inner.value)?.to_u64()?;
+                // Roughly the number of bits in n!
+                if n.saturating_mul(u64::BITS as u64 - n.leading_zeros() as u64) > MAX_BITS {
+                    return None;
+                }
+                Real {
+                    value: BigRational::from_integer((1..=n).map(BigInt::from).product()),
+                    ..inner
+                }
+            }
+            NodeKind::Percent(inner) => {
+                let inner = eval(inner)?;
+                Real {
+                    value: inner.value / BigInt::from(100u8),
+                    ..inner
+                }
+            }
+            NodeKind::AsPercent(part, whole) => {
+                let fraction = self.binary(BinOp::Divide, eval(part)?, eval(whole)?)?;
+                self.binary(BinOp::Multiply, fraction, Real::integer(100))?
+            }
+            // `x + 15%` is x * (1 + 15%)
+            NodeKind::Binary(op @ (BinOp::Add | BinOp::Subtract), lhs, rhs)
+                if matches!(rhs.kind, NodeKind::Percent(_)) =>
+            {
+                let factor = self.binary(*op, Real::integer(1), eval(rhs)?)?;
+                self.binary(BinOp::Multiply, eval(lhs)?, factor)?
+            }
+            NodeKind::Binary(op, lhs, rhs) => self.binary(*op, eval(lhs)?, eval(rhs)?)?,
+            NodeKind::Call(name, args) => {
+                let args = args.iter().map(eval).collect::<Option<Vec<Real>>>()?;
+                self.call(name, args)?
+            }
+            NodeKind::BitNot(_)
+            | NodeKind::Convert(..)
+            | NodeKind::DateTime(_)
+            | NodeKind::Time(_)
+            | NodeKind::Duration(_) => return None,
+        })
+    }
+
+    fn variable(&self, name: &str) -> Option<Real> {
+        let precise = self.precise();
+        let value = match name {
+            "pi" => precise.pi(),
+            "tau" => precise.pi() * 2,
+            "e" => precise.exp(&precise.one)?,
+            "phi" => (&precise.one + precise.sqrt(&(&precise.one * 5))) / 2,
+            _ => {
+                if let Some(real) = self.memory.reals.get(name) {
+                    return Some(real.clone());
+                }
+                // Variables loaded from disk are f64, so are read as the decimal they are shown as
+                let value = self.memory.get(name)?;
+                return Some(Real::exact(format::decimal(&format!("{value:e}"))?));
+            }
+        };
+        Some(precise.real(value))
+    }
+
+    fn binary(&self, op: BinOp, lhs: Real, rhs: Real) -> Option<Real> {
+        let exact = lhs.exact && rhs.exact;
+        let (lhs, rhs) = (lhs.value, rhs.value);
+        let value = match op {
+            BinOp::Add => lhs + rhs,
+            BinOp::Subtract => lhs - rhs,
+            BinOp::Multiply => lhs * rhs,
+            // The f64 evaluator gives infinity
+            BinOp::Divide | BinOp::Modulo if rhs.is_zero() => return None,
+            BinOp::Divide => lhs / rhs,
+            // The same sign as `lhs`, like f64's %
+            BinOp::Modulo => &lhs - &rhs * (&lhs / &rhs).trunc(),
+            BinOp::Power => return self.power(Real { value: lhs, exact }, rhs, exact),
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::ShiftLeft | BinOp::ShiftRight => {
+                return None;
+            }
+        };
+        Some(Real { value, exact })
+    }
+
+    fn power(&self, base: Real, exponent: BigRational, exact: bool) -> Option<Real> {
+        if let Some(exponent) = whole(&exponent) {
+            let bits = base.value.numer().bits().max(base.value.denom().bits());
+            let exponent = exponent.to_i32()?;
+            if bits.saturating_mul(exponent.unsigned_abs() as u64) > MAX_BITS
+                || (base.value.is_zero() && exponent < 0)
+            {
+                return None;
+            }
+            return Some(Real {
+                value: base.value.pow(exponent),
+                exact,
+            });
+        }
+        // Negative numbers to fractional powers aren't real
+        if !base.value.is_positive() {
+            return None;
+        }
+        let precise = self.precise();
+        let log = precise.ln(&base.value)?;
+        let exponent = precise.mul(&log, &precise.fixed(&exponent));
+        self.exp(&BigRational::new(exponent, precise.one.clone()))
+    }
+
+    fn call(&self, name: &str, args: Vec<Real>) -> Option<Real> {
+        let exact = args.iter().all(|arg| arg.exact);
+        match (name, &args[..]) {
+            ("min", [_, ..]) => return args.into_iter().min_by(|a, b| a.value.cmp(&b.value)),
+            ("max", [_, ..]) => return args.into_iter().max_by(|a, b| a.value.cmp(&b.value)),
+            (_, [_]) => {}
+            _ => return None,
+        }
+        let x = &args[0].value;
+        let keep = |value: BigRational| Some(Real { value, exact });
+
+        match name {
+            "abs" => return keep(x.abs()),
+            "floor" => return keep(x.floor()),
+            "ceil" => return keep(x.ceil()),
+            "round" => return keep(x.round()),
+            "sqrt" | "cbrt" => {
+                let n = if name == "sqrt" { 2 } else { 3 };
+                if x.is_negative() && n == 2 {
+                    return None;
+                }
+                if let (Some(numer), Some(denom)) = (root(x.numer(), n), root(x.denom(), n)) {
+                    return keep(BigRational::new(numer, denom));
+                }
+            }
+            _ => {}
+        }
+
+        if name == "exp" {
+            return self.exp(x);
+        }
+        let precise = self.precise_with(leading_zeros(x))?;
+        let fixed = precise.fixed(x);
+        let to_radians = |angle: BigInt| match self.angle_unit {
+            AngleUnit::Radians => angle,
+            AngleUnit::Degrees => angle * precise.pi() / &precise.one / 180,
+        };
+        let from_radians = |angle: BigInt| match self.angle_unit {
+            AngleUnit::Radians => angle,
+            AngleUnit::Degrees => angle * 180 * &precise.one / precise.pi(),
+        };
+        let value = match name {
+            "sqrt" => precise.sqrt(&fixed),
+            "cbrt" => precise.cbrt(&fixed),
+            "ln" => precise.ln(x)?,
+            "log10" => precise.div(
+                &precise.ln(x)?,
+                &precise.ln(&BigRational::from_integer(10.into()))?,
+            ),
+            "log2" => precise.div(&precise.ln(x)?, &precise.ln2()),
+            "sin" => precise.sin(&to_radians(fixed))?,
+            "cos" => precise.sin(&(to_radians(fixed) + precise.pi() / 2))?,
+            "tan" => {
+                let angle = to_radians(fixed);
+                let cos = precise.sin(&(&angle + precise.pi() / 2))?;
+                if cos.is_zero() {
+                    return None;
+                }
+                precise.div(&precise.sin(&angle)?, &cos)
+            }
+            "asin" | "acos" => {
+                if fixed.abs() > precise.one {
+                    return None;
+                }
+                let asin = if fixed.abs() == precise.one {
+                    precise.pi() / 2 * fixed.signum()
+                } else {
+                    let cos = precise.sqrt(&(&precise.one - precise.mul(&fixed, &fixed)));
+                    precise.atan(&precise.div(&fixed, &cos))
+                };
+                match name {
+                    "asin" => from_radians(asin),
+                    _ => from_radians(precise.pi() / 2 - asin),
+                }
+            }
+            "atan" => from_radians(precise.atan(&fixed)),
+            _ => return None,
+        };
+        Some(precise.real(value))
+    }
+
+    fn precise(&self) -> Precise {
+        Precise::new(self.precision + GUARD_DIGITS)
+    }
+
+    /// With `extra` more digits, for tiny numbers. `None` if that is more than `MAX_DIGITS`
+    fn precise_with(&self, extra: usize) -> Option<Precise> {
+        (extra <= MAX_DIGITS).then(|| Precise::new(self.precision + GUARD_DIGITS + extra))
+    }
+
+    /// Tiny answers, like e^-100, get as many significant digits as any other
+    fn exp(&self, x: &BigRational) -> Option<Real> {
+        let zeros = (-x.to_f64()? / std::f64::consts::LN_10).max(0.0).ceil();
+        let precise = self.precise_with(zeros as usize)?;
+        Some(precise.real(precise.exp(&precise.fixed(x))?))
+    }
+}
+
+/// At least how many zeros come straight after the decimal point, like 3 for 0.0005
+fn leading_zeros(value: &BigRational) -> usize {
+    let bits = value.denom().bits().saturating_sub(value.numer().bits());
+    (bits as f64 * std::f64::consts::LOG10_2) as usize
+}
+
+/// The whole number `value` is, if it is one
+fn whole(value: &BigRational) -> Option<BigInt> {
+    value.is_integer().then(|| value.to_integer())
+}
+
+/// The exact nth root, if there is one
+fn root(value: &BigInt, n: u32) -> Option<BigInt> {
+    let root = value.nth_root(n);
+    (&Pow::pow(&root, n) == value).then_some(root)
+}
+
+/// Fixed point maths, where a `BigInt` of `one` means 1
+struct Precise {
+    digits: usize,
+    one: BigInt,
+}
+
+impl Precise {
+    fn new(digits: usize) -> Self {
+        Precise {
+            digits,
+            one: BigInt::from(10u8).pow(digits as u32),
+        }
+    }
+
+    fn fixed(&self, value: &BigRational) -> BigInt {
+        (value * BigRational::from_integer(self.one.clone()))
+            .round()
+            .to_integer()
+    }
+
+    /// Rounded to the digits that are sure to be right
+    fn real(&self, value: BigInt) -> Real {
+        let value = BigRational::new(value, self.one.clone());
+        let value = match value.is_zero() {
+            true => value,
+            false => {
+                let digits = self.digits - GUARD_DIGITS / 2;
+                let (mantissa, exponent) = format::significant(&value, digits);
+                let mantissa = match value.is_negative() {
+                    true => -mantissa,
+                    false => mantissa,
+                };
+                BigRational::from_integer(mantissa) * power_of_ten(exponent + 1 - digits as i32)
+            }
+        };
+        Real {
+            value,
+            exact: false,
+        }
+    }
+
+    fn mul(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        a * b / &self.one
+    }
+
+    fn div(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        a * &self.one / b
+    }
+
+    fn sqrt(&self, x: &BigInt) -> BigInt {
+        (x * &self.one).sqrt()
+    }
+
+    fn cbrt(&self, x: &BigInt) -> BigInt {
+        (x * &self.one * &self.one).cbrt()
+    }
+
+    /// Adds up terms from `next(previous term, n)` until they are too small to matter
+    fn series(&self, first: BigInt, mut next: impl FnMut(&BigInt, u32) -> BigInt) -> BigInt {
+        let mut sum = first.clone();
+        let mut term = first;
+        for n in 1.. {
+            term = next(&term, n);
+            if term.is_zero() {
+                break;
+            }
+            sum += &term;
+        }
+        sum
+    }
+
+    /// Only fast for small `x`
+    fn atan_series(&self, x: &BigInt) -> BigInt {
+        let x_squared = self.mul(x, x);
+        let mut power = x.clone();
+        self.series(x.clone(), |_, n| {
+            power = -self.mul(&power, &x_squared);
+            &power / (2 * n + 1)
+        })
+    }
+
+    fn pi(&self) -> BigInt {
+        // Machin's formula, pi/4 = 4 atan(1/5) - atan(1/239)
+        let fifth = &self.one / 5;
+        let two_hundred_thirty_ninth = &self.one / 239;
+        (self.atan_series(&fifth) * 4 - self.atan_series(&two_hundred_thirty_ninth)) * 4
+    }
+
+    fn ln2(&self) -> BigInt {
+        // 2 atanh(1/3)
+        self.atanh_series(&(&self.one / 3)) * 2
+    }
+
+    fn atanh_series(&self, x: &BigInt) -> BigInt {
+        let x_squared = self.mul(x, x);
+        let mut power = x.clone();
+        self.series(x.clone(), |_, n| {
+            power = self.mul(&power, &x_squared);
+            &power / (2 * n + 1)
+        })
+    }
+
+    fn atan(&self, x: &BigInt) -> BigInt {
+        if x.abs() > self.one {
+            let half_pi = self.pi() / 2 * x.signum();
+            return half_pi - self.atan(&self.div(&self.one, x));
+        }
+        // atan(x) = 2 atan(x / (1 + sqrt(1 + x^2))), which makes x smaller each time
+        let mut x = x.clone();
+        for _ in 0..2 {
+            let hypotenuse = self.sqrt(&(&self.one + self.mul(&x, &x)));
+            x = self.div(&x, &(&self.one + hypotenuse));
+        }
+        self.atan_series(&x) * 4
+    }
+
+    /// `None` if `x` isn't positive
+    fn ln(&self, x: &BigRational) -> Option<BigInt> {
+        if !x.is_positive() {
+            return None;
+        }
+        // x = m * 2^k, with m between 2/3 and 4/3 so the series is quick
+        let mut k = x.numer().bits() as i64 - x.denom().bits() as i64;
+        let two = BigRational::from_integer(2.into());
+        let mut m = x / two.clone().pow(k as i32);
+        let (low, high) = (
+            BigRational::new(2.into(), 3.into()),
+            BigRational::new(4.into(), 3.into()),
+        );
+        while m < low {
+            m *= &two;
+            k -= 1;
+        }
+        while m > high {
+            m /= &two;
+            k += 1;
+        }
+        // ln(m) = 2 atanh((m - 1) / (m + 1))
+        let one = BigRational::one();
+        let z = self.fixed(&((&m - &one) / (&m + &one)));
+        Some(self.atanh_series(&z) * 2 + self.ln2() * k)
+    }
+
+    /// `None` if the answer is too big
+    fn exp(&self, x: &BigInt) -> Option<BigInt> {
+        // e^x = 2^k e^r, with r small
+        let ln2 = self.ln2();
+        let k = BigRational::new(x.clone(), ln2.clone())
+            .round()
+            .to_integer();
+        let r = x - &k * &ln2;
+        let exp_r = self.series(self.one.clone(), |term, n| self.mul(term, &r) / n);
+        if k.is_negative() {
+            // Too small for fixed point
+            return Some(k.abs().to_u64().map_or(BigInt::zero(), |k| exp_r >> k));
+        }
+        let k = k.to_u64().filter(|k| *k <= MAX_BITS)?;
+        Some(exp_r << k)
+    }
+
+    /// `None` if the angle is too big
+    fn sin(&self, x: &BigInt) -> Option<BigInt> {
+        // Bring x between -pi and pi. Big angles need pi to more digits
+        let extra = x.abs().to_string().len().saturating_sub(self.digits);
+        if extra > MAX_DIGITS {
+            return None;
+        }
+        let precise = Precise::new(self.digits + extra);
+        let x = x * BigInt::from(10u8).pow(extra as u32);
+        let tau = precise.pi() * 2;
+        let mut x: BigInt = x % &tau;
+        if x.abs() * 2 > tau {
+            x -= &tau * x.signum();
+        }
+        let x_squared = precise.mul(&x, &x);
+        let sin = precise.series(x.clone(), |term, n| {
+            -precise.mul(term, &x_squared) / (2 * n * (2 * n + 1))
+        });
+        Some(sin / BigInt::from(10u8).pow(extra as u32))
+    }
+}
+
+#[test]
+fn irrational_numbers_are_precise() {
+    let precise = Precise::new(40);
+    let text = |value: BigInt| {
+        let value = precise.real(value).value;
+        format::NumberFormat {
+            precision: 30,
+            thousands_separator: None,
+            scientific_exponent: 30,
+        }
+        .rational(&value)
+    };
+    let two = BigRational::from_integer(2.into());
+
+    assert_eq!(text(precise.pi()), "3.14159265358979323846264338328");
+    assert_eq!(
+        text(precise.sqrt(&(&precise.one * 2))),
+        "1.41421356237309504880168872421"
+    );
+    assert_eq!(
+        text(precise.ln(&two).unwrap()),
+        "0.693147180559945309417232121458"
+    );
+    assert_eq!(
+        text(precise.exp(&precise.one).unwrap()),
+        "2.71828182845904523536028747135"
+    );
+    assert_eq!(
+        text(precise.sin(&precise.one).unwrap()),
+        "0.84147098480789650665250232163"
+    );
+    assert_eq!(
+        text(precise.atan(&precise.one)),
+        "0.78539816339744830961566084582"
+    );
+    assert_eq!(
+        text(precise.exp(&(&precise.one * 100)).unwrap()),
+        "2.68811714181613544841262555158e43"
+    );
+    assert!(precise.ln(&BigRational::zero()).is_none());
+}
@@ -0,0 +1,147 @@
+// Turns answers into text, like `1,234.5` or `1.5e20`.
+// Both exact and f64 answers go through `BigRational`, so they are rounded the same way.
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Pow, Signed, Zero};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NumberFormat {
+    /// Significant digits
+    pub precision: usize,
+    pub thousands_separator: Option<char>,
+    /// Answers of at least 10^this, or at most 10^-this, use scientific notation
+    pub scientific_exponent: i32,
+}
+
+impl NumberFormat {
+    pub fn float(&self, value: f64) -> String {
+        match decimal(&format!("{value:e}")) {
+            Some(value) => self.rational(&value),
+            // Infinity and NaN
+            None => value.to_string(),
+        }
+    }
+
+    pub fn rational(&self, value: &BigRational) -> String {
+        if value.is_zero() {
+            return "0".to_string();
+        }
+        let sign = if value.is_negative() { "-" } else { "" };
+        let (digits, exponent) = significant(value, self.precision.max(1));
+        let digits = digits.to_string();
+        let digits = digits.trim_end_matches('0');
+
+        if exponent >= self.scientific_exponent || exponent <= -self.scientific_exponent {
+            let (first, rest) = digits.split_at(1);
+            return match rest {
+                "" => format!("{sign}{first}e{exponent}"),
+                _ => format!("{sign}{first}.{rest}e{exponent}"),
+            };
+        }
+
+        let (whole, fraction) = if exponent < 0 {
+            let zeros = "0".repeat((-exponent - 1) as usize);
+            ("0".to_string(), format!("{zeros}{digits}"))
+        } else {
+            let point = exponent as usize + 1;
+            if digits.len() > point {
+                (digits[..point].to_string(), digits[point..].to_string())
+            } else {
+                (format!("{digits:0<point$}"), String::new())
+            }
+        };
+        let whole = self.group(&whole);
+        match fraction.as_str() {
+            "" => format!("{sign}{whole}"),
+            _ => format!("{sign}{whole}.{fraction}"),
+        }
+    }
+
+    fn group(&self, whole: &str) -> String {
+        let Some(separator) = self.thousands_separator else {
+            return whole.to_string();
+        };
+        let mut out = String::new();
+        for (i, c) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i).is_multiple_of(3) {
+                out.push(separator);
+            }
+            out.push(c);
+        }
+        out
+    }
+}
+
+/// `|value|` rounded to `precision` digits, and the power of 10 of the first one.
+/// So 1234.5 to 3 digits is (123, 3)
+pub fn significant(value: &BigRational, precision: usize) -> (BigInt, i32) {
+    let value = value.abs();
+    let digit_count = |n: &BigInt| n.to_string().len() as i32;
+    let mut exponent = digit_count(value.numer()) - digit_count(value.denom());
+    if value < power_of_ten(exponent) {
+        exponent -= 1;
+    }
+
+    let digits = (value * power_of_ten(precision as i32 - 1 - exponent))
+        .round()
+        .to_integer();
+    // Rounding up can add a digit, like 9.99 to 10.0
+    if digits.to_string().len() > precision {
+        return (digits / 10, exponent + 1);
+    }
+    (digits, exponent)
+}
+
+pub fn power_of_ten(exponent: i32) -> BigRational {
+    BigRational::from_integer(BigInt::from(10u8)).pow(exponent)
+}
+
+/// Reads a number like `0.1` or `1.5e-7` exactly
+pub fn decimal(text: &str) -> Option<BigRational> {
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().ok()?),
+        None => (text, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{whole}{fraction}");
+    let digits = match digits.strip_prefix('-') {
+        Some(digits) => format!("-{}", digits.trim_start_matches('0')),
+        None => digits.trim_start_matches('0').to_string(),
+    };
+    let numer = match digits.as_str() {
+        "" | "-" => BigInt::zero(),
+        digits => digits.parse::<BigInt>().ok()?,
+    };
+    Some(BigRational::from_integer(numer) * power_of_ten(exponent - fraction.len() as i32))
+}
+
+#[test]
+fn numbers_are_formatted() {
+    let format = NumberFormat {
+        precision: 6,
+        thousands_separator: None,
+        scientific_exponent: 9,
+    };
+    let rational = |text| format.rational(&decimal(text).unwrap());
+    assert_eq!(rational("0.30000000000000004"), "0.3");
+    assert_eq!(rational("1234.5"), "1234.5");
+    assert_eq!(rational("-0.00012345678"), "-0.000123457");
+    assert_eq!(rational("99999.99"), "100000");
+    assert_eq!(rational("1e9"), "1e9");
+    assert_eq!(rational("-1.5e-12"), "-1.5e-12");
+    assert_eq!(
+        format.rational(&BigRational::new(1.into(), 3.into())),
+        "0.333333"
+    );
+    assert_eq!(format.float(0.1 + 0.2), "0.3");
+    assert_eq!(format.float(f64::INFINITY), "inf");
+
+    let format = NumberFormat {
+        thousands_separator: Some(','),
+        ..format
+    };
+    assert_eq!(format.rational(&decimal("1234567").unwrap()), "1,234,570");
+    assert_eq!(format.rational(&decimal("123.25").unwrap()), "123.25");
+    assert_eq!(format.rational(&decimal("-1000").unwrap()), "-1,000");
+}
@@ -1,20 +1,264 @@
-use std::fmt::Display;
+use std::collections::HashMap;
+use std::sync::LazyLock;
 
-use anyhow::bail;
 use iced::{Element, Task, widget};
 use thiserror::Error;
 
-// Some inspiration from here:
-// https://docs.rs/crate/calculator-lib/0.1.1/source/src/lib.rs
+pub mod currency;
+mod dates;
+mod exact;
+mod format;
+mod parse;
+mod programmer;
+mod units;
+
+use exact::{Exact, Real};
+use format::NumberFormat;
+use num_traits::ToPrimitive;
+use parse::{BinOp, Node, NodeKind, SpanError, Spanned, Token};
+use programmer::Programmer;
+use units::Quantity;
 
 use crate::module::{Module, ModuleMessage};
 
+use crate::config::{self, AngleUnit, IntegerWidth};
+use crate::constants;
+use crate::store::Store;
 use crate::widglets;
 
-const BASE: u32 = 10;
+const VARIABLES_RELPATH: &str = "calculator_variables";
+
+static VARIABLES_FILE_PATH: LazyLock<String> =
+    LazyLock::new(|| constants::DATA_DIR.to_owned() + VARIABLES_RELPATH);
+
+/// Names that can't be assigned to. `x` is multiplication, and the rest are for dates
+const RESERVED_NAMES: [&str; 6] = ["ans", "x", "now", "today", "date", "unix"];
+
+const CONSTANTS: [(&str, f64); 4] = [
+    ("pi", std::f64::consts::PI),
+    ("e", std::f64::consts::E),
+    ("tau", std::f64::consts::TAU),
+    // The golden ratio
+    ("phi", 1.618_033_988_749_895),
+];
+
+const FUNCTIONS: [&str; 18] = [
+    "sqrt", "cbrt", "abs", "floor", "ceil", "round", "ln", "log10", "log2", "exp", "sin", "cos",
+    "tan", "asin", "acos", "atan", "min", "max",
+];
+
+#[derive(Debug, Clone)]
+pub enum CalcMsg {
+    /// Enter was pressed. Saves the answer, and any assignment
+    Commit,
+}
+
+/// Variables set by the user, and the previous answer
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Memory {
+    variables: HashMap<String, f64>,
+    ans: f64,
+    /// Exact mode values of the variables and `ans`, so `1/3` then `ans*3` is exactly 1.
+    /// Not saved to disk
+    reals: HashMap<String, Real>,
+}
+
+impl Memory {
+    /// Only reads from disk if `save_variables` is set
+    fn load() -> Self {
+        if !config::SETTINGS
+            .lock()
+            .expect("mutex")
+            .calculator_settings
+            .save_variables
+        {
+            return Memory::default();
+        }
+        let mut variables: HashMap<String, f64> = Store::new(VARIABLES_FILE_PATH.as_str()).load();
+        // Stored with the variables, since it can never be assigned to
+        let ans = variables.remove("ans").unwrap_or_default();
+        Memory {
+            variables,
+            ans,
+            reals: HashMap::new(),
+        }
+    }
+
+    fn save(&self) {
+        if !config::SETTINGS
+            .lock()
+            .expect("mutex")
+            .calculator_settings
+            .save_variables
+        {
+            return;
+        }
+        let res = Store::new(VARIABLES_FILE_PATH.as_str()).update(|map| {
+            *map = self.variables.clone();
+            map.insert("ans".to_string(), self.ans);
+        });
+        if let Err(e) = res {
+            log::error!("Could not save calculator variables. E: {e}");
+        }
+    }
+
+    /// Saves the answer as `ans`, and sets the variable if it was an assignment
+    fn remember(&mut self, answer: &Answer) {
+        let names = std::iter::once("ans").chain(answer.assign_to.as_deref());
+        for name in names {
+            match name {
+                "ans" => self.ans = answer.value,
+                name => {
+                    self.variables.insert(name.to_string(), answer.value);
+                }
+            }
+            match &answer.real {
+                Some(real) => self.reals.insert(name.to_string(), real.clone()),
+                None => self.reals.remove(name),
+            };
+        }
+    }
+
+    /// The exact value of a variable or `ans`, if it is a whole number that fits in an i128
+    fn integer(&self, name: &str) -> Option<i128> {
+        let real = self.reals.get(name).filter(|real| real.exact)?;
+        real.value
+            .is_integer()
+            .then(|| real.value.to_integer().to_i128())?
+    }
+
+    fn get(&self, name: &str) -> Option<f64> {
+        if let Some((_, value)) = CONSTANTS.iter().find(|(constant, _)| *constant == name) {
+            return Some(*value);
+        }
+        match name {
+            "ans" => Some(self.ans),
+            _ => self.variables.get(name).copied(),
+        }
+    }
+}
+
+const PROGRAMMER_ONLY: &str = "Bitwise operators only work in programmer mode";
+
+/// Everything a calculation can use, besides the input
+struct Context<'a> {
+    memory: &'a Memory,
+    angle_unit: AngleUnit,
+    integer_width: IntegerWidth,
+    /// Calculate with fractions where possible
+    exact: bool,
+    /// Significant digits that will be shown
+    precision: usize,
+    /// `%` finds remainders, like `mod`
+    percent_is_modulo: bool,
+    /// Dates and times are in its time zone
+    now: jiff::Zoned,
+    /// `None` if there is no rates file
+    rates: Option<&'a currency::Rates>,
+}
+
+impl<'a> Context<'a> {
+    /// The default settings, without currency rates
+    #[cfg(test)]
+    fn for_test(memory: &'a Memory) -> Self {
+        let settings = config::CalculatorSettings::default();
+        Context {
+            memory,
+            angle_unit: settings.angle_unit,
+            integer_width: settings.integer_width,
+            exact: settings.exact,
+            precision: settings.precision,
+            percent_is_modulo: settings.percent_is_modulo,
+            now: jiff::Zoned::now(),
+            rates: None,
+        }
+    }
+}
+
+/// What evaluating part of the input gives
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Quantity(Quantity),
+    /// A date and time, like `now`
+    Moment(jiff::Zoned),
+}
+
+impl Value {
+    fn quantity(self) -> Result<Quantity, String> {
+        match self {
+            Value::Quantity(quantity) => Ok(quantity),
+            Value::Moment(moment) => Err(format!(
+                "Expected a number, not the date {}",
+                dates::text(&moment)
+            )),
+        }
+    }
+}
+
+/// A calculated result, and the variable it is being assigned to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Answer {
+    assign_to: Option<String>,
+    value: f64,
+    /// Like `km/h`
+    unit: Option<String>,
+    /// When the currency rates used are from
+    rates_date: Option<String>,
+    /// The exact value, in programmer mode
+    integer: Option<i128>,
+    /// The value to more digits than `value` has, in exact mode
+    real: Option<Real>,
+    /// Like `2026-12-25 10:30 AEDT`, if the answer is a date
+    date: Option<String>,
+}
+
+impl Answer {
+    /// The value with its unit, like `5 km`
+    fn value_text(&self, value: String) -> String {
+        match &self.unit {
+            Some(unit) if unit == "%" => format!("{value}%"),
+            Some(unit) => format!("{value} {unit}"),
+            None => value,
+        }
+    }
+
+    /// The value with its unit, or the date
+    fn text(&self, format: &NumberFormat) -> String {
+        match &self.date {
+            Some(date) => date.clone(),
+            None => self.value_text(self.number_text(format)),
+        }
+    }
+
+    fn number_text(&self, format: &NumberFormat) -> String {
+        match &self.real {
+            Some(real) => format.rational(&real.value),
+            None => format.float(self.value),
+        }
+    }
+
+    /// Like `1/3`, if the answer is an exact fraction that had to be rounded to be shown
+    fn fraction_text(&self, format: &NumberFormat) -> Option<String> {
+        let real = self.real.as_ref().filter(|real| real.exact)?;
+        let shown = NumberFormat {
+            thousands_separator: None,
+            ..*format
+        };
+        (format::decimal(&self.number_text(&shown)).as_ref() != Some(&real.value))
+            .then(|| real.value.to_string())
+    }
+}
 
 pub struct Calc {
-    answer: anyhow::Result<f64>,
+    answer: anyhow::Result<Answer>,
+    memory: Memory,
+    angle_unit: AngleUnit,
+    integer_width: IntegerWidth,
+    exact: bool,
+    percent_is_modulo: bool,
+    number_format: NumberFormat,
+    /// Which of the hex, dec, oct and bin rows is copied in programmer mode
+    selected_row: usize,
 }
 
 impl Default for Calc {
@@ -25,7 +269,76 @@ impl Default for Calc {
 
 impl Calc {
     pub fn new() -> Self {
-        Calc { answer: Ok(0.0) }
+        let settings = config::SETTINGS
+            .lock()
+            .expect("mutex")
+            .calculator_settings
+            .clone();
+        Calc {
+            answer: Ok(Answer::default()),
+            memory: Memory::load(),
+            angle_unit: settings.angle_unit,
+            integer_width: settings.integer_width,
+            exact: settings.exact,
+            percent_is_modulo: settings.percent_is_modulo,
+            number_format: NumberFormat {
+                precision: settings.precision,
+                thousands_separator: settings.thousands_separator,
+                scientific_exponent: settings.scientific_exponent,
+            },
+            selected_row: 0,
+        }
+    }
+
+    fn calculate(&self, input: &str) -> anyhow::Result<Answer> {
+        let context = Context {
+            memory: &self.memory,
+            angle_unit: self.angle_unit,
+            integer_width: self.integer_width,
+            exact: self.exact,
+            precision: self.number_format.precision,
+            percent_is_modulo: self.percent_is_modulo,
+            now: jiff::Zoned::now(),
+            rates: currency::RATES.as_ref(),
+        };
+        Self::calculate_in(input, &context)
+    }
+
+    /// Saves the answer as `ans`, and sets the variable if it was an assignment.
+    /// Assignments keep lumin open so more can be typed, otherwise the answer is copied.
+    fn commit(&mut self) -> Task<ModuleMessage> {
+        let Ok(answer) = &self.answer else {
+            return Task::none();
+        };
+        let answer = answer.clone();
+
+        self.memory.remember(&answer);
+        self.memory.save();
+
+        if answer.assign_to.is_some() {
+            return Task::done(ModuleMessage::ResetInput);
+        }
+
+        // Ideally it would save the result to the system clipboard.
+        // However, wayland clipbards are stupid so the clipboard data lives only as long as the
+        // source window for the data. And then since lumin closes right after, it is lost.
+        // Should work if the user has an external clipboard manager however.
+        // Maybe i could build a clipboard manager into lumin, like how macos does it.
+        let text = match answer.integer {
+            Some(value) => programmer::rows(value)[self.selected_row].1.clone(),
+            // Separators would stop it being typed back in
+            None => answer.text(&NumberFormat {
+                thousands_separator: None,
+                ..self.number_format
+            }),
+        };
+        iced::clipboard::write(text)
+            .chain(Task::perform(std::future::ready(()), |_| {
+                // little stupid hack to make it wait for a moment so the clipboard manager can copy it
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                ModuleMessage::DoNothing
+            }))
+            .chain(iced::exit())
     }
 }
 
@@ -34,13 +347,61 @@ impl Module for Calc {
         let font = iced::Font::MONOSPACE;
 
         let widgy = match &self.answer {
-            Ok(num) => widget::container(
-                widglets::heading(
-                    widglets::HeadingLevel::H1,
-                    format!("{:#?}", num.clone()),
-                    None,
-                )
-                .font(font),
+            Ok(Answer {
+                integer: Some(value),
+                assign_to,
+                ..
+            }) => widget::container(
+                widget::column(assign_to.as_ref().map(|name| {
+                    widglets::heading(
+                        widglets::HeadingLevel::H2,
+                        format!("{name} = {value}"),
+                        None,
+                    )
+                    .font(font)
+                    .into()
+                }))
+                .extend(programmer::rows(*value).into_iter().enumerate().map(
+                    |(i, (label, text))| {
+                        widglets::ListRow::new(text)
+                            .subtext(label)
+                            .show_icon(false)
+                            .selected(i == self.selected_row)
+                            .on_activate(ModuleMessage::ActivatedIndex(i))
+                            .into()
+                    },
+                )),
+            ),
+            Ok(answer) => widget::container(
+                widget::column![
+                    widglets::heading(
+                        widglets::HeadingLevel::H1,
+                        match &answer.assign_to {
+                            Some(name) => {
+                                format!("{name} = {}", answer.number_text(&self.number_format))
+                            }
+                            None => answer.text(&self.number_format),
+                        },
+                        None,
+                    )
+                    .font(font)
+                ]
+                .push(answer.fraction_text(&self.number_format).map(|fraction| {
+                    widglets::heading(
+                        widglets::HeadingLevel::Subheading,
+                        format!("= {fraction}"),
+                        None,
+                    )
+                    .font(font)
+                }))
+                .push(answer.rates_date.as_ref().map(|date| {
+                    widglets::heading(
+                        widglets::HeadingLevel::Subheading,
+                        format!("Using currency rates from {date}"),
+                        None,
+                    )
+                }))
+                .align_x(iced::Center),
             )
             .center_x(iced::Fill),
             Err(err) => widget::container(widget::text(err.to_string()).font(font).style(
@@ -54,32 +415,35 @@ impl Module for Calc {
     }
 
     fn update(&mut self, msg: ModuleMessage) -> Task<ModuleMessage> {
-        let ModuleMessage::TextChanged(input) = msg else {
-            return Task::none();
-        };
-
-        let start = std::time::Instant::now();
-        self.answer = Calc::calculate_str(&input);
-        log::debug!("Time to calculate calculator was: {:#?}", start.elapsed());
-        Task::none()
+        match msg {
+            ModuleMessage::TextChanged(input) => {
+                let start = std::time::Instant::now();
+                self.answer = self.calculate(&input);
+                log::debug!("Time to calculate calculator was: {:#?}", start.elapsed());
+                Task::none()
+            }
+            ModuleMessage::CalcMessage(CalcMsg::Commit) => self.commit(),
+            ModuleMessage::SelectionUp => {
+                self.selected_row = self.selected_row.saturating_sub(1);
+                Task::none()
+            }
+            ModuleMessage::SelectionDown => {
+                // Hex, dec, oct and bin
+                self.selected_row = (self.selected_row + 1).min(3);
+                Task::none()
+            }
+            ModuleMessage::ActivatedIndex(i) => {
+                self.selected_row = i;
+                self.commit()
+            }
+            _ => Task::none(),
+        }
     }
 
     fn run(&self) -> Task<crate::message::Message> {
-        // Ideally it would save the result to the system clipboard.
-        // However, wayland clipbards are stupid so the clipboard data lives only as long as the
-        // source window for the data. And then since lumin closes right after, it is lost.
-        // Should work if the user has an external clipboard manager however.
-        // Maybe i could build a clipboard manager into lumin, like how macos does it.
-        // Anyway for now, just do nothing.
-
-        if let Ok(number) = self.answer {
-            iced::clipboard::write(number.to_string()).chain(Task::perform(
-                std::future::ready(()),
-                |_| {
-                    // little stupid hack to make it wait for a moment so the clipboard manager can copy it
-                    std::thread::sleep(std::time::Duration::from_millis(1));
-                    crate::message::Message::Close
-                },
+        if self.answer.is_ok() {
+            Task::done(crate::message::Message::PluginMessage(
+                ModuleMessage::CalcMessage(CalcMsg::Commit),
             ))
         } else {
             Task::none()
@@ -87,439 +451,344 @@ impl Module for Calc {
     }
 }
 
-#[derive(PartialEq, Debug, Clone)]
-enum Expr {
-    Number(f64),
-    Plus,
-    Minus,
-    UnaryMinus,
-    Multiply,
-    Divide,
-    Power,
-    Modulo,
-    Bracket(Vec<Expr>),
-    OpenParen,
-    CloseParen,
-}
-
-impl Display for Expr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&match self {
-            Self::Number(a) => format!("{a}"),
-            Self::Plus => String::from("+"),
-            Self::Minus => String::from("-"),
-            Self::UnaryMinus => String::from("--"),
-            Self::Multiply => String::from("*"),
-            Self::Divide => String::from("÷"),
-            Self::Power => String::from("^"),
-            Self::Modulo => String::from("%"),
-            Self::Bracket(inner) => inner
-                .iter()
-                .map(|expr| format!("{} ", expr))
-                .collect::<Vec<String>>()
-                .concat(),
-            Self::OpenParen => String::from("("),
-            Self::CloseParen => String::from(")"),
-        })
-    }
-}
-
-impl Expr {
-    fn get_number(&self) -> anyhow::Result<f64> {
-        if let Self::Number(num) = self {
-            Ok(*num)
-        } else {
-            bail!(
-                "Expression was not number! this is strange. Notify the developer with your input message pls"
-            )
-        }
+impl Calc {
+    #[cfg(test)]
+    pub fn calculate_str(input: &str) -> anyhow::Result<f64> {
+        Calc::calculate_with(input, &Memory::default(), AngleUnit::Radians)
+            .map(|answer| answer.value)
     }
 
-    fn precedence(&self) -> Option<u8> {
-        match self {
-            Self::UnaryMinus => Some(4),
-            Self::Power => Some(3),
-            Self::Divide | Self::Multiply | Self::Modulo => Some(2),
-            Self::Plus | Self::Minus => Some(1),
-            _ => None,
-        }
+    /// Like `calculate_str`, but can use and assign variables
+    #[cfg(test)]
+    pub fn calculate_with(
+        input: &str,
+        memory: &Memory,
+        angle_unit: AngleUnit,
+    ) -> anyhow::Result<Answer> {
+        let context = Context {
+            angle_unit,
+            rates: currency::RATES.as_ref(),
+            ..Context::for_test(memory)
+        };
+        Self::calculate_in(input, &context)
     }
-}
 
-impl Calc {
-    pub fn calculate_str(input: &str) -> anyhow::Result<f64> {
+    fn calculate_in(input: &str, context: &Context) -> anyhow::Result<Answer> {
         log::trace!("Calculating new input: {input}");
-        let tokens = Calc::tokenize(input)?;
-        let normalised = Calc::parse_unary_minus(tokens);
-        let bracketed = Calc::evaluate_brackets(normalised)?;
-        log::trace!("Bracketing finished");
-        Calc::calc(bracketed)
+        Self::calculate_spanned(input, context).map_err(|e| CalcError::from_span(e, input).into())
     }
 
-    fn tokenize(source: &str) -> anyhow::Result<Vec<Expr>> {
-        // TODO. this function sucks. So ugly and buggy and repeated code
-        log::trace!("raw tokenize input: {source}");
-        let mut out = Vec::new();
-        let chars = source.chars();
-
-        let mut number_buf: String = String::new();
-
-        let mut iter = chars.enumerate().peekable();
-        while let Some((idx, c)) = iter.next() {
-            log::trace!("Character in tokenisation is: {c}");
-            if c == '_' {
-                continue;
+    fn calculate_spanned(input: &str, context: &Context) -> Result<Answer, SpanError> {
+        let mut tokens = parse::tokenize(input)?;
+        if context.percent_is_modulo {
+            for spanned in tokens.iter_mut().filter(|t| t.token == Token::Percent) {
+                spanned.token = Token::Modulo;
             }
+        }
 
-            if c.is_digit(BASE) || c == '.' {
-                log::trace!("Character was num: {c}");
-                number_buf.push(c);
-
-                match iter.peek() {
-                    Some((_, '.')) | Some((_, '0'..='9')) => {
-                        let next = iter.next().expect("Can get next");
-                        log::trace!("Next character ({}) was a num or .", next.1);
-                        number_buf.push(next.1)
-                    }
-                    Some((_, '_')) => {
-                        // do nothing
-                    }
-                    // No number next
-                    _ => {
-                        log::trace!("Got to num next with: {c}");
-                        out.push(Expr::Number(number_buf.parse().map_err(|e| {
-                            CalcError::from_expr_list(
-                                format!("Tokeniser could not parse to number. Error: {e}"),
-                                out.clone(),
-                                idx,
-                            )
-                        })?));
-                        number_buf.clear();
-                    }
+        let (assign_to, tokens) = match &tokens[..] {
+            [
+                Spanned {
+                    token: Token::Ident(name),
+                    span,
+                },
+                Spanned {
+                    token: Token::Assign,
+                    ..
+                },
+                rest @ ..,
+            ] => {
+                if RESERVED_NAMES.contains(&name.as_str())
+                    || FUNCTIONS.contains(&name.as_str())
+                    || CONSTANTS.iter().any(|(constant, _)| constant == name)
+                {
+                    return Err(SpanError::new(
+                        format!("{name} can't be assigned to"),
+                        *span,
+                    ));
                 }
-                continue;
-            }
-
-            if !number_buf.is_empty() {
-                log::trace!("Number buf len was not 0 with {c}");
-                out.push(Expr::Number(
-                    number_buf.parse().expect("can parse to number"),
-                ));
-                number_buf.clear();
+                (Some(name.clone()), rest)
             }
+            _ => (None, &tokens[..]),
+        };
 
-            log::trace!("Got past number_buf check with char: {c}");
-
-            out.push(match c {
-                ' ' => continue,
-                '+' => Expr::Plus,
-                '-' => Expr::Minus,
-                '*' | 'x' => Expr::Multiply,
-                '/' | '÷' => Expr::Divide,
-                '^' => Expr::Power,
-                '%' => Expr::Modulo,
-                '(' => Expr::OpenParen,
-                ')' => Expr::CloseParen,
-                a => {
-                    bail!(CalcError::new(
-                        format!("Unknown token {a}").to_string(),
-                        source.to_string(),
-                        idx
-                    ))
-                }
+        let tree = parse::parse(tokens, input.len())?;
+        log::trace!("Parsed to: {tree}");
+
+        if tokens.iter().any(|spanned| spanned.token.is_programmer()) {
+            let programmer = Programmer {
+                source: input,
+                memory: context.memory,
+                width: context.integer_width,
+            };
+            let value = programmer.evaluate(&tree)?;
+            return Ok(Answer {
+                assign_to,
+                value: value as f64,
+                integer: Some(value),
+                // So big numbers aren't rounded when they are saved
+                real: Some(Real::integer(value)),
+                ..Default::default()
             });
-
-            log::trace!("Output at end of tokn while loop: {out:?}");
-        }
-
-        if !number_buf.is_empty() {
-            log::trace!("Number buf len was not 0 at end of function");
-            out.push(Expr::Number(number_buf.parse().map_err(|e| {
-                CalcError::new(
-                    format!("Tokeniser could not parse to number. Error: {e}"),
-                    number_buf.to_string(),
-                    0,
-                )
-            })?));
-            number_buf.clear();
         }
 
-        Ok(out)
-    }
-
-    fn parse_unary_minus(exprs: Vec<Expr>) -> Vec<Expr> {
-        let mut out = Vec::new();
-        let mut last_was_op = true;
-
-        for e in exprs {
-            match e {
-                // If current is minus, and previous was operator
-                Expr::Minus if last_was_op => out.push(Expr::UnaryMinus),
-                _ => out.push(e),
+        let result = match Self::evaluate(&tree, context)? {
+            Value::Quantity(result) => result,
+            Value::Moment(_) if assign_to.is_some() => {
+                return Err(SpanError::new(
+                    "Only plain numbers can be saved in variables",
+                    tree.span,
+                ));
+            }
+            Value::Moment(moment) => {
+                return Ok(Answer {
+                    value: dates::to_unix(&moment),
+                    date: Some(dates::text(&moment)),
+                    ..Default::default()
+                });
             }
-            last_was_op = matches!(
-                out.last(),
-                Some(
-                    Expr::Minus
-                        | Expr::Multiply
-                        | Expr::Plus
-                        | Expr::Power
-                        | Expr::Modulo
-                        | Expr::Divide
-                        | Expr::OpenParen
-                )
-            );
+        };
+        if assign_to.is_some() && result.unit.is_some() {
+            return Err(SpanError::new(
+                "Only plain numbers can be saved in variables",
+                tree.span,
+            ));
         }
-        out
+        let rates_date = match (&result.unit, context.rates) {
+            (Some(unit), Some(rates)) if unit.is_money() => Some(rates.date.clone()),
+            _ => None,
+        };
+        let real = match (&result.unit, context.exact) {
+            (None, true) => Exact {
+                source: input,
+                memory: context.memory,
+                angle_unit: context.angle_unit,
+                precision: context.precision,
+            }
+            .evaluate(&tree),
+            _ => None,
+        };
+        Ok(Answer {
+            assign_to,
+            value: real
+                .as_ref()
+                .and_then(|real| real.value.to_f64())
+                .unwrap_or(result.value),
+            unit: match &tree.kind {
+                NodeKind::AsPercent(..) => Some("%".to_string()),
+                _ => result.unit.map(|unit| unit.to_string()),
+            },
+            rates_date,
+            integer: None,
+            real,
+            date: None,
+        })
     }
 
-    fn evaluate_brackets(mut input: Vec<Expr>) -> anyhow::Result<Vec<Expr>> {
-        log::trace!("Running evaluate_brackets with input vec: {input:?}");
-        let mut equation_buf: Vec<Expr> = vec![]; // Expr inside a set of bracekts
-        let mut eval_buf: Vec<Expr> = vec![]; // Expr outside brackets
-
-        let mut bracket_counter = 0;
-        let mut bracket_pos: (usize, usize) = (0, 0);
-
-        let mut iter_idx = 0;
-
-        while input.len() > iter_idx {
-            let expr = input[iter_idx].clone();
-            log::trace!("Current expr = {expr:?}");
-
-            match &expr {
-                Expr::OpenParen => {
-                    if bracket_counter == 0 {
-                        bracket_pos.0 = iter_idx;
-                    } else {
-                        equation_buf.push(Expr::OpenParen);
-                    }
-
-                    bracket_counter += 1;
-                }
-                Expr::CloseParen => {
-                    bracket_counter -= 1;
-
-                    if bracket_counter == 0 {
-                        bracket_pos.1 = iter_idx;
-
-                        input.drain(bracket_pos.0..bracket_pos.1);
-
-                        eval_buf.insert(
-                            bracket_pos.0,
-                            Expr::Bracket(Self::evaluate_brackets(equation_buf.clone())?),
-                        );
-                        log::trace!("Finished recursion. Eval_buf: {eval_buf:?}");
-
-                        iter_idx = bracket_pos.0;
-
-                        equation_buf.clear();
-                    } else {
-                        equation_buf.push(Expr::CloseParen);
-                    }
+    fn evaluate(node: &Node, context: &Context) -> Result<Value, SpanError> {
+        let eval = |node: &Node| Self::evaluate(node, context);
+        let number = |node: &Node| {
+            eval(node)?
+                .quantity()
+                .map_err(|e| SpanError::new(e, node.span))
+        };
+        let at_node = |e: String| SpanError::new(e, node.span);
+
+        let quantity = match &node.kind {
+            NodeKind::Number(n) => Quantity::number(*n),
+            NodeKind::Integer(n) => Quantity::number(*n as f64),
+            NodeKind::Duration(seconds) => Quantity {
+                value: *seconds,
+                ..dates::seconds()
+            },
+            NodeKind::DateTime(date) => {
+                return dates::at(&context.now, *date)
+                    .map(Value::Moment)
+                    .map_err(at_node);
+            }
+            NodeKind::Time(time) => {
+                return dates::at(&context.now, context.now.date().to_datetime(*time))
+                    .map(Value::Moment)
+                    .map_err(at_node);
+            }
+            NodeKind::Variable(name) if name == "now" => {
+                return Ok(Value::Moment(context.now.clone()));
+            }
+            NodeKind::Variable(name) if name == "today" => {
+                return dates::at(&context.now, context.now.date().into())
+                    .map(Value::Moment)
+                    .map_err(at_node);
+            }
+            // Variables come first, so a variable called `m` hides metres
+            NodeKind::Variable(name) => match context.memory.get(name) {
+                Some(value) => Quantity::number(value),
+                None => units::lookup(name)
+                    .or_else(|| context.rates.and_then(|rates| rates.lookup(name)))
+                    .map(Quantity::of_unit)
+                    .ok_or_else(|| at_node(format!("Unknown variable or unit {name}")))?,
+            },
+            NodeKind::Negate(inner) => {
+                let inner = number(inner)?;
+                Quantity {
+                    value: -inner.value,
+                    ..inner
                 }
-                _ => {
-                    if bracket_counter > 0 {
-                        equation_buf.push(expr.clone())
-                    } else {
-                        eval_buf.push(expr.clone())
-                    }
+            }
+            // Programmer mode is used whenever these are in the input
+            NodeKind::BitNot(_) => return Err(at_node(PROGRAMMER_ONLY.to_string())),
+            NodeKind::Factorial(inner) => Quantity::number(
+                number(inner)?
+                    .plain()
+                    .and_then(factorial)
+                    .map_err(|e| SpanError::new(e, inner.span))?,
+            ),
+            NodeKind::Percent(inner) => {
+                let inner = number(inner)?;
+                Quantity {
+                    value: inner.value / 100.0,
+                    ..inner
                 }
             }
-            iter_idx += 1;
-        }
-
-        Ok(eval_buf)
-    }
-
-    fn apply_op(input: &mut Vec<Expr>, idx: usize) -> anyhow::Result<()> {
-        let op = input[idx].clone();
-
-        match input[idx] {
-            Expr::UnaryMinus => {
-                let rhs = match input.clone().get(idx + 1).ok_or(CalcError::from_expr_list(
-                    "RHS of UnaryMinus not found".to_string(),
-                    input.clone(),
-                    idx + 1,
-                ))? {
-                    Expr::Bracket(_) => unreachable!("Should not have brackets at this stage"),
-                    Expr::Number(inner) => *inner,
-                    _ => bail!(CalcError::from_expr_list(
-                        String::from("Could not turn RHS of UnaryMinus into number"),
-                        input.clone(),
-                        idx + 1,
-                    )),
+            NodeKind::AsPercent(part, whole) => {
+                let (part, whole) = (number(part)?, number(whole)?);
+                let part = part.value_in(&whole, "compare").map_err(at_node)?;
+                Quantity::number(part / whole.value * 100.0)
+            }
+            // `x + 15%` is x * 1.15
+            NodeKind::Binary(op @ (BinOp::Add | BinOp::Subtract), lhs, rhs)
+                if matches!(rhs.kind, NodeKind::Percent(_)) =>
+            {
+                let change = number(rhs)?
+                    .plain()
+                    .map_err(|e| SpanError::new(e, rhs.span))?;
+                let change = match op {
+                    BinOp::Add => change,
+                    _ => -change,
                 };
-                input.drain(idx..=idx + 1); // Safe since passed above .get()
-                input.insert(idx, Expr::Number(-rhs));
+                number(lhs)?
+                    .mul(Quantity::number(1.0 + change))
+                    .map_err(at_node)?
             }
-            _ => {
-                if idx == 0 {
-                    bail!(CalcError::from_expr_list(
-                        "Operator as first arg isnt allowed".to_string(),
-                        input.clone(),
-                        idx
-                    ))
-                }
-
-                let lhs = match input.clone().get(idx - 1).ok_or(CalcError::from_expr_list(
-                    "LHS of operator not found. This is a strange state. Pls notify developer"
-                        .to_string(),
-                    input.clone(),
-                    idx,
-                ))? {
-                    Expr::Bracket(inner) => Self::calc(inner.clone())?,
-                    Expr::Number(inner) => *inner,
-                    _ => bail!(CalcError::from_expr_list(
-                        String::from(
-                            "LHS Expression could not be turned into number. This is a strange state. Pls notify developer"
-                        ),
-                        input.clone(),
-                        idx - 1
-                    )),
+            NodeKind::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = match (eval(lhs)?, eval(rhs)?) {
+                    (Value::Quantity(lhs), Value::Quantity(rhs)) => (lhs, rhs),
+                    (lhs, rhs) => return dates::combine(*op, lhs, rhs).map_err(at_node),
                 };
-
-                if input.clone().get(idx + 1).is_none() {
-                    input.push(Expr::Number(0.0));
+                match op {
+                    BinOp::Add => lhs.combine(&rhs, "add", |a, b| a + b),
+                    BinOp::Subtract => lhs.combine(&rhs, "subtract", |a, b| a - b),
+                    BinOp::Multiply => lhs.mul(rhs),
+                    BinOp::Divide => lhs.div(rhs),
+                    BinOp::Power => lhs.pow(rhs),
+                    BinOp::Modulo => lhs.combine(&rhs, "find the remainder of", |a, b| a % b),
+                    BinOp::BitAnd
+                    | BinOp::BitOr
+                    | BinOp::BitXor
+                    | BinOp::ShiftLeft
+                    | BinOp::ShiftRight => Err(PROGRAMMER_ONLY.to_string()),
                 }
-
-                let rhs = match input.clone().get(idx + 1).ok_or(CalcError::from_expr_list(
-                    "RHS of operator not found".to_string(),
-                    input.clone(),
-                    idx + 1,
-                ))? {
-                    Expr::Bracket(inner) => Self::calc(inner.clone())?,
-                    Expr::Number(inner) => *inner,
-                    _ => bail!(CalcError::from_expr_list(
-                        String::from("RHS Expression could not be turned into number"),
-                        input.clone(),
-                        idx + 1
-                    )),
-                };
-
-                let val = match op {
-                    Expr::Divide => lhs / rhs,
-                    Expr::Plus => lhs + rhs,
-                    Expr::Minus => lhs - rhs,
-                    Expr::Power => lhs.powf(rhs),
-                    Expr::Modulo => lhs % rhs,
-                    Expr::Multiply => lhs * rhs,
-                    _ => unreachable!("Should have been an operator"),
-                };
-
-                input.drain(idx - 1..=idx + 1);
-                input.insert(idx - 1, Expr::Number(val));
+                .map_err(at_node)?
             }
-        }
-
-        Ok(())
-    }
-
-    fn calc(mut input: Vec<Expr>) -> anyhow::Result<f64> {
-        // Evaluate brackets
-        let mut last_expr: Option<Expr> = None;
-        let mut i = 0;
-        while input.len() > i {
-            if let Expr::Bracket(inner) = &input[i] {
-                let val = Self::calc(inner.clone())?;
-                input[i] = Expr::Number(val);
-
-                if let Some(Expr::Number(_)) = last_expr {
-                    input.insert(i, Expr::Multiply);
-                    i += 1
-                }
+            NodeKind::Call(name, args) => {
+                let args = args.iter().map(number).collect::<Result<Vec<_>, _>>()?;
+                apply_function_with_units(name, &args, context.angle_unit).map_err(at_node)?
             }
-            last_expr = Some(input[i].clone());
-            i += 1
-        }
-
-        log::trace!("Second pass of calc. Input is now: {:?}", input);
-
-        for prec in (1..=4).rev() {
-            log::trace!("for loop entered with prec: {prec}");
-            let mut idx = 0;
-
-            while idx < input.len() {
-                log::trace!("entered while loop. Prec {prec}. idx: {idx}");
-
-                if input[idx].precedence() == Some(prec) {
-                    log::trace!("input before idx: {idx} apply op: {input:?}");
-                    Self::apply_op(&mut input, idx)?;
-                    log::trace!("input AFTER  idx: {idx}apply op: {input:?}");
-                    // Stay on left
-                    idx = idx.saturating_sub(1);
-                    continue;
+            NodeKind::Convert(value, target) => {
+                let value = eval(value)?;
+                // Dates can be converted to time zones, which aren't units
+                if let Some(converted) = dates::convert(&value, target, &context.now) {
+                    return converted.map_err(at_node);
                 }
-                log::trace!(
-                    "Precedence did not match. {:?}, prec: {}",
-                    input[idx].precedence(),
-                    prec
-                );
-                idx += 1;
+                let value = value.quantity().map_err(at_node)?;
+                value.convert(number(target)?).map_err(at_node)?
             }
-        }
-
-        if input.len() != 1 {
-            bail!(CalcError::from_expr_list(
-                "Invalid expression".to_string(),
-                input,
-                0,
-            ))
-        }
-
-        input[0].get_number()
+        };
+        Ok(Value::Quantity(quantity))
     }
 }
 
-#[test]
-fn can_convert_source_to_tokenvec() {
-    assert_eq!(
-        Calc::tokenize("1 + 22-1*2.7 + 12^2").unwrap(),
-        vec![
-            Expr::Number(1.0),
-            Expr::Plus,
-            Expr::Number(22.0),
-            Expr::Minus,
-            Expr::Number(1.0),
-            Expr::Multiply,
-            Expr::Number(2.7),
-            Expr::Plus,
-            Expr::Number(12.0),
-            Expr::Power,
-            Expr::Number(2.0),
-        ]
-    );
-    assert_eq!(
-        Calc::tokenize("1+22").unwrap(),
-        vec![Expr::Number(1.0), Expr::Plus, Expr::Number(22.0),]
-    );
+/// Functions that don't care about units, like `abs` and `max`, keep them.
+/// The rest need plain numbers
+fn apply_function_with_units(
+    name: &str,
+    args: &[Quantity],
+    angle_unit: AngleUnit,
+) -> Result<Quantity, String> {
+    match args.first() {
+        Some(first @ Quantity { unit: Some(_), .. })
+            if matches!(name, "abs" | "floor" | "ceil" | "round" | "min" | "max") =>
+        {
+            let values = args
+                .iter()
+                .map(|arg| arg.value_in(first, "compare"))
+                .collect::<Result<Vec<f64>, String>>()?;
+            Ok(Quantity {
+                value: apply_function(name, &values, angle_unit)?,
+                unit: first.unit.clone(),
+            })
+        }
+        _ => {
+            let values = args
+                .iter()
+                .map(Quantity::plain)
+                .collect::<Result<Vec<f64>, String>>()?;
+            apply_function(name, &values, angle_unit).map(Quantity::number)
+        }
+    }
 }
 
-#[test]
-fn can_parse_unary_minus() {
-    assert_eq!(
-        Calc::parse_unary_minus(vec![Expr::Minus, Expr::Number(1.0)]),
-        vec![Expr::UnaryMinus, Expr::Number(1.0)]
-    );
+fn apply_function(name: &str, args: &[f64], angle_unit: AngleUnit) -> Result<f64, String> {
+    let to_radians = |angle: f64| match angle_unit {
+        AngleUnit::Radians => angle,
+        AngleUnit::Degrees => angle.to_radians(),
+    };
+    let from_radians = |angle: f64| match angle_unit {
+        AngleUnit::Radians => angle,
+        AngleUnit::Degrees => angle.to_degrees(),
+    };
+
+    match name {
+        "min" => return Ok(args.iter().copied().fold(f64::INFINITY, f64::min)),
+        "max" => return Ok(args.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+        _ => {}
+    }
 
-    assert_eq!(
-        Calc::parse_unary_minus(vec![Expr::Power, Expr::Minus, Expr::Number(1.0)]),
-        vec![Expr::Power, Expr::UnaryMinus, Expr::Number(1.0)]
-    );
+    let [x] = args else {
+        return Err(format!("{name} takes 1 argument, not {}", args.len()));
+    };
+    let x = *x;
+    Ok(match name {
+        "sqrt" => x.sqrt(),
+        "cbrt" => x.cbrt(),
+        "abs" => x.abs(),
+        "floor" => x.floor(),
+        "ceil" => x.ceil(),
+        "round" => x.round(),
+        "ln" => x.ln(),
+        "log10" => x.log10(),
+        "log2" => x.log2(),
+        "exp" => x.exp(),
+        "sin" => to_radians(x).sin(),
+        "cos" => to_radians(x).cos(),
+        "tan" => to_radians(x).tan(),
+        "asin" => from_radians(x.asin()),
+        "acos" => from_radians(x.acos()),
+        "atan" => from_radians(x.atan()),
+        _ => return Err(format!("Unknown function {name}")),
+    })
 }
 
-#[test]
-fn can_parse_brackets() {
-    assert_eq!(
-        Calc::evaluate_brackets(Calc::tokenize("((1+4)*2)").unwrap()).unwrap(),
-        vec![Expr::Bracket(vec![
-            Expr::Bracket(vec![Expr::Number(1.0), Expr::Plus, Expr::Number(4.0)]),
-            Expr::Multiply,
-            Expr::Number(2.0)
-        ])]
-    );
-
-    // TODO. Add test for trailing openParen
+fn factorial(n: f64) -> Result<f64, String> {
+    if n < 0.0 || n.fract() != 0.0 {
+        return Err(format!(
+            "Factorial needs a whole number that is 0 or more, not {n}"
+        ));
+    }
+    // Anything bigger doesn't fit in a f64
+    if n > 170.0 {
+        return Ok(f64::INFINITY);
+    }
+    Ok((1..=n as u32).map(f64::from).product())
 }
 
 #[test]
@@ -548,34 +817,46 @@ fn can_do_math() {
     );
     // Unary minus
     assert_eq!(Calc::calculate_str("2^-3").unwrap(), 0.125);
+    // Right associative
+    assert_eq!(Calc::calculate_str("2^3^2").unwrap(), 512.0);
+}
+
+#[test]
+fn errors_point_at_input_column() {
+    let error = |input: &str| Calc::calculate_str(input).unwrap_err().to_string();
+    // A trailing operator used to be treated as `op 0`
+    assert_eq!(
+        error("1 +"),
+        "Calc Error! \nMessage: Expected a number after +\n1 +\n   ^"
+    );
+    assert_eq!(
+        error("2 * unknown"),
+        "Calc Error! \nMessage: Unknown variable or unit unknown\n2 * unknown\n    ^^^^^^^"
+    );
+    // Columns are counted in characters, not bytes
+    assert!(error("6 ÷ 2 # 1").ends_with("\n6 ÷ 2 # 1\n      ^"));
+    assert!(error("2.5! + 1").ends_with("\n^^^"));
 }
 
 #[derive(Debug, Error)]
 struct CalcError {
     message: String,
     equation: String,
+    /// Column of the first character to point at
     error_idx: usize,
+    /// How many characters to point at
+    width: usize,
 }
 
 impl CalcError {
-    fn new(message: String, equation: String, error_idx: usize) -> Self {
+    /// Spans are in bytes, but the error is shown per character
+    fn from_span(error: SpanError, equation: &str) -> Self {
+        let columns = |text: Option<&str>| text.map_or(0, |text| text.chars().count());
         CalcError {
-            message,
-            equation,
-            error_idx,
-        }
-    }
-
-    fn from_expr_list(message: String, equation: Vec<Expr>, error_idx: usize) -> Self {
-        let mut equation_string = String::new();
-
-        for expr in equation {
-            equation_string.push_str(&format!("{} ", expr));
-        }
-        CalcError {
-            message: (message),
-            equation: (equation_string),
-            error_idx: { if error_idx != 0 { error_idx * 2 + 1 } else { 0 } },
+            message: error.message,
+            equation: equation.to_string(),
+            error_idx: columns(equation.get(..error.span.start)),
+            width: columns(equation.get(error.span.start..error.span.end)).max(1),
         }
     }
 }
@@ -583,18 +864,404 @@ impl CalcError {
 impl std::fmt::Display for CalcError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!(
-            "Calc Error! \nMessage: {}\n{}\n{}",
+            "Calc Error! \nMessage: {}\n{}\n{}{}",
             self.message,
             self.equation,
-            {
-                let mut indent = String::new();
-
-                for _ in 0..self.error_idx {
-                    indent.push(' ');
-                }
-                indent.push('^');
-                indent
-            }
+            " ".repeat(self.error_idx),
+            "^".repeat(self.width)
         ))
     }
 }
+
+#[test]
+fn can_use_variables() {
+    let mut memory = Memory::default();
+    memory.variables.insert("r".to_string(), 4.5);
+    memory.variables.insert("my_var2".to_string(), 2.0);
+    memory.ans = 10.0;
+
+    let calc = |input: &str| Calc::calculate_with(input, &memory, AngleUnit::Radians).unwrap();
+    assert_eq!(calc("r * 2").value, 9.0);
+    assert_eq!(calc("2r").value, 9.0);
+    assert_eq!(calc("(1+1)r").value, 9.0);
+    assert_eq!(calc("ans / my_var2").value, 5.0);
+    assert_eq!(calc("2 x 3").value, 6.0);
+    assert_eq!(
+        calc("area = r^2"),
+        Answer {
+            assign_to: Some("area".to_string()),
+            value: 20.25,
+            real: Some(Real {
+                value: num_rational::BigRational::new(81.into(), 4.into()),
+                exact: true,
+            }),
+            ..Default::default()
+        }
+    );
+
+    assert!(Calc::calculate_with("unknown + 1", &memory, AngleUnit::Radians).is_err());
+    assert!(Calc::calculate_with("ans = 2", &memory, AngleUnit::Radians).is_err());
+    assert!(Calc::calculate_with("2 = 2", &memory, AngleUnit::Radians).is_err());
+
+    // Exact answers stay exact when they are saved
+    let mut memory = Memory::default();
+    let calc = |input: &str, memory: &Memory| {
+        Calc::calculate_with(input, memory, AngleUnit::Radians).unwrap()
+    };
+    memory.remember(&calc("third = 1/3", &memory));
+    assert_eq!(calc("third * 3", &memory).value, 1.0);
+    memory.remember(&calc("1/3", &memory));
+    let answer = calc("ans * 3", &memory);
+    assert_eq!(answer.value, 1.0);
+    assert!(answer.real.unwrap().exact);
+}
+
+#[test]
+fn can_use_functions() {
+    assert_eq!(Calc::calculate_str("sqrt(16)").unwrap(), 4.0);
+    assert_eq!(Calc::calculate_str("cbrt(27)").unwrap(), 3.0);
+    assert_eq!(Calc::calculate_str("abs(-3)").unwrap(), 3.0);
+    assert_eq!(Calc::calculate_str("floor(2.7)").unwrap(), 2.0);
+    assert_eq!(Calc::calculate_str("ceil(2.1)").unwrap(), 3.0);
+    assert_eq!(Calc::calculate_str("round(2.5)").unwrap(), 3.0);
+    assert_eq!(Calc::calculate_str("ln(e^2)").unwrap(), 2.0);
+    assert_eq!(Calc::calculate_str("log10(1000)").unwrap(), 3.0);
+    assert_eq!(Calc::calculate_str("log2(8)").unwrap(), 3.0);
+    assert_eq!(Calc::calculate_str("exp(0)").unwrap(), 1.0);
+    assert_eq!(Calc::calculate_str("sin(0)").unwrap(), 0.0);
+    assert_eq!(Calc::calculate_str("cos(0)").unwrap(), 1.0);
+    assert_eq!(Calc::calculate_str("tan(0)").unwrap(), 0.0);
+    assert_eq!(
+        Calc::calculate_str("asin(1)").unwrap(),
+        std::f64::consts::FRAC_PI_2
+    );
+    assert_eq!(Calc::calculate_str("acos(1)").unwrap(), 0.0);
+    assert_eq!(Calc::calculate_str("atan(0)").unwrap(), 0.0);
+    assert_eq!(Calc::calculate_str("min(3, -1, 2)").unwrap(), -1.0);
+    assert_eq!(Calc::calculate_str("max(3, 1+4, 2)").unwrap(), 5.0);
+    assert_eq!(Calc::calculate_str("max(2)").unwrap(), 2.0);
+    // Nested and multiplied
+    assert_eq!(Calc::calculate_str("2sqrt(max(4, 9))").unwrap(), 6.0);
+    assert_eq!(Calc::calculate_str("sqrt(4)(3)").unwrap(), 6.0);
+
+    assert!(Calc::calculate_str("sqrt 4").is_err());
+    assert!(Calc::calculate_str("sqrt(4, 9)").is_err());
+    assert!(Calc::calculate_str("(1, 2)").is_err());
+}
+
+#[test]
+fn can_use_constants_and_factorial() {
+    assert_eq!(Calc::calculate_str("pi").unwrap(), std::f64::consts::PI);
+    assert_eq!(Calc::calculate_str("2pi").unwrap(), std::f64::consts::TAU);
+    assert_eq!(
+        Calc::calculate_str("tau / 2").unwrap(),
+        std::f64::consts::PI
+    );
+    assert_eq!(Calc::calculate_str("e").unwrap(), std::f64::consts::E);
+    assert_eq!(Calc::calculate_str("phi^2 - phi").unwrap().round(), 1.0);
+
+    assert_eq!(Calc::calculate_str("5!").unwrap(), 120.0);
+    assert_eq!(Calc::calculate_str("0!").unwrap(), 1.0);
+    assert_eq!(Calc::calculate_str("-3!").unwrap(), -6.0);
+    assert_eq!(Calc::calculate_str("(1+2)! + 1").unwrap(), 7.0);
+    assert_eq!(Calc::calculate_str("2^3!").unwrap(), 64.0);
+    assert_eq!(Calc::calculate_str("171!").unwrap(), f64::INFINITY);
+    assert!(Calc::calculate_str("2.5!").is_err());
+    assert!(Calc::calculate_str("(-1)!").is_err());
+
+    assert!(Calc::calculate_with("pi = 3", &Memory::default(), AngleUnit::Radians).is_err());
+    assert!(Calc::calculate_with("sqrt = 3", &Memory::default(), AngleUnit::Radians).is_err());
+}
+
+#[test]
+fn can_use_degrees() {
+    let calc = |input: &str| {
+        Calc::calculate_with(input, &Memory::default(), AngleUnit::Degrees)
+            .unwrap()
+            .value
+    };
+    assert!((calc("sin(30)") - 0.5).abs() < 1e-12);
+    assert!((calc("cos(60)") - 0.5).abs() < 1e-12);
+    assert!((calc("tan(45)") - 1.0).abs() < 1e-12);
+    assert!((calc("asin(1)") - 90.0).abs() < 1e-12);
+    assert!((calc("acos(0)") - 90.0).abs() < 1e-12);
+    assert!((calc("atan(1)") - 45.0).abs() < 1e-12);
+}
+
+#[test]
+fn can_convert_units() {
+    let calc = |input: &str| {
+        let answer = Calc::calculate_with(input, &Memory::default(), AngleUnit::Radians).unwrap();
+        (answer.value, answer.unit.unwrap_or_default())
+    };
+    let close = |(value, unit): (f64, String), expected: f64, expected_unit: &str| {
+        assert!(
+            (value - expected).abs() < 1e-9 * expected.abs().max(1.0),
+            "{value} != {expected}"
+        );
+        assert_eq!(unit, expected_unit);
+    };
+
+    close(calc("5 km in mi"), 3.106_855_961_186_669_7, "mi");
+    close(calc("72 F to C"), 22.222_222_222_222, "C");
+    close(calc("-40 °C to °F"), -40.0, "°F");
+    close(calc("0 C in K"), 273.15, "K");
+    close(calc("3.5 GiB in MB"), 3_758.096_384, "MB");
+    close(calc("90 km/h in m/s"), 25.0, "m/s");
+    close(calc("1 km + 500 m"), 1.5, "km");
+    close(calc("1 km + 500 m to cm"), 150_000.0, "cm");
+    close(calc("5 km / 2 h"), 2.5, "km/h");
+    close(calc("3 m * 4 m"), 12.0, "m^2");
+    close(calc("2 kWh in J"), 7.2e6, "J");
+    close(calc("1 L in cm^3"), 1000.0, "cm^3");
+    close(calc("max(1 km, 300 m)"), 1.0, "km");
+    // Units that cancel out are plain numbers
+    close(calc("1 km / 1 m"), 1000.0, "");
+    close(calc("sqrt(16 m/m)"), 4.0, "");
+
+    assert!(Calc::calculate_str("5 km in s").is_err());
+    assert!(Calc::calculate_str("5 km + 2 s").is_err());
+    assert!(Calc::calculate_str("5 km in 2 m").is_err());
+    assert!(Calc::calculate_str("sqrt(4 m)").is_err());
+    assert!(Calc::calculate_str("2 ^ (1 m)").is_err());
+    assert!(Calc::calculate_with("d = 5 km", &Memory::default(), AngleUnit::Radians).is_err());
+}
+
+#[test]
+fn can_convert_currency() {
+    let rates =
+        currency::Rates::from_csv("base,EUR\ndate,2024-05-01\nUSD,1.25\nGBP,0.8\n").unwrap();
+    let memory = Memory::default();
+    let context = Context {
+        exact: false,
+        rates: Some(&rates),
+        ..Context::for_test(&memory)
+    };
+    let calc = |input: &str| Calc::calculate_spanned(input, &context).unwrap();
+
+    let answer = calc("100 USD in EUR");
+    assert_eq!(answer.value, 80.0);
+    assert_eq!(answer.unit.as_deref(), Some("EUR"));
+    assert_eq!(answer.rates_date.as_deref(), Some("2024-05-01"));
+    assert_eq!(calc("10 gbp to usd").value, 15.625);
+    assert_eq!(calc("20 EUR/h * 8 h").value, 160.0);
+    // Cancels out, so the rates don't matter
+    assert_eq!(calc("10 USD / 5 USD").rates_date, None);
+    assert_eq!(calc("5 km").rates_date, None);
+
+    assert!(Calc::calculate_spanned("100 USD in km", &context).is_err());
+    assert!(Calc::calculate_spanned("100 XYZ", &context).is_err());
+}
+
+#[test]
+fn can_use_programmer_mode() {
+    let memory = Memory::default();
+    let calc_as = |input: &str, integer_width| {
+        let context = Context {
+            integer_width,
+            exact: false,
+            ..Context::for_test(&memory)
+        };
+        Calc::calculate_spanned(input, &context).map(|answer| answer.integer.unwrap())
+    };
+    let calc = |input: &str| calc_as(input, IntegerWidth::I128).unwrap();
+
+    assert_eq!(calc("0xff"), 255);
+    assert_eq!(calc("0b1010 | 0b0101"), 15);
+    assert_eq!(calc("0xf0 & 0x3c"), 0x30);
+    assert_eq!(calc("6 ^^ 3"), 5);
+    assert_eq!(calc("~0"), -1);
+    assert_eq!(calc("1 << 3 + 1"), 16);
+    assert_eq!(calc("256 >> 4"), 16);
+    assert_eq!(calc("0x2^3^2"), 512);
+    // Whole number division
+    assert_eq!(calc("0x7 / 2"), 3);
+    assert_eq!(calc("-0x7 mod 2"), -1);
+    assert_eq!(calc("0x5!"), 120);
+    // Exact, where a f64 would round
+    assert_eq!(calc("0x1 << 100 | 1"), (1 << 100) | 1);
+    assert_eq!(calc("18446744073709551615 | 0"), i128::from(u64::MAX));
+    assert_eq!(calc("1 << 127"), i128::MIN);
+
+    let calc_u64 = |input: &str| calc_as(input, IntegerWidth::U64).unwrap();
+    assert_eq!(calc_u64("~0"), i128::from(u64::MAX));
+    assert_eq!(calc_u64("0x0 - 1"), i128::from(u64::MAX));
+    assert_eq!(calc_u64("0xffffffffffffffff + 1"), 0);
+    assert_eq!(calc_u64("0x1 << 63"), 1 << 63);
+    assert_eq!(calc_u64("100! & 0xff"), 0);
+    assert!(calc_as("1 << 64", IntegerWidth::U64).is_err());
+
+    assert!(calc_as("1.5 | 1", IntegerWidth::I128).is_err());
+    assert!(calc_as("0x1 / 0", IntegerWidth::I128).is_err());
+    assert!(calc_as("2 km & 1", IntegerWidth::I128).is_err());
+    assert!(calc_as("sqrt(0x4)", IntegerWidth::I128).is_err());
+    assert!(calc_as("0x2 ^ -1", IntegerWidth::I128).is_err());
+
+    // Saved exactly, even when a f64 would round
+    let mut memory = Memory::default();
+    let calc_in = |input: &str, memory: &Memory| {
+        Calc::calculate_spanned(input, &Context::for_test(memory)).unwrap()
+    };
+    memory.remember(&calc_in("big = 1 << 100", &memory));
+    assert_eq!(calc_in("big | 1", &memory).integer, Some((1 << 100) | 1));
+    memory.remember(&calc_in("big + 0x1", &memory));
+    assert_eq!(calc_in("ans ^^ big", &memory).integer, Some(1));
+    // Without any of them, it is normal maths
+    assert_eq!(Calc::calculate_str("7 / 2").unwrap(), 3.5);
+}
+
+#[test]
+fn can_use_exact_mode() {
+    let format = NumberFormat {
+        precision: 20,
+        thousands_separator: None,
+        scientific_exponent: 25,
+    };
+    let calc_in = |input: &str, angle_unit| {
+        Calc::calculate_with(input, &Memory::default(), angle_unit).unwrap()
+    };
+    let calc = |input: &str| calc_in(input, AngleUnit::Radians);
+    let text = |input: &str| calc(input).number_text(&format);
+    let exact = |input: &str| calc(input).real.unwrap().exact;
+
+    assert_eq!(text("0.1 + 0.2"), "0.3");
+    assert_eq!(calc("0.1 + 0.2").value, 0.3);
+    assert_eq!(text("2^64"), "18446744073709551616");
+    assert_eq!(text("20!"), "2432902008176640000");
+    assert_eq!(text("1/3"), "0.33333333333333333333");
+    assert_eq!(calc("1/3").fraction_text(&format).unwrap(), "1/3");
+    assert_eq!(calc("1/4").fraction_text(&format), None);
+    assert_eq!(text("-7 mod 3"), "-1");
+    assert_eq!(text("sqrt(16/9)"), "1.3333333333333333333");
+    assert!(exact("sqrt(16/9)"));
+
+    // Irrational answers are worked out to more digits than f64 has
+    assert_eq!(text("sqrt(2)"), "1.4142135623730950488");
+    assert!(!exact("sqrt(2)"));
+    assert_eq!(text("pi"), "3.1415926535897932385");
+    assert_eq!(text("2^0.5"), "1.4142135623730950488");
+    assert_eq!(text("ln(e^2)"), "2");
+    assert_eq!(text("log10(1000)"), "3");
+    assert_eq!(text("cos(pi)"), "-1");
+    // Tiny answers aren't rounded to 0
+    assert_eq!(
+        text("sqrt(2/10^40)"),
+        "0.000000000000000000014142135623730950488"
+    );
+    assert_eq!(text("exp(-100)"), "3.720075976020835963e-44");
+    assert_eq!(text("sin(10^-35)"), "1e-35");
+    assert_eq!(text("(2/10^40)^0.5"), text("sqrt(2/10^40)"));
+    assert_eq!(
+        calc_in("sin(30)", AngleUnit::Degrees).number_text(&format),
+        "0.5"
+    );
+    assert_eq!(
+        calc_in("atan(1)", AngleUnit::Degrees).number_text(&format),
+        "45"
+    );
+
+    // Left to the f64 maths
+    assert_eq!(calc("1/0").value, f64::INFINITY);
+    assert_eq!(calc("1/0").real, None);
+    assert_eq!(calc("(-8)^(1/3)").real, None);
+    assert_eq!(calc("2^100000").real, None);
+    assert_eq!(calc("sin(10^12000)").real, None);
+    assert_eq!(calc("2 km").real, None);
+}
+
+#[test]
+fn can_use_percentages() {
+    let calc = |input: &str| Calc::calculate_str(input).unwrap();
+    assert_eq!(calc("200 + 15%"), 230.0);
+    assert_eq!(calc("200 - 15%"), 170.0);
+    assert_eq!(calc("20% of 85"), 17.0);
+    assert_eq!(calc("15%"), 0.15);
+    assert_eq!(calc("200 * 15%"), 30.0);
+    assert_eq!(calc("7 mod 3"), 1.0);
+    assert_eq!(calc("(100 + 10%) - 10%"), 99.0);
+
+    let answer =
+        Calc::calculate_with("17 as % of 85", &Memory::default(), AngleUnit::Radians).unwrap();
+    assert_eq!(answer.value, 20.0);
+    assert_eq!(answer.value_text("20".to_string()), "20%");
+    assert_eq!(calc("500 m as % of 2 km"), 25.0);
+    assert!(Calc::calculate_str("5 km as % of 2 s").is_err());
+    assert!(Calc::calculate_str("7 % 3").is_err());
+
+    let memory = Memory::default();
+    let context = Context {
+        percent_is_modulo: true,
+        ..Context::for_test(&memory)
+    };
+    let old = |input: &str| Calc::calculate_spanned(input, &context).unwrap().value;
+    assert_eq!(old("7 % 3"), 1.0);
+    assert_eq!(old("0x7 % 3"), 1.0);
+}
+
+#[test]
+fn can_use_dates() {
+    let memory = Memory::default();
+    let context = Context {
+        now: "2026-10-19T09:30[Europe/London]".parse().unwrap(),
+        ..Context::for_test(&memory)
+    };
+    let calc = |input: &str| Calc::calculate_spanned(input, &context);
+    let date = |input: &str| calc(input).unwrap().date.unwrap();
+    let quantity = |input: &str| {
+        let answer = calc(input).unwrap();
+        (answer.value, answer.unit.unwrap_or_default())
+    };
+
+    assert_eq!(date("now"), "Mon 2026-10-19 09:30 BST");
+    assert_eq!(date("now + 3 days"), "Thu 2026-10-22 09:30 BST");
+    // Daylight saving ends on the 25th
+    assert_eq!(date("now + 1 week"), "Mon 2026-10-26 09:30 GMT");
+    assert_eq!(date("now - 1.5 h"), "Mon 2026-10-19 08:00 BST");
+    assert_eq!(date("2026-10-25T00:00 + 1 day"), "Mon 2026-10-26 00:00 GMT");
+    assert_eq!(date("2026-10-25T00:00 + 24 h"), "Sun 2026-10-25 23:00 GMT");
+    assert_eq!(
+        date("2026-10-25T00:00 + 86400 s"),
+        "Sun 2026-10-25 23:00 GMT"
+    );
+    assert_eq!(date("10:30 + 2h45m"), "Mon 2026-10-19 13:15 BST");
+    assert_eq!(date("10:30:15"), "Mon 2026-10-19 10:30:15 BST");
+    assert_eq!(date("2026-12-25"), "Fri 2026-12-25 00:00 GMT");
+    assert_eq!(date("1700000000 to date"), "Tue 2023-11-14 22:13:20 GMT");
+
+    assert_eq!(quantity("2026-12-25 - today"), (67.0, "days".to_string()));
+    assert_eq!(
+        quantity("2026-12-25T18:00 - 2026-12-25 12:00"),
+        (6.0, "hours".to_string())
+    );
+    assert_eq!(
+        quantity("2026-10-25T03:00 - 2026-10-25T00:00"),
+        (4.0, "hours".to_string())
+    );
+    assert_eq!(
+        quantity("2026-10-26 - 2026-10-25"),
+        (1.0, "days".to_string())
+    );
+    assert_eq!(
+        quantity("(2026-12-28 - today) in weeks"),
+        (10.0, "weeks".to_string())
+    );
+    assert_eq!(quantity("90 min in hours"), (1.5, "hours".to_string()));
+    assert_eq!(quantity("2h45m in min"), (165.0, "min".to_string()));
+    assert_eq!(quantity("now to unix"), (1_792_398_600.0, String::new()));
+
+    // Time zones come from /usr/share/zoneinfo
+    assert_eq!(date("now in Asia/Tokyo"), "Mon 2026-10-19 17:30 JST");
+    assert_eq!(date("now in tokyo"), "Mon 2026-10-19 17:30 JST");
+    assert_eq!(date("now in New York"), "Mon 2026-10-19 04:30 EDT");
+    assert_eq!(date("now in utc"), "Mon 2026-10-19 08:30 UTC");
+    assert_eq!(date("(now in utc) to date"), "Mon 2026-10-19 09:30 BST");
+
+    assert!(calc("now + 5").is_err());
+    assert!(calc("now * 2").is_err());
+    assert!(calc("2026-13-01").is_err());
+    assert!(calc("25:00").is_err());
+    assert!(calc("now in Nowhere").is_err());
+    assert!(calc("5 km to date").is_err());
+    assert!(calc("d = now").is_err());
+    assert!(calc("now = 5").is_err());
+}
@@ -0,0 +1,973 @@
+// Turns calculator input into a tree of `Node`s, using a Pratt parser.
+// Tokens and nodes remember which bytes of the input they came from, so errors can point at them.
+// https://matklad.github.io/2020/04/13/simple-but-powerful-pratt-parsing.html
+
+use std::fmt::Display;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use jiff::civil;
+
+use super::{FUNCTIONS, units};
+
+/// Byte range in the input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Covers both spans, and everything between them
+    fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+/// Something wrong with the input, and where it is
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl SpanError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        SpanError {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Number(f64),
+    /// Written like `0xff`, `0b101` or `0o17`
+    Integer(i128),
+    /// `2026-12-25`, or `2026-12-25 10:30` with a time
+    DateTime(civil::DateTime),
+    /// `10:30`, which is today
+    Time(civil::Time),
+    /// In seconds, written like `2h45m`
+    Duration(f64),
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Power,
+    /// `mod`, or `%` if `percent_is_modulo` is set
+    Modulo,
+    /// `%`, like `200 + 15%`
+    Percent,
+    /// `of`, like `20% of 85`. Multiplies
+    Of,
+    /// `as`, like `17 as % of 85`
+    As,
+    OpenParen,
+    CloseParen,
+    /// Variable, constant or function name
+    Ident(String),
+    Assign,
+    Comma,
+    Factorial,
+    /// `in` or `to`, like `5 km in mi`
+    Convert,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl Token {
+    /// Tokens that only work on whole numbers, so they turn on programmer mode
+    pub fn is_programmer(&self) -> bool {
+        matches!(
+            self,
+            Self::Integer(_)
+                | Self::BitAnd
+                | Self::BitOr
+                | Self::BitXor
+                | Self::BitNot
+                | Self::ShiftLeft
+                | Self::ShiftRight
+        )
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Integer(n) => write!(f, "{n}"),
+            Self::DateTime(date) => write!(f, "{date}"),
+            Self::Time(time) => write!(f, "{time}"),
+            Self::Duration(seconds) => write!(f, "{seconds}s"),
+            Self::Ident(name) => f.write_str(name),
+            Self::Plus => f.write_str("+"),
+            Self::Minus => f.write_str("-"),
+            Self::Multiply => f.write_str("*"),
+            Self::Divide => f.write_str("/"),
+            Self::Power => f.write_str("^"),
+            Self::Modulo => f.write_str("mod"),
+            Self::Percent => f.write_str("%"),
+            Self::Of => f.write_str("of"),
+            Self::As => f.write_str("as"),
+            Self::OpenParen => f.write_str("("),
+            Self::CloseParen => f.write_str(")"),
+            Self::Assign => f.write_str("="),
+            Self::Comma => f.write_str(","),
+            Self::Factorial => f.write_str("!"),
+            Self::Convert => f.write_str("in"),
+            Self::BitAnd => f.write_str("&"),
+            Self::BitOr => f.write_str("|"),
+            Self::BitXor => f.write_str("^^"),
+            Self::BitNot => f.write_str("~"),
+            Self::ShiftLeft => f.write_str("<<"),
+            Self::ShiftRight => f.write_str(">>"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    pub span: Span,
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Spanned>, SpanError> {
+    let mut out = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        // `_` can be used to split up long numbers, like 1_000_000
+        if c.is_whitespace() || c == '_' {
+            continue;
+        }
+
+        let mut end = start + c.len_utf8();
+
+        let token = if c == '0'
+            && let Some(&(_, prefix)) = chars.peek()
+            && let Some(radix) = radix(prefix)
+        {
+            chars.next();
+            end = take_while(&mut chars, start + 2, |c| {
+                c.is_ascii_alphanumeric() || c == '_'
+            });
+            let digits: String = source[start + 2..end]
+                .chars()
+                .filter(|c| *c != '_')
+                .collect();
+            let value = i128::from_str_radix(&digits, radix).map_err(|_| {
+                SpanError::new(
+                    format!("{} is not a number", &source[start..end]),
+                    Span { start, end },
+                )
+            })?;
+            Token::Integer(value)
+        } else if c.is_ascii_digit()
+            && let Some((token, len)) = date_or_time(&source[start..])
+        {
+            end = start + len;
+            while chars.next_if(|(i, _)| *i < end).is_some() {}
+            token.map_err(|e| SpanError::new(e, Span { start, end }))?
+        } else if c.is_ascii_digit() || c == '.' {
+            end = take_while(&mut chars, end, |c| {
+                c.is_ascii_digit() || c == '.' || c == '_'
+            });
+            let text: String = source[start..end].chars().filter(|c| *c != '_').collect();
+            let number = text.parse().map_err(|_| {
+                SpanError::new(format!("{text} is not a number"), Span { start, end })
+            })?;
+            Token::Number(number)
+        } else if c == 'x'
+            && chars
+                .peek()
+                .is_some_and(|(_, next)| next.is_ascii_digit() || *next == '(')
+        {
+            // `2x3` and `2x(1+2)`, without spaces
+            Token::Multiply
+        } else if c.is_alphabetic() || c == '°' {
+            end = take_while(&mut chars, end, |c| c.is_alphanumeric() || c == '_');
+            match &source[start..end] {
+                "x" => Token::Multiply,
+                "in" | "to" => Token::Convert,
+                "mod" => Token::Modulo,
+                "of" => Token::Of,
+                "as" => Token::As,
+                name => Token::Ident(name.to_string()),
+            }
+        } else if let Some(token) = doubled_operator(c)
+            && chars.next_if(|(_, next)| *next == c).is_some()
+        {
+            end += c.len_utf8();
+            token
+        } else {
+            match c {
+                '+' => Token::Plus,
+                '-' => Token::Minus,
+                '*' => Token::Multiply,
+                '/' | '÷' => Token::Divide,
+                '^' => Token::Power,
+                '%' => Token::Percent,
+                '(' => Token::OpenParen,
+                ')' => Token::CloseParen,
+                '=' => Token::Assign,
+                ',' => Token::Comma,
+                '!' => Token::Factorial,
+                '&' => Token::BitAnd,
+                '|' => Token::BitOr,
+                '~' => Token::BitNot,
+                _ => {
+                    return Err(SpanError::new(
+                        format!("Unknown token {c}"),
+                        Span { start, end },
+                    ));
+                }
+            }
+        };
+        out.push(Spanned {
+            token,
+            span: Span { start, end },
+        });
+    }
+
+    Ok(out)
+}
+
+/// Returns the end of the last character taken
+fn take_while(chars: &mut Peekable<CharIndices>, mut end: usize, keep: fn(char) -> bool) -> usize {
+    while let Some(&(idx, next)) = chars.peek()
+        && keep(next)
+    {
+        end = idx + next.len_utf8();
+        chars.next();
+    }
+    end
+}
+
+/// For `0x`, `0b` and `0o` literals
+fn radix(prefix: char) -> Option<u32> {
+    match prefix {
+        'x' => Some(16),
+        'b' => Some(2),
+        'o' => Some(8),
+        _ => None,
+    }
+}
+
+/// Dates like `2026-12-25` or `2026-12-25T10:30`, times like `10:30`, and durations like `2h45m`.
+/// Returns the token and how many bytes it is, or `None` if `text` doesn't start with one
+fn date_or_time(text: &str) -> Option<(Result<Token, String>, usize)> {
+    let digits = |from: usize| {
+        text.get(from..).map_or(0, |rest| {
+            rest.bytes().take_while(u8::is_ascii_digit).count()
+        })
+    };
+    let is_date = digits(0) == 4
+        && text.as_bytes().get(4) == Some(&b'-')
+        && digits(5) == 2
+        && text.as_bytes().get(7) == Some(&b'-')
+        && digits(8) == 2;
+
+    if is_date {
+        let (time, len) = match text[10..].strip_prefix(['T', ' ']).and_then(clock) {
+            Some((time, time_len)) => (Some(time), 11 + time_len),
+            None => (None, 10),
+        };
+        let part = |range: std::ops::Range<usize>| text[range].parse().unwrap_or_default();
+        let date = civil::Date::new(
+            text[..4].parse().unwrap_or_default(),
+            part(5..7),
+            part(8..10),
+        )
+        .map_err(|_| format!("{} is not a date", &text[..10]));
+        let token = match (date, time.unwrap_or(Ok(civil::Time::midnight()))) {
+            (Ok(date), Ok(time)) => Ok(Token::DateTime(date.to_datetime(time))),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+        return Some((token, len));
+    }
+    if let Some((time, len)) = clock(text) {
+        return Some((time.map(Token::Time), len));
+    }
+    compound_duration(text).map(|(seconds, len)| (Ok(Token::Duration(seconds)), len))
+}
+
+/// `10:30` or `10:30:15`, and how many bytes it is
+fn clock(text: &str) -> Option<(Result<civil::Time, String>, usize)> {
+    let digits = |from: usize| {
+        text.get(from..).map_or(0, |rest| {
+            rest.bytes().take_while(u8::is_ascii_digit).count()
+        })
+    };
+    let hour_len = digits(0);
+    if !(1..=2).contains(&hour_len)
+        || text.as_bytes().get(hour_len) != Some(&b':')
+        || digits(hour_len + 1) != 2
+    {
+        return None;
+    }
+    let mut len = hour_len + 3;
+    if text.as_bytes().get(len) == Some(&b':') && digits(len + 1) == 2 {
+        len += 3;
+    }
+    let mut parts = text[..len]
+        .split(':')
+        .map(|part| part.parse().unwrap_or_default());
+    let mut part = || parts.next().unwrap_or_default();
+    let time = civil::Time::new(part(), part(), part(), 0)
+        .map_err(|_| format!("{} is not a time", &text[..len]));
+    Some((time, len))
+}
+
+/// Like `2h45m` or `1d12h`, with at least 2 parts. `m` is minutes here, not metres
+fn compound_duration(text: &str) -> Option<(f64, usize)> {
+    let mut seconds = 0.0;
+    let mut len = 0;
+    let mut parts = 0;
+    loop {
+        let rest = &text[len..];
+        let number_len = rest
+            .bytes()
+            .take_while(|b| b.is_ascii_digit() || *b == b'.')
+            .count();
+        let size = match rest.as_bytes().get(number_len) {
+            Some(b'd') => 86_400.0,
+            Some(b'h') => 3_600.0,
+            Some(b'm') => 60.0,
+            Some(b's') => 1.0,
+            _ => break,
+        };
+        // `5min` is a number and a unit
+        let after = &rest[number_len + 1..];
+        if number_len == 0 || after.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            break;
+        }
+        seconds += rest[..number_len].parse::<f64>().ok()? * size;
+        len += number_len + 1;
+        parts += 1;
+    }
+    (parts >= 2).then_some((seconds, len))
+}
+
+/// Operators written with two of the same character. `^^` is xor, since `^` is power
+fn doubled_operator(c: char) -> Option<Token> {
+    match c {
+        '^' => Some(Token::BitXor),
+        '<' => Some(Token::ShiftLeft),
+        '>' => Some(Token::ShiftRight),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Modulo,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl BinOp {
+    fn from_token(token: &Token) -> Option<Self> {
+        Some(match token {
+            Token::Plus => Self::Add,
+            Token::Minus => Self::Subtract,
+            Token::Multiply | Token::Of => Self::Multiply,
+            Token::Divide => Self::Divide,
+            Token::Power => Self::Power,
+            Token::Modulo => Self::Modulo,
+            Token::BitAnd => Self::BitAnd,
+            Token::BitOr => Self::BitOr,
+            Token::BitXor => Self::BitXor,
+            Token::ShiftLeft => Self::ShiftLeft,
+            Token::ShiftRight => Self::ShiftRight,
+            _ => return None,
+        })
+    }
+
+    /// How tightly the operator holds onto the things to its left and right.
+    /// Right being lower makes it right associative, so `2^3^2` is `2^(3^2)`.
+    /// Bitwise operators are looser than maths, like in C, so `1 << 2 + 1` is `1 << 3`
+    fn binding_power(self) -> (u8, u8) {
+        match self {
+            Self::BitOr => (2, 3),
+            Self::BitXor => (4, 5),
+            Self::BitAnd => (6, 7),
+            Self::ShiftLeft | Self::ShiftRight => (8, 9),
+            Self::Add | Self::Subtract => (10, 11),
+            Self::Multiply | Self::Divide | Self::Modulo => (12, 13),
+            Self::Power => (15, 14),
+        }
+    }
+}
+
+impl Display for BinOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Add => "+",
+            Self::Subtract => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+            Self::Power => "^",
+            Self::Modulo => "mod",
+            Self::BitAnd => "&",
+            Self::BitOr => "|",
+            Self::BitXor => "^^",
+            Self::ShiftLeft => "<<",
+            Self::ShiftRight => ">>",
+        })
+    }
+}
+
+/// For things like `2r` and `5 km`. Tighter than `*` and `/`, so `5 km / 2 h` is
+/// `(5 km) / (2 h)`, but looser than `^`
+const IMPLICIT_MULTIPLY_BINDING_POWER: (u8, u8) = (13, 14);
+/// Looser than everything, so `1 km + 2 m in cm` converts the sum
+const CONVERT_BINDING_POWER: (u8, u8) = (0, 1);
+/// Binds tighter than `^`, so `-2^2` is 4. Also used for `~`
+const NEGATE_BINDING_POWER: u8 = 16;
+/// Binds tighter than negation, so `-3!` is -6. Also used for `%`
+const FACTORIAL_BINDING_POWER: u8 = 18;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Number(f64),
+    Integer(i128),
+    DateTime(civil::DateTime),
+    Time(civil::Time),
+    /// In seconds
+    Duration(f64),
+    Variable(String),
+    Negate(Box<Node>),
+    /// `~`, flips every bit
+    BitNot(Box<Node>),
+    Factorial(Box<Node>),
+    /// `15%`, which is 0.15. `x + 15%` and `x - 15%` add or take away 15% of x
+    Percent(Box<Node>),
+    /// `x as % of y`
+    AsPercent(Box<Node>, Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+    /// Function name and arguments
+    Call(String, Vec<Node>),
+    /// Value, and the unit to convert it to
+    Convert(Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+/// Fully bracketed, so the structure of the tree is visible. Used for logs and tests
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            NodeKind::Number(n) => write!(f, "{n}"),
+            NodeKind::Integer(n) => write!(f, "{n}"),
+            NodeKind::DateTime(date) => write!(f, "{date}"),
+            NodeKind::Time(time) => write!(f, "{time}"),
+            NodeKind::Duration(seconds) => write!(f, "{seconds}s"),
+            NodeKind::Variable(name) => f.write_str(name),
+            NodeKind::Negate(inner) => write!(f, "(-{inner})"),
+            NodeKind::BitNot(inner) => write!(f, "(~{inner})"),
+            NodeKind::Factorial(inner) => write!(f, "({inner}!)"),
+            NodeKind::Percent(inner) => write!(f, "({inner}%)"),
+            NodeKind::AsPercent(part, whole) => write!(f, "({part} as % of {whole})"),
+            NodeKind::Binary(op, lhs, rhs) => write!(f, "({lhs} {op} {rhs})"),
+            NodeKind::Convert(value, unit) => write!(f, "({value} in {unit})"),
+            NodeKind::Call(name, args) => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{name}({})", args.join(", "))
+            }
+        }
+    }
+}
+
+/// `source_len` is used to point at the end of the input when something is missing
+pub fn parse(tokens: &[Spanned], source_len: usize) -> Result<Node, SpanError> {
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: Span {
+            start: source_len,
+            end: source_len,
+        },
+    };
+    let node = parser.expression(0)?;
+    match parser.peek() {
+        // Probably `7 % 3`, which used to be the remainder
+        Some(extra) if parser.tokens[parser.pos - 1].token == Token::Percent => {
+            Err(SpanError::new(
+                format!(
+                    "Unexpected {}. Remainders use mod, like 7 mod 3",
+                    extra.token
+                ),
+                extra.span,
+            ))
+        }
+        Some(extra) => Err(unexpected(extra)),
+        None => Ok(node),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Spanned],
+    pos: usize,
+    end: Span,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Spanned> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Spanned> {
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    /// Parses until an operator that binds less tightly than `min_bp`
+    fn expression(&mut self, min_bp: u8) -> Result<Node, SpanError> {
+        let mut lhs = self.prefix()?;
+
+        while let Some(next) = self.peek() {
+            if matches!(next.token, Token::Factorial | Token::Percent) {
+                if FACTORIAL_BINDING_POWER < min_bp {
+                    break;
+                }
+                self.pos += 1;
+                let inner = Box::new(lhs);
+                lhs = Node {
+                    span: inner.span.to(next.span),
+                    kind: match next.token {
+                        Token::Factorial => NodeKind::Factorial(inner),
+                        _ => NodeKind::Percent(inner),
+                    },
+                };
+                continue;
+            }
+
+            if next.token == Token::As {
+                let (left_bp, right_bp) = CONVERT_BINDING_POWER;
+                if left_bp < min_bp {
+                    break;
+                }
+                self.pos += 1;
+                for expected in [Token::Percent, Token::Of] {
+                    match self.next() {
+                        Some(Spanned { token, .. }) if *token == expected => {}
+                        other => {
+                            return Err(SpanError::new(
+                                "as needs to be followed by % of, like 17 as % of 85",
+                                other.map_or(self.end, |other| other.span),
+                            ));
+                        }
+                    }
+                }
+                let whole = self.expression(right_bp)?;
+                lhs = Node {
+                    span: lhs.span.to(whole.span),
+                    kind: NodeKind::AsPercent(Box::new(lhs), Box::new(whole)),
+                };
+                continue;
+            }
+
+            if next.token == Token::Convert {
+                let (left_bp, right_bp) = CONVERT_BINDING_POWER;
+                if left_bp < min_bp {
+                    break;
+                }
+                self.pos += 1;
+                let unit = self.expression(right_bp)?;
+                lhs = Node {
+                    span: lhs.span.to(unit.span),
+                    kind: NodeKind::Convert(Box::new(lhs), Box::new(unit)),
+                };
+                continue;
+            }
+
+            // Something like `2r`, `2(3)` or `2sqrt(4)` is multiplied
+            let implicit = matches!(next.token, Token::Ident(_) | Token::OpenParen);
+            let (op, (left_bp, right_bp)) = match BinOp::from_token(&next.token) {
+                Some(op) => (op, op.binding_power()),
+                None if implicit => (BinOp::Multiply, IMPLICIT_MULTIPLY_BINDING_POWER),
+                None => break,
+            };
+            if left_bp < min_bp {
+                break;
+            }
+            if !implicit {
+                self.pos += 1;
+            }
+
+            let rhs = self.expression(right_bp)?;
+            lhs = Node {
+                span: lhs.span.to(rhs.span),
+                kind: NodeKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    /// A number, variable, function call, bracket or negation
+    fn prefix(&mut self) -> Result<Node, SpanError> {
+        let Some(Spanned { token, span }) = self.next() else {
+            let message = match self.pos.checked_sub(1).map(|i| &self.tokens[i]) {
+                Some(prev) => format!("Expected a number after {}", prev.token),
+                None => "Nothing to calculate".to_string(),
+            };
+            return Err(SpanError::new(message, self.end));
+        };
+        let span = *span;
+
+        let kind = match token {
+            Token::Number(n) => NodeKind::Number(*n),
+            Token::Integer(n) => NodeKind::Integer(*n),
+            Token::DateTime(date) => NodeKind::DateTime(*date),
+            Token::Time(time) => NodeKind::Time(*time),
+            Token::Duration(seconds) => NodeKind::Duration(*seconds),
+            // `min` is also minutes, like `90 min`
+            Token::Ident(name)
+                if FUNCTIONS.contains(&name.as_str())
+                    && (self
+                        .peek()
+                        .is_some_and(|next| next.token == Token::OpenParen)
+                        || units::lookup(name).is_none()) =>
+            {
+                return self.call(name, span);
+            }
+            Token::Ident(name) => NodeKind::Variable(name.clone()),
+            Token::Minus | Token::BitNot => {
+                let inner = Box::new(self.expression(NEGATE_BINDING_POWER)?);
+                return Ok(Node {
+                    span: span.to(inner.span),
+                    kind: match token {
+                        Token::Minus => NodeKind::Negate(inner),
+                        _ => NodeKind::BitNot(inner),
+                    },
+                });
+            }
+            Token::OpenParen => {
+                let inner = self.expression(0)?;
+                let close = self.close_bracket(span)?;
+                return Ok(Node {
+                    span: span.to(close),
+                    kind: inner.kind,
+                });
+            }
+            Token::Assign => {
+                return Err(unexpected(&Spanned {
+                    token: Token::Assign,
+                    span,
+                }));
+            }
+            other => {
+                return Err(SpanError::new(
+                    format!("Expected a number, not {other}"),
+                    span,
+                ));
+            }
+        };
+        Ok(Node { kind, span })
+    }
+
+    /// Arguments are split by commas, like `max(1, 2)`
+    fn call(&mut self, name: &str, name_span: Span) -> Result<Node, SpanError> {
+        let open = match self.peek() {
+            Some(Spanned {
+                token: Token::OpenParen,
+                span,
+            }) => *span,
+            _ => {
+                return Err(SpanError::new(
+                    format!("{name} needs brackets, like {name}(2)"),
+                    name_span,
+                ));
+            }
+        };
+        self.pos += 1;
+
+        let mut args = vec![self.expression(0)?];
+        while let Some(Spanned {
+            token: Token::Comma,
+            ..
+        }) = self.peek()
+        {
+            self.pos += 1;
+            args.push(self.expression(0)?);
+        }
+        let close = self.close_bracket(open)?;
+
+        Ok(Node {
+            kind: NodeKind::Call(name.to_string(), args),
+            span: name_span.to(close),
+        })
+    }
+
+    /// Returns where the `)` is
+    fn close_bracket(&mut self, open: Span) -> Result<Span, SpanError> {
+        match self.next() {
+            Some(Spanned {
+                token: Token::CloseParen,
+                span,
+            }) => Ok(*span),
+            Some(other) => Err(SpanError::new(
+                format!("Expected ), not {}", other.token),
+                other.span,
+            )),
+            None => Err(SpanError::new("This bracket is never closed", open)),
+        }
+    }
+}
+
+/// For a token that can't go where it is
+fn unexpected(token: &Spanned) -> SpanError {
+    let message = match &token.token {
+        Token::Assign => "= can only come after a variable name, like r = 2".to_string(),
+        Token::CloseParen => "This bracket was never opened".to_string(),
+        other => format!("Unexpected {other}"),
+    };
+    SpanError::new(message, token.span)
+}
+
+#[cfg(test)]
+fn parse_str(source: &str) -> Result<String, SpanError> {
+    let tokens = tokenize(source)?;
+    parse(&tokens, source.len()).map(|node| node.to_string())
+}
+
+#[test]
+fn can_convert_source_to_tokenvec() {
+    let tokens = |source: &str| -> Vec<Token> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect()
+    };
+    assert_eq!(
+        tokens("1 + 22-1*2.7 + 12^2"),
+        vec![
+            Token::Number(1.0),
+            Token::Plus,
+            Token::Number(22.0),
+            Token::Minus,
+            Token::Number(1.0),
+            Token::Multiply,
+            Token::Number(2.7),
+            Token::Plus,
+            Token::Number(12.0),
+            Token::Power,
+            Token::Number(2.0),
+        ]
+    );
+    assert_eq!(
+        tokens("1+22"),
+        vec![Token::Number(1.0), Token::Plus, Token::Number(22.0),]
+    );
+    assert_eq!(
+        tokens("1_000 x my_var"),
+        vec![
+            Token::Number(1000.0),
+            Token::Multiply,
+            Token::Ident("my_var".to_string())
+        ]
+    );
+    assert_eq!(
+        tokens("2x3x(4)"),
+        vec![
+            Token::Number(2.0),
+            Token::Multiply,
+            Token::Number(3.0),
+            Token::Multiply,
+            Token::OpenParen,
+            Token::Number(4.0),
+            Token::CloseParen,
+        ]
+    );
+    assert_eq!(tokens("x1"), vec![Token::Multiply, Token::Number(1.0)]);
+    assert_eq!(tokens("xy"), vec![Token::Ident("xy".to_string())]);
+}
+
+#[test]
+fn tokens_have_byte_spans() {
+    let spans: Vec<(usize, usize)> = tokenize(" 12 ÷ pi")
+        .unwrap()
+        .into_iter()
+        .map(|spanned| (spanned.span.start, spanned.span.end))
+        .collect();
+    // ÷ is two bytes
+    assert_eq!(spans, vec![(1, 3), (4, 6), (7, 9)]);
+
+    assert_eq!(
+        tokenize("1 + 2.3.4").unwrap_err().span,
+        Span { start: 4, end: 9 }
+    );
+    assert_eq!(
+        tokenize("2 # 3").unwrap_err().span,
+        Span { start: 2, end: 3 }
+    );
+}
+
+#[test]
+fn can_parse_unary_minus() {
+    assert_eq!(parse_str("-1").unwrap(), "(-1)");
+    assert_eq!(parse_str("2^-1").unwrap(), "(2 ^ (-1))");
+    assert_eq!(parse_str("--1").unwrap(), "(-(-1))");
+    assert_eq!(parse_str("1 - -1").unwrap(), "(1 - (-1))");
+    assert_eq!(parse_str("-3!").unwrap(), "(-(3!))");
+}
+
+#[test]
+fn can_parse_brackets() {
+    assert_eq!(parse_str("((1+4)*2)").unwrap(), "((1 + 4) * 2)");
+    assert_eq!(parse_str("2(3)(4)").unwrap(), "((2 * 3) * 4)");
+    assert_eq!(parse_str("2r^2").unwrap(), "(2 * (r ^ 2))");
+    assert_eq!(parse_str("max(1, 2+3)").unwrap(), "max(1, (2 + 3))");
+
+    assert_eq!(
+        parse_str("2 * (3 + 1").unwrap_err().span,
+        Span { start: 4, end: 5 }
+    );
+    assert_eq!(
+        parse_str("1 + 2)").unwrap_err().span,
+        Span { start: 5, end: 6 }
+    );
+}
+
+#[test]
+fn operators_have_precedence_and_associativity() {
+    assert_eq!(parse_str("1 + 2 * 3").unwrap(), "(1 + (2 * 3))");
+    assert_eq!(parse_str("1 - 2 - 3").unwrap(), "((1 - 2) - 3)");
+    assert_eq!(parse_str("12 / 2 * 3").unwrap(), "((12 / 2) * 3)");
+    assert_eq!(parse_str("2^3^2").unwrap(), "(2 ^ (3 ^ 2))");
+    assert_eq!(parse_str("2^3!").unwrap(), "(2 ^ (3!))");
+    assert_eq!(parse_str("2^3 r").unwrap(), "((2 ^ 3) * r)");
+    assert_eq!(parse_str("5 km / 2 h").unwrap(), "((5 * km) / (2 * h))");
+}
+
+#[test]
+fn can_parse_programmer_operators() {
+    let tokens = |source: &str| -> Vec<Token> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect()
+    };
+    assert_eq!(
+        tokens("0x1F_ff ^^ 0b101 << 0o17"),
+        vec![
+            Token::Integer(0x1fff),
+            Token::BitXor,
+            Token::Integer(0b101),
+            Token::ShiftLeft,
+            Token::Integer(0o17),
+        ]
+    );
+    assert_eq!(
+        parse_str("1 | 2 ^^ 3 & 4 << 1 + 1").unwrap(),
+        "(1 | (2 ^^ (3 & (4 << (1 + 1)))))"
+    );
+    assert_eq!(parse_str("~0xf & -1").unwrap(), "((~15) & (-1))");
+    assert_eq!(parse_str("2^3^2 >> 1").unwrap(), "((2 ^ (3 ^ 2)) >> 1)");
+
+    assert_eq!(
+        tokenize("1 + 0xfg").unwrap_err().span,
+        Span { start: 4, end: 8 }
+    );
+    assert!(tokenize("0b").is_err());
+    assert!(tokenize("1 < 2").is_err());
+}
+
+#[test]
+fn can_parse_conversions() {
+    assert_eq!(
+        parse_str("90 km/h in m/s").unwrap(),
+        "(((90 * km) / h) in (m / s))"
+    );
+    assert_eq!(
+        parse_str("1 km + 2 m to cm").unwrap(),
+        "(((1 * km) + (2 * m)) in cm)"
+    );
+    assert_eq!(parse_str("72 °F to °C").unwrap(), "((72 * °F) in °C)");
+    assert_eq!(parse_str("5 km in").unwrap_err().span.start, 7);
+}
+
+#[test]
+fn can_tokenize_dates_and_times() {
+    let token = |source: &str| tokenize(source).unwrap().remove(0).token;
+    let date = |year, month, day, hour, minute| {
+        Token::DateTime(civil::date(year, month, day).at(hour, minute, 0, 0))
+    };
+    assert_eq!(token("2026-12-25"), date(2026, 12, 25, 0, 0));
+    assert_eq!(token("2026-12-25T10:30"), date(2026, 12, 25, 10, 30));
+    assert_eq!(token("2026-12-25 10:30 + 1"), date(2026, 12, 25, 10, 30));
+    assert_eq!(token("9:05:30"), Token::Time(civil::time(9, 5, 30, 0)));
+    assert_eq!(token("2h45m"), Token::Duration(9_900.0));
+    assert_eq!(token("1d0.5h"), Token::Duration(88_200.0));
+
+    // Not quite dates, so they are maths like before
+    assert_eq!(tokenize("2026-1-1").unwrap().len(), 5);
+    assert_eq!(tokenize("5min").unwrap().len(), 2);
+    assert_eq!(tokenize("45m").unwrap().len(), 2);
+    assert_eq!(
+        tokenize("2026-02-30").unwrap_err().span,
+        Span { start: 0, end: 10 }
+    );
+}
+
+#[test]
+fn can_parse_percentages() {
+    assert_eq!(parse_str("200 + 15%").unwrap(), "(200 + (15%))");
+    assert_eq!(parse_str("-5%").unwrap(), "(-(5%))");
+    assert_eq!(parse_str("20% of 85").unwrap(), "((20%) * 85)");
+    assert_eq!(
+        parse_str("17 as % of 80 + 5").unwrap(),
+        "(17 as % of (80 + 5))"
+    );
+    assert_eq!(parse_str("7 mod 3").unwrap(), "(7 mod 3)");
+    assert_eq!(
+        parse_str("17 as of 85").unwrap_err().span,
+        Span { start: 6, end: 8 }
+    );
+    let error = parse_str("7 % 3").unwrap_err();
+    assert_eq!(
+        error.message,
+        "Unexpected 3. Remainders use mod, like 7 mod 3"
+    );
+}
+
+#[test]
+fn errors_point_at_the_problem() {
+    let span = |source: &str| {
+        let error = parse_str(source).unwrap_err();
+        (error.span.start, error.span.end)
+    };
+    // Missing things are at the end of the input
+    assert_eq!(span("1 +"), (3, 3));
+    assert_eq!(span(""), (0, 0));
+    assert_eq!(span("2 3"), (2, 3));
+    assert_eq!(span("sqrt 4"), (0, 4));
+    assert_eq!(span("1 = 2"), (2, 3));
+    assert_eq!(span("(1, 2)"), (2, 3));
+    assert_eq!(span("4 * / 2"), (4, 5));
+}
@@ -0,0 +1,183 @@
+// Programmer mode. Used when the input has a `0x`, `0b` or `0o` literal, or a bitwise operator.
+// Everything is a whole number, calculated exactly and wrapping around like a CPU register,
+// either as an i128 or a u64 depending on `integer_width` in the config.
+
+use super::parse::{BinOp, Node, NodeKind, SpanError};
+use super::{Memory, units};
+use crate::config::IntegerWidth;
+
+/// Biggest whole number a f64 can store exactly
+const MAX_EXACT_FLOAT: f64 = 9_007_199_254_740_992.0;
+
+pub struct Programmer<'a> {
+    /// Big decimal numbers are read from here, since their f64 isn't exact
+    pub source: &'a str,
+    pub memory: &'a Memory,
+    pub width: IntegerWidth,
+}
+
+impl Programmer<'_> {
+    fn bits(&self) -> u32 {
+        match self.width {
+            IntegerWidth::I128 => i128::BITS,
+            IntegerWidth::U64 => u64::BITS,
+        }
+    }
+
+    fn wrap(&self, value: i128) -> i128 {
+        match self.width {
+            IntegerWidth::I128 => value,
+            IntegerWidth::U64 => i128::from(value as u64),
+        }
+    }
+
+    fn whole(value: f64) -> Result<i128, String> {
+        if value.fract() != 0.0 || value.abs() > MAX_EXACT_FLOAT {
+            return Err(format!(
+                "Programmer mode only works with whole numbers, not {value}"
+            ));
+        }
+        Ok(value as i128)
+    }
+
+    pub fn evaluate(&self, node: &Node) -> Result<i128, SpanError> {
+        let eval = |node: &Node| self.evaluate(node);
+        let at_node = |e: String| SpanError::new(e, node.span);
+
+        let value = match &node.kind {
+            NodeKind::Integer(n) => *n,
+            NodeKind::Number(n) => {
+                let text: String = self
+                    .source
+                    .get(node.span.start..node.span.end)
+                    .unwrap_or_default()
+                    .chars()
+                    .filter(|c| *c != '_')
+                    .collect();
+                match text.parse::<i128>() {
+                    Ok(n) => n,
+                    Err(_) => Self::whole(*n).map_err(at_node)?,
+                }
+            }
+            NodeKind::Variable(name) => match self.memory.get(name) {
+                Some(value) => match self.memory.integer(name) {
+                    Some(n) => self.wrap(n),
+                    None => Self::whole(value).map_err(at_node)?,
+                },
+                None if units::lookup(name).is_some() => {
+                    return Err(at_node(format!(
+                        "Units like {name} can't be used in programmer mode"
+                    )));
+                }
+                None => return Err(at_node(format!("Unknown variable {name}"))),
+            },
+            NodeKind::Negate(inner) => eval(inner)?.wrapping_neg(),
+            NodeKind::BitNot(inner) => !eval(inner)?,
+            NodeKind::Factorial(inner) => {
+                let n = eval(inner)?;
+                if n < 0 {
+                    return Err(SpanError::new(
+                        format!("Factorial needs a whole number that is 0 or more, not {n}"),
+                        inner.span,
+                    ));
+                }
+                let mut product: i128 = 1;
+                // Once it wraps to 0 it stays there, so big numbers don't take forever
+                for i in 1..=n {
+                    product = self.wrap(product.wrapping_mul(i));
+                    if product == 0 {
+                        break;
+                    }
+                }
+                product
+            }
+            NodeKind::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (eval(lhs)?, eval(rhs)?);
+                self.apply_op(*op, lhs, rhs).map_err(at_node)?
+            }
+            NodeKind::Call(name, args) => {
+                let args = args.iter().map(eval).collect::<Result<Vec<i128>, _>>()?;
+                match (name.as_str(), &args[..]) {
+                    ("abs", [n]) => n.wrapping_abs(),
+                    ("min", _) => args.iter().copied().min().unwrap_or_default(),
+                    ("max", _) => args.iter().copied().max().unwrap_or_default(),
+                    ("abs", _) => {
+                        return Err(at_node(format!("abs takes 1 argument, not {}", args.len())));
+                    }
+                    _ => {
+                        return Err(at_node(format!("{name} doesn't work in programmer mode")));
+                    }
+                }
+            }
+            NodeKind::DateTime(_) | NodeKind::Time(_) | NodeKind::Duration(_) => {
+                return Err(at_node(
+                    "Dates and times can't be used in programmer mode".to_string(),
+                ));
+            }
+            NodeKind::Percent(_) | NodeKind::AsPercent(..) => {
+                return Err(at_node(
+                    "Percentages can't be used in programmer mode. Remainders use mod".to_string(),
+                ));
+            }
+            NodeKind::Convert(..) => {
+                return Err(at_node(
+                    "Units can't be used in programmer mode".to_string(),
+                ));
+            }
+        };
+        Ok(self.wrap(value))
+    }
+
+    fn apply_op(&self, op: BinOp, lhs: i128, rhs: i128) -> Result<i128, String> {
+        let shift = || match u32::try_from(rhs) {
+            Ok(shift) if shift < self.bits() => Ok(shift),
+            _ => Err(format!(
+                "Can only shift by 0 to {}, not {rhs}",
+                self.bits() - 1
+            )),
+        };
+
+        Ok(match op {
+            BinOp::Add => lhs.wrapping_add(rhs),
+            BinOp::Subtract => lhs.wrapping_sub(rhs),
+            BinOp::Multiply => lhs.wrapping_mul(rhs),
+            BinOp::Divide | BinOp::Modulo if rhs == 0 => {
+                return Err("Can't divide by zero".to_string());
+            }
+            // Rounds towards zero
+            BinOp::Divide => lhs.wrapping_div(rhs),
+            BinOp::Modulo => lhs.wrapping_rem(rhs),
+            BinOp::Power => {
+                let exponent = u32::try_from(rhs)
+                    .map_err(|_| format!("Powers need to be between 0 and {}", u32::MAX))?;
+                lhs.wrapping_pow(exponent)
+            }
+            BinOp::BitAnd => lhs & rhs,
+            BinOp::BitOr => lhs | rhs,
+            BinOp::BitXor => lhs ^ rhs,
+            BinOp::ShiftLeft => lhs << shift()?,
+            BinOp::ShiftRight => lhs >> shift()?,
+        })
+    }
+}
+
+/// The labels and text for each base. Negative numbers are written with a `-`, like `-0xff`,
+/// so they can be typed back in
+pub fn rows(value: i128) -> [(&'static str, String); 4] {
+    let sign = if value < 0 { "-" } else { "" };
+    let magnitude = value.unsigned_abs();
+    [
+        ("Hex", format!("{sign}0x{magnitude:x}")),
+        ("Dec", value.to_string()),
+        ("Oct", format!("{sign}0o{magnitude:o}")),
+        ("Bin", format!("{sign}0b{magnitude:b}")),
+    ]
+}
+
+#[test]
+fn rows_are_in_each_base() {
+    let text = |value| rows(value).map(|(_, text)| text);
+    assert_eq!(text(255), ["0xff", "255", "0o377", "0b11111111"]);
+    assert_eq!(text(-16), ["-0x10", "-16", "-0o20", "-0b10000"]);
+    assert_eq!(text(0), ["0x0", "0", "0o0", "0b0"]);
+}
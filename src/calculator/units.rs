@@ -0,0 +1,504 @@
+// Units for the calculator, like `5 km in mi` or `72 F to C`.
+// Every unit is a multiple of the SI base units, so anything with the same dimensions can be
+// converted. Celsius and Fahrenheit are also offset from kelvin, which is only used when a
+// temperature on its own is converted.
+
+use std::fmt::Display;
+
+const TOO_BIG: &str = "Unit power is too big";
+
+/// Powers of metres, kilograms, seconds, amperes, kelvin, moles, bytes and money.
+/// Bytes and money are counted as their own dimensions so they can be converted
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Dims([i8; 8]);
+
+impl Dims {
+    const fn new(m: i8, kg: i8, s: i8, a: i8, k: i8, mol: i8, bytes: i8) -> Self {
+        Dims([m, kg, s, a, k, mol, bytes, 0])
+    }
+
+    fn is_none(&self) -> bool {
+        self.0.iter().all(|power| *power == 0)
+    }
+
+    /// `None` if a power doesn't fit in an `i8`
+    fn mul(self, other: Dims) -> Option<Self> {
+        let mut dims = self;
+        for (power, other) in dims.0.iter_mut().zip(other.0) {
+            *power = power.checked_add(other)?;
+        }
+        Some(dims)
+    }
+
+    fn powi(self, n: i8) -> Option<Self> {
+        let mut dims = self;
+        for power in &mut dims.0 {
+            *power = power.checked_mul(n)?;
+        }
+        Some(dims)
+    }
+}
+
+const NONE: Dims = Dims::new(0, 0, 0, 0, 0, 0, 0);
+const LENGTH: Dims = Dims::new(1, 0, 0, 0, 0, 0, 0);
+const AREA: Dims = Dims::new(2, 0, 0, 0, 0, 0, 0);
+const VOLUME: Dims = Dims::new(3, 0, 0, 0, 0, 0, 0);
+const MASS: Dims = Dims::new(0, 1, 0, 0, 0, 0, 0);
+const TIME: Dims = Dims::new(0, 0, 1, 0, 0, 0, 0);
+const FREQUENCY: Dims = Dims::new(0, 0, -1, 0, 0, 0, 0);
+const SPEED: Dims = Dims::new(1, 0, -1, 0, 0, 0, 0);
+const FORCE: Dims = Dims::new(1, 1, -2, 0, 0, 0, 0);
+const PRESSURE: Dims = Dims::new(-1, 1, -2, 0, 0, 0, 0);
+const ENERGY: Dims = Dims::new(2, 1, -2, 0, 0, 0, 0);
+const POWER: Dims = Dims::new(2, 1, -3, 0, 0, 0, 0);
+const CURRENT: Dims = Dims::new(0, 0, 0, 1, 0, 0, 0);
+const VOLTAGE: Dims = Dims::new(2, 1, -3, -1, 0, 0, 0);
+const TEMPERATURE: Dims = Dims::new(0, 0, 0, 0, 1, 0, 0);
+const AMOUNT: Dims = Dims::new(0, 0, 0, 0, 0, 1, 0);
+const DATA: Dims = Dims::new(0, 0, 0, 0, 0, 0, 1);
+const MONEY_IDX: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prefixes {
+    None,
+    /// k, M, m, µ, and so on
+    Si,
+    /// Only the big SI prefixes, and Ki, Mi, Gi and so on
+    Data,
+}
+
+struct UnitDef {
+    names: &'static [&'static str],
+    /// How many base units one of this is
+    factor: f64,
+    dims: Dims,
+    /// Added after `factor` to get kelvin
+    offset: f64,
+    prefixes: Prefixes,
+}
+
+const fn unit(names: &'static [&'static str], factor: f64, dims: Dims) -> UnitDef {
+    UnitDef {
+        names,
+        factor,
+        dims,
+        offset: 0.0,
+        prefixes: Prefixes::None,
+    }
+}
+
+const fn prefixed(names: &'static [&'static str], factor: f64, dims: Dims) -> UnitDef {
+    UnitDef {
+        prefixes: Prefixes::Si,
+        ..unit(names, factor, dims)
+    }
+}
+
+// `in` is used for conversions, so inches are only `inch`
+const UNITS: &[UnitDef] = &[
+    prefixed(&["m", "meter", "meters", "metre", "metres"], 1.0, LENGTH),
+    unit(&["inch", "inches"], 0.0254, LENGTH),
+    unit(&["ft", "foot", "feet"], 0.3048, LENGTH),
+    unit(&["yd", "yard", "yards"], 0.9144, LENGTH),
+    unit(&["mi", "mile", "miles"], 1609.344, LENGTH),
+    unit(&["nmi"], 1852.0, LENGTH),
+    unit(&["ha", "hectare", "hectares"], 1e4, AREA),
+    unit(&["acre", "acres"], 4_046.856_422_4, AREA),
+    prefixed(
+        &["L", "l", "liter", "liters", "litre", "litres"],
+        1e-3,
+        VOLUME,
+    ),
+    unit(&["gal", "gallon", "gallons"], 3.785_411_784e-3, VOLUME),
+    prefixed(&["g", "gram", "grams"], 1e-3, MASS),
+    unit(&["t", "tonne", "tonnes"], 1e3, MASS),
+    unit(&["lb", "lbs", "pound", "pounds"], 0.453_592_37, MASS),
+    unit(&["oz", "ounce", "ounces"], 0.028_349_523_125, MASS),
+    unit(&["st", "stone"], 6.350_293_18, MASS),
+    prefixed(&["s", "sec", "second", "seconds"], 1.0, TIME),
+    unit(&["min", "minute", "minutes"], 60.0, TIME),
+    unit(&["h", "hr", "hour", "hours"], 3600.0, TIME),
+    unit(&["d", "day", "days"], 86400.0, TIME),
+    unit(&["wk", "week", "weeks"], 604_800.0, TIME),
+    // Julian year, 365.25 days
+    unit(&["yr", "year", "years"], 31_557_600.0, TIME),
+    prefixed(&["Hz", "hertz"], 1.0, FREQUENCY),
+    unit(&["mph"], 0.447_04, SPEED),
+    unit(&["kn", "knot", "knots"], 1852.0 / 3600.0, SPEED),
+    prefixed(&["N", "newton", "newtons"], 1.0, FORCE),
+    prefixed(&["Pa", "pascal"], 1.0, PRESSURE),
+    prefixed(&["bar"], 1e5, PRESSURE),
+    unit(&["atm"], 101_325.0, PRESSURE),
+    unit(&["psi"], 6_894.757_293_168, PRESSURE),
+    prefixed(&["J", "joule", "joules"], 1.0, ENERGY),
+    prefixed(&["cal", "calorie", "calories"], 4.184, ENERGY),
+    prefixed(&["Wh"], 3600.0, ENERGY),
+    prefixed(&["W", "watt", "watts"], 1.0, POWER),
+    unit(&["hp"], 745.699_871_582_270_2, POWER),
+    prefixed(&["A", "amp", "amps", "ampere", "amperes"], 1.0, CURRENT),
+    prefixed(&["V", "volt", "volts"], 1.0, VOLTAGE),
+    prefixed(&["K", "kelvin"], 1.0, TEMPERATURE),
+    UnitDef {
+        offset: 273.15,
+        ..unit(&["C", "°C", "degC", "celsius"], 1.0, TEMPERATURE)
+    },
+    UnitDef {
+        offset: 459.67 * 5.0 / 9.0,
+        ..unit(&["F", "°F", "degF", "fahrenheit"], 5.0 / 9.0, TEMPERATURE)
+    },
+    prefixed(&["mol"], 1.0, AMOUNT),
+    UnitDef {
+        prefixes: Prefixes::Data,
+        ..unit(&["B", "byte", "bytes"], 1.0, DATA)
+    },
+    UnitDef {
+        prefixes: Prefixes::Data,
+        ..unit(&["b", "bit", "bits"], 0.125, DATA)
+    },
+];
+
+const SI_PREFIXES: [(&str, f64); 21] = [
+    ("Y", 1e24),
+    ("Z", 1e21),
+    ("E", 1e18),
+    ("P", 1e15),
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("h", 1e2),
+    ("da", 1e1),
+    ("d", 1e-1),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("µ", 1e-6),
+    ("u", 1e-6),
+    ("n", 1e-9),
+    ("p", 1e-12),
+    ("f", 1e-15),
+    ("a", 1e-18),
+    ("z", 1e-21),
+    ("y", 1e-24),
+];
+
+/// Data sizes can't be smaller than a bit, so only use the first 8 `SI_PREFIXES`
+const DATA_SI_PREFIXES: usize = 8;
+
+const BINARY_PREFIXES: [(&str, f64); 6] = [
+    ("Ki", 1024.0),
+    ("Mi", 1_048_576.0),
+    ("Gi", 1_073_741_824.0),
+    ("Ti", 1_099_511_627_776.0),
+    ("Pi", 1_125_899_906_842_624.0),
+    ("Ei", 1_152_921_504_606_846_976.0),
+];
+
+/// A unit like `km`, or one made from others like `km/h`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    /// Names of the units it is made of, and their powers
+    terms: Vec<(String, i8)>,
+    factor: f64,
+    offset: f64,
+    dims: Dims,
+}
+
+/// Looks up a unit by name, like `km` or `GiB`
+pub fn lookup(name: &str) -> Option<Unit> {
+    let make = |def: &UnitDef, prefix_factor: f64| Unit {
+        terms: vec![(name.to_string(), 1)],
+        factor: def.factor * prefix_factor,
+        offset: def.offset,
+        dims: def.dims,
+    };
+
+    if let Some(def) = UNITS.iter().find(|def| def.names.contains(&name)) {
+        return Some(make(def, 1.0));
+    }
+
+    UNITS.iter().find_map(|def| {
+        let prefixes: &[(&str, f64)] = match def.prefixes {
+            Prefixes::None => return None,
+            Prefixes::Si => &SI_PREFIXES,
+            Prefixes::Data => &SI_PREFIXES[..DATA_SI_PREFIXES],
+        };
+        let binary: &[(&str, f64)] = match def.prefixes {
+            Prefixes::Data => &BINARY_PREFIXES,
+            _ => &[],
+        };
+        prefixes
+            .iter()
+            .chain(binary)
+            .find_map(|(prefix, prefix_factor)| {
+                let base = name.strip_prefix(prefix)?;
+                // Only short names get prefixes, like `km` and `ml` but not `kmetre`
+                let short = def.names.iter().any(|n| *n == base && n.len() <= 3);
+                short.then(|| make(def, *prefix_factor))
+            })
+    })
+}
+
+/// One of a currency, worth `factor` of the base currency of the rates
+pub fn currency(code: String, factor: f64) -> Unit {
+    let mut dims = NONE;
+    dims.0[MONEY_IDX] = 1;
+    Unit {
+        terms: vec![(code, 1)],
+        factor,
+        offset: 0.0,
+        dims,
+    }
+}
+
+impl Unit {
+    /// If it is money, or something like money per hour
+    pub fn is_money(&self) -> bool {
+        self.dims.0[MONEY_IDX] != 0
+    }
+
+    /// The name it was written with, if it is one unit on its own like `days`
+    pub fn name(&self) -> Option<&str> {
+        match &self.terms[..] {
+            [(name, 1)] => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Merges terms with the same name, so `m*m` is `m^2` and `km/km` disappears
+    fn mul(&self, other: &Unit) -> Result<Unit, String> {
+        let mut terms = self.terms.clone();
+        for (name, power) in &other.terms {
+            match terms.iter_mut().find(|(existing, _)| existing == name) {
+                Some((_, existing)) => {
+                    *existing = existing.checked_add(*power).ok_or(TOO_BIG)?;
+                }
+                None => terms.push((name.clone(), *power)),
+            }
+        }
+        terms.retain(|(_, power)| *power != 0);
+        Ok(Unit {
+            terms,
+            factor: self.factor * other.factor,
+            offset: 0.0,
+            dims: self.dims.mul(other.dims).ok_or(TOO_BIG)?,
+        })
+    }
+
+    fn powi(&self, n: i8) -> Result<Unit, String> {
+        let terms = self
+            .terms
+            .iter()
+            .map(|(name, power)| Some((name.clone(), power.checked_mul(n)?)))
+            .collect::<Option<_>>()
+            .ok_or(TOO_BIG)?;
+        Ok(Unit {
+            terms,
+            factor: self.factor.powi(n.into()),
+            offset: if n == 1 { self.offset } else { 0.0 },
+            dims: self.dims.powi(n).ok_or(TOO_BIG)?,
+        })
+    }
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let term = |(name, power): &(String, i8)| match power.abs() {
+            1 => name.clone(),
+            power => format!("{name}^{power}"),
+        };
+        let above: Vec<String> = self.terms.iter().filter(|t| t.1 > 0).map(term).collect();
+        let below: Vec<String> = self.terms.iter().filter(|t| t.1 < 0).map(term).collect();
+
+        match (above.is_empty(), below.is_empty()) {
+            (_, true) => f.write_str(&above.join("*")),
+            (true, false) => write!(f, "1/{}", below.join("/")),
+            (false, false) => write!(f, "{}/{}", above.join("*"), below.join("/")),
+        }
+    }
+}
+
+/// A number, and the unit it is in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    /// `None` for plain numbers
+    pub unit: Option<Unit>,
+}
+
+impl Quantity {
+    pub fn number(value: f64) -> Self {
+        Quantity { value, unit: None }
+    }
+
+    /// One of `unit`
+    pub fn of_unit(unit: Unit) -> Self {
+        Quantity {
+            value: 1.0,
+            unit: Some(unit),
+        }
+    }
+
+    fn dims(&self) -> Dims {
+        self.unit.as_ref().map_or(NONE, |unit| unit.dims)
+    }
+
+    fn describe(&self) -> String {
+        match &self.unit {
+            Some(unit) => unit.to_string(),
+            None => "a plain number".to_string(),
+        }
+    }
+
+    /// Units that cancel out, like `km/m`, become plain numbers
+    fn simplify(self) -> Self {
+        match &self.unit {
+            Some(unit) if unit.dims.is_none() => Quantity::number(self.value * unit.factor),
+            _ => self,
+        }
+    }
+
+    /// Errors if it has a unit
+    pub fn plain(&self) -> Result<f64, String> {
+        match &self.unit {
+            Some(unit) => Err(format!("Expected a plain number, not {unit}")),
+            None => Ok(self.value),
+        }
+    }
+
+    /// The value in the same unit as `other`, without any temperature offset
+    pub fn value_in(&self, other: &Quantity, verb: &str) -> Result<f64, String> {
+        if self.dims() != other.dims() {
+            return Err(format!(
+                "Can't {verb} {} and {}",
+                other.describe(),
+                self.describe()
+            ));
+        }
+        let factor = |q: &Quantity| q.unit.as_ref().map_or(1.0, |unit| unit.factor);
+        Ok(self.value * factor(self) / factor(other))
+    }
+
+    /// For `+`, `-` and `%`. The answer is in the unit of `self`
+    pub fn combine(
+        self,
+        other: &Quantity,
+        verb: &str,
+        op: fn(f64, f64) -> f64,
+    ) -> Result<Quantity, String> {
+        let rhs = other.value_in(&self, verb)?;
+        Ok(Quantity {
+            value: op(self.value, rhs),
+            unit: self.unit,
+        })
+    }
+
+    pub fn mul(self, other: Quantity) -> Result<Quantity, String> {
+        let unit = match (self.unit, other.unit) {
+            (Some(lhs), Some(rhs)) => Some(lhs.mul(&rhs)?),
+            (lhs, rhs) => lhs.or(rhs),
+        };
+        Ok(Quantity {
+            value: self.value * other.value,
+            unit,
+        }
+        .simplify())
+    }
+
+    pub fn div(self, other: Quantity) -> Result<Quantity, String> {
+        let inverse = Quantity {
+            value: 1.0 / other.value,
+            unit: other.unit.map(|unit| unit.powi(-1)).transpose()?,
+        };
+        self.mul(inverse)
+    }
+
+    pub fn pow(self, exponent: Quantity) -> Result<Quantity, String> {
+        let exponent = exponent.plain()?;
+        let Some(unit) = self.unit else {
+            return Ok(Quantity::number(self.value.powf(exponent)));
+        };
+        if exponent.fract() != 0.0 || exponent.abs() > f64::from(i8::MAX) {
+            return Err(format!(
+                "{unit} can only be raised to a whole power, not {exponent}"
+            ));
+        }
+        let n = exponent as i8;
+        Ok(Quantity {
+            value: self.value.powi(n.into()),
+            unit: Some(unit.powi(n)?),
+        }
+        .simplify())
+    }
+
+    /// `self in target`. Temperatures on their own are offset, so `0 C in F` is 32
+    pub fn convert(self, target: Quantity) -> Result<Quantity, String> {
+        let Some(unit) = target.unit.as_ref().filter(|_| target.value == 1.0) else {
+            return Err("Can only convert to a unit, like km".to_string());
+        };
+        if self.dims() != unit.dims {
+            return Err(format!("Can't convert {} to {unit}", self.describe()));
+        }
+        let (factor, offset) = self
+            .unit
+            .as_ref()
+            .map_or((1.0, 0.0), |unit| (unit.factor, unit.offset));
+        Ok(Quantity {
+            value: (self.value * factor + offset - unit.offset) / unit.factor,
+            unit: target.unit,
+        })
+    }
+}
+
+#[test]
+fn units_can_be_looked_up() {
+    let factor = |name: &str| lookup(name).unwrap().factor;
+    assert_eq!(factor("km"), 1000.0);
+    assert_eq!(factor("kg"), 1.0);
+    assert_eq!(factor("ms"), 0.001);
+    assert_eq!(factor("min"), 60.0);
+    assert_eq!(factor("mi"), 1609.344);
+    assert_eq!(factor("hPa"), 100.0);
+    assert_eq!(factor("kWh"), 3_600_000.0);
+    assert_eq!(factor("GiB"), 1_073_741_824.0);
+    assert_eq!(factor("MB"), 1e6);
+    assert_eq!(factor("Mb"), 125_000.0);
+    assert_eq!(factor("µs"), 1e-6);
+    assert_eq!(factor("ml"), 1e-6);
+
+    assert!(lookup("kmiles").is_none());
+    assert!(lookup("mB").is_none());
+    assert!(lookup("KiL").is_none());
+    assert!(lookup("kft").is_none());
+}
+
+#[test]
+fn units_combine() {
+    let km = Quantity::of_unit(lookup("km").unwrap());
+    let h = Quantity::of_unit(lookup("h").unwrap());
+    let m = Quantity::of_unit(lookup("m").unwrap());
+
+    let speed = km.clone().div(h).unwrap();
+    assert_eq!(speed.unit.as_ref().unwrap().to_string(), "km/h");
+    let area = m.clone().mul(m.clone()).unwrap();
+    assert_eq!(area.unit.as_ref().unwrap().to_string(), "m^2");
+    assert_eq!(
+        Quantity::number(2.0)
+            .div(m.clone())
+            .unwrap()
+            .unit
+            .unwrap()
+            .to_string(),
+        "1/m"
+    );
+    // Cancels out
+    assert_eq!(km.clone().div(m.clone()).unwrap(), Quantity::number(1000.0));
+
+    // Powers that don't fit are errors instead of overflowing
+    let big = m.clone().pow(Quantity::number(100.0)).unwrap();
+    assert_eq!(big.clone().mul(big.clone()), Err(TOO_BIG.to_string()));
+    assert_eq!(area.pow(Quantity::number(100.0)), Err(TOO_BIG.to_string()));
+    assert_eq!(big.pow(Quantity::number(-2.0)), Err(TOO_BIG.to_string()));
+
+    assert!(km.clone().combine(&m, "add", |a, b| a + b).is_ok());
+    assert!(km.combine(&speed, "add", |a, b| a + b).is_err());
+}
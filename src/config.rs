@@ -28,6 +28,23 @@ fn default_settings_work() {
     println!("{:#?}", DEFAULT_SETTINGS.color_scheme);
 }
 
+#[test]
+fn configs_without_new_sections_still_load() {
+    let config = r#"
+        color_scheme = "Dark"
+        transparent_background = false
+        app_prefixes = {}
+        input_prompt = "Search"
+        show_icons = true
+
+        [file_settings]
+        search_directories = ["~"]
+    "#;
+    let settings: Settings = toml::from_str(config).expect("config without new sections");
+    assert_eq!(settings.run_settings.terminal, "alacritty");
+    assert!(settings.app_settings.pinned.is_empty());
+}
+
 pub static SETTINGS: Mutex<LazyLock<Settings>> = Mutex::new(LazyLock::new(|| {
     load_from_disk().unwrap_or_else(|e| {
         log::error!(
@@ -56,6 +73,18 @@ pub struct Settings {
     pub app_prefixes: HashMap<crate::module::ModuleEnum, String>,
     pub input_prompt: String,
     pub show_icons: bool,
+    /// Overrides the icon theme read from gtk and kde settings
+    pub icon_theme: Option<String>,
+    #[serde(default)]
+    pub run_settings: RunSettings,
+    #[serde(default)]
+    pub app_settings: AppSettings,
+    #[serde(default)]
+    pub custom_entries: Vec<CustomEntry>,
+    #[serde(default)]
+    pub launch_settings: LaunchSettings,
+    #[serde(default)]
+    pub calculator_settings: CalculatorSettings,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -63,6 +92,134 @@ pub struct FileSettings {
     pub search_directories: Vec<String>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AppSettings {
+    /// Desktop file IDs or app names to show at the top when nothing is typed
+    #[serde(default)]
+    pub pinned: Vec<String>,
+    /// Desktop file IDs or app names to never show
+    #[serde(default)]
+    pub hidden: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RunSettings {
+    pub terminal: String,
+    pub terminal_args: Vec<String>,
+    pub use_terminal: bool,
+}
+
+impl Default for RunSettings {
+    fn default() -> Self {
+        RunSettings {
+            terminal: "alacritty".to_string(),
+            terminal_args: vec!["-e".to_string()],
+            use_terminal: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+pub enum LaunchBackend {
+    /// Start the process in its own session
+    #[default]
+    Setsid,
+    /// Start the process in a transient systemd scope, falling back to `Setsid` if systemd is
+    /// not running
+    SystemdScope,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LaunchSettings {
+    #[serde(default)]
+    pub backend: LaunchBackend,
+    /// Put before every launched command, e.g `["uwsm", "app", "--"]`
+    #[serde(default)]
+    pub prefix: Vec<String>,
+    /// Keyed by desktop file ID, with or without `.desktop`
+    #[serde(default)]
+    pub apps: HashMap<String, AppLaunchOverride>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AppLaunchOverride {
+    /// Put after the global prefix, e.g `["prime-run"]`
+    #[serde(default)]
+    pub prefix: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+pub enum AngleUnit {
+    #[default]
+    Radians,
+    Degrees,
+}
+
+/// How whole numbers are stored in the calculator's programmer mode.
+/// Both wrap around when they get too big
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+pub enum IntegerWidth {
+    #[default]
+    I128,
+    U64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CalculatorSettings {
+    /// Keep variables and `ans` in `DATA_DIR` between runs
+    pub save_variables: bool,
+    /// Used by sin, cos, tan and their inverses
+    pub angle_unit: AngleUnit,
+    /// Used in programmer mode
+    pub integer_width: IntegerWidth,
+    /// Calculate with exact fractions where possible, so 0.1 + 0.2 is 0.3
+    pub exact: bool,
+    /// Significant digits shown in answers
+    pub precision: usize,
+    /// Put between every 3 digits, like `,` for 1,000,000
+    pub thousands_separator: Option<char>,
+    /// Answers of at least 10^this, or at most 10^-this, use scientific notation like 1.5e20
+    pub scientific_exponent: i32,
+    /// `%` finds remainders like `mod`, instead of being a percentage
+    pub percent_is_modulo: bool,
+    /// Where `lumin update-rates` downloads currency rates from
+    pub currency_rates_url: Option<String>,
+}
+
+impl Default for CalculatorSettings {
+    fn default() -> Self {
+        CalculatorSettings {
+            save_variables: false,
+            angle_unit: AngleUnit::default(),
+            integer_width: IntegerWidth::default(),
+            exact: true,
+            precision: 20,
+            thousands_separator: None,
+            scientific_exponent: 15,
+            percent_is_modulo: false,
+            currency_rates_url: None,
+        }
+    }
+}
+
+/// A launcher entry defined in the config, for things without a `.desktop` file
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct CustomEntry {
+    pub name: String,
+    pub subtext: Option<String>,
+    /// Either an icon name from the icon theme, or a path to an image
+    pub icon: Option<String>,
+    /// The program followed by its arguments
+    pub command: Vec<String>,
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub terminal: bool,
+}
+
 fn load_from_disk() -> Result<Settings, ConfigError> {
     if !std::fs::exists(CONFIG_PATH.clone()).is_ok_and(|v| v) {
         match std::fs::write(CONFIG_PATH.clone(), include_str!("../assets/config.toml")) {
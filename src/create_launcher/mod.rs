@@ -0,0 +1,253 @@
+// Makes a .desktop file for a script or AppImage, so it shows up in the app launcher.
+// The text input is used for one field at a time, and enter moves on to the next one.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use iced::{Task, widget};
+
+use crate::{
+    apps::desktop_entry::{self, DesktopEntry},
+    message::Message,
+    module::{Module, ModuleMessage},
+    util, widglets,
+};
+
+#[derive(Debug, Clone)]
+pub enum LauncherMsg {
+    /// Enter was pressed on the current field
+    Submit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Name,
+    Command,
+    Icon,
+}
+
+pub struct LauncherCreator {
+    step: Step,
+    /// Text typed for the current step
+    input: String,
+    name: String,
+    command: String,
+    /// Where the finished entry was written
+    written: Option<PathBuf>,
+}
+
+impl Default for LauncherCreator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LauncherCreator {
+    pub fn new() -> Self {
+        LauncherCreator {
+            step: Step::Name,
+            input: String::new(),
+            name: String::new(),
+            command: String::new(),
+            written: None,
+        }
+    }
+
+    fn submit(&mut self) -> Task<ModuleMessage> {
+        if self.written.is_some() {
+            return iced::exit();
+        }
+
+        let input = self.input.trim().to_string();
+        match self.step {
+            Step::Name if input.is_empty() => {
+                return Task::done(ModuleMessage::Error(
+                    "The launcher needs a name".to_string(),
+                ));
+            }
+            Step::Name => {
+                self.name = input;
+                self.step = Step::Command;
+            }
+            Step::Command if input.is_empty() => {
+                return Task::done(ModuleMessage::Error(
+                    "The launcher needs a command to run".to_string(),
+                ));
+            }
+            Step::Command => {
+                let command = util::expand_tilde(&input);
+                if let Err(e) = check_executable(split_command(&command).0) {
+                    return Task::done(ModuleMessage::Error(e));
+                }
+                self.command = command;
+                self.step = Step::Icon;
+            }
+            Step::Icon => {
+                let entry = make_entry(&self.name, &self.command, &input);
+                match desktop_entry::write_user_entry(&entry) {
+                    Ok(path) => self.written = Some(path),
+                    Err(e) => {
+                        log::error!("Could not write desktop entry. E: {e}");
+                        return Task::done(ModuleMessage::Error(format!(
+                            "Could not write launcher: {e}"
+                        )));
+                    }
+                }
+            }
+        }
+
+        self.input.clear();
+        Task::done(ModuleMessage::ResetInput)
+    }
+}
+
+/// Builds the entry from what the user typed. An empty icon means no icon.
+fn make_entry(name: &str, command: &str, icon: &str) -> DesktopEntry {
+    let icon = icon.trim();
+    let (program, args) = split_command(command);
+    let program = desktop_entry::quote_exec_arg(program);
+    DesktopEntry {
+        name: name.to_string(),
+        exec: match args {
+            "" => program,
+            args => format!("{program} {args}"),
+        },
+        icon: (!icon.is_empty()).then(|| util::expand_tilde(icon)),
+        ..Default::default()
+    }
+}
+
+/// The program and its arguments. AppImages are often in paths with spaces, so the longest
+/// start of `command` that is a file is the program.
+fn split_command(command: &str) -> (&str, &str) {
+    let command = command.trim();
+    let mut ends: Vec<usize> = command.match_indices(' ').map(|(i, _)| i).collect();
+    ends.push(command.len());
+
+    match ends
+        .into_iter()
+        .rev()
+        .find(|end| Path::new(&command[..*end]).is_file())
+    {
+        Some(end) => (&command[..end], command[end..].trim_start()),
+        None => command.split_once(' ').unwrap_or((command, "")),
+    }
+}
+
+/// Errors if `program` is not an executable file, or the name of a program in `$PATH`
+fn check_executable(program: &str) -> Result<(), String> {
+    let path = if program.contains('/') {
+        PathBuf::from(program)
+    } else {
+        let path_var = std::env::var_os("PATH").unwrap_or_default();
+        std::env::split_paths(&path_var)
+            .map(|dir| dir.join(program))
+            .find(|path| path.is_file())
+            .ok_or_else(|| format!("{program} is not a file or a program in $PATH"))?
+    };
+
+    match std::fs::metadata(&path) {
+        Ok(metadata) if !metadata.is_file() => Err(format!("{program} is not a file")),
+        Ok(metadata) if metadata.permissions().mode() & 0o111 == 0 => Err(format!(
+            "{program} is not executable. It can be made executable with chmod +x"
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Could not read {program}: {e}")),
+    }
+}
+
+impl Module for LauncherCreator {
+    fn view(&self) -> iced::Element<'_, ModuleMessage> {
+        if let Some(path) = &self.written {
+            return widglets::ListRow::new(format!("Created launcher for {}", self.name))
+                .subtext(format!("{}. Press enter to close", path.display()))
+                .show_icon(false)
+                .selected(true)
+                .into();
+        }
+
+        let fields = [
+            (Step::Name, "Name", &self.name),
+            (Step::Command, "Command", &self.command),
+            (Step::Icon, "Icon (optional)", &String::new()),
+        ];
+
+        widget::column(fields.into_iter().map(|(step, label, value)| {
+            let value = if step == self.step {
+                &self.input
+            } else {
+                value
+            };
+            widglets::ListRow::new(label)
+                .subtext(value)
+                .show_icon(false)
+                .selected(step == self.step)
+                .on_activate(ModuleMessage::LauncherMessage(LauncherMsg::Submit))
+                .into()
+        }))
+        .into()
+    }
+
+    fn update(&mut self, msg: ModuleMessage) -> Task<ModuleMessage> {
+        match msg {
+            ModuleMessage::TextChanged(input) => {
+                self.input = input;
+                Task::none()
+            }
+            ModuleMessage::LauncherMessage(LauncherMsg::Submit) => self.submit(),
+            x => {
+                log::warn!("Create launcher module received irrelevant msg: {x:?}");
+                Task::none()
+            }
+        }
+    }
+
+    fn run(&self) -> Task<Message> {
+        Task::done(Message::PluginMessage(ModuleMessage::LauncherMessage(
+            LauncherMsg::Submit,
+        )))
+    }
+}
+
+#[test]
+fn entry_is_made_from_input() {
+    let entry = make_entry("My Tool", "/opt/tool.AppImage --flag", "  ");
+    assert_eq!(entry.name, "My Tool");
+    assert_eq!(entry.exec, "/opt/tool.AppImage --flag");
+    assert_eq!(entry.icon, None);
+    assert!(
+        entry
+            .to_file_contents()
+            .starts_with("[Desktop Entry]\nType=Application\nName=My Tool\n")
+    );
+
+    let entry = make_entry("My Tool", "tool", "~/icons/tool.png");
+    assert_eq!(
+        entry.icon,
+        Some(crate::constants::HOME_DIR.clone() + "/icons/tool.png")
+    );
+}
+
+#[test]
+fn paths_with_spaces_are_quoted() {
+    let dir = std::env::temp_dir().join(format!("lumin_launcher_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let app_image = dir.join("My 100% Tool.AppImage");
+    std::fs::write(&app_image, "").unwrap();
+    let app_image = app_image.to_string_lossy().to_string();
+
+    let command = format!("{app_image} --flag two");
+    assert_eq!(split_command(&command), (app_image.as_str(), "--flag two"));
+    assert_eq!(
+        make_entry("Tool", &command, "").exec,
+        format!("\"{app_image}\" --flag two")
+    );
+
+    assert!(check_executable(&app_image).is_err());
+    std::fs::set_permissions(&app_image, std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(check_executable(&app_image), Ok(()));
+    assert!(check_executable(&dir.join("missing").to_string_lossy()).is_err());
+    assert_eq!(check_executable("sh"), Ok(()));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
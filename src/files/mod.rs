@@ -12,12 +12,14 @@ use std::path::PathBuf;
 use shared_mime_info;
 
 use crate::{
-    config, constants,
+    config, constants, icons,
     module::{Module, ModuleMessage},
     widglets,
 };
 
-static ICON_SEARCHER: LazyLock<icon::Icons> = LazyLock::new(icon::Icons::new);
+/// Size in logical pixels to look up file icons at
+const FILE_ICON_SIZE: u32 = crate::widglets::list::ICON_SIZE;
+
 static ICON_LOOKUP: Mutex<LazyLock<HashMap<String, Option<image::Handle>>>> =
     Mutex::new(LazyLock::new(HashMap::new));
 
@@ -103,8 +105,7 @@ impl Module for FileSearcher {
                 }
             }
             ModuleMessage::ActivatedIndex(i) => {
-                self.run_at_index(i);
-                return iced::exit();
+                return self.run_at_index(i);
             }
             ModuleMessage::FileMessage(FileMsg::FoundFile(f)) => {
                 self.found_files.push(f);
@@ -127,8 +128,8 @@ impl Module for FileSearcher {
     }
 
     fn run(&self) -> iced::Task<crate::message::Message> {
-        self.run_at_index(self.selected_index);
-        iced::exit()
+        self.run_at_index(self.selected_index)
+            .map(crate::message::Message::PluginMessage)
     }
 }
 
@@ -149,15 +150,8 @@ impl FileSearcher {
             return (path, handle.clone());
         }
 
-        let icon_path = ICON_SEARCHER.find_icon(&icon_name, 32, 1, "breeze");
-
-        let icon_handle = icon_path.map(|i| {
-            if i.path().extension() == Some(std::ffi::OsStr::new("svg")) {
-                widglets::svg_path_to_handle(i.path().to_path_buf()).expect("can render svg")
-            } else {
-                iced::advanced::image::Handle::from_path(i.path())
-            }
-        });
+        let icon_handle = icons::find_icon(&icon_name, FILE_ICON_SIZE)
+            .and_then(|path| icons::load_image(&path, FILE_ICON_SIZE));
 
         ICON_LOOKUP
             .lock()
@@ -221,11 +215,14 @@ impl FileSearcher {
         rx
     }
 
-    fn run_at_index(&self, i: usize) {
-        Self::open_file(self.found_files[i].0.as_os_str())
+    fn run_at_index(&self, i: usize) -> Task<ModuleMessage> {
+        match self.found_files.get(i) {
+            Some((path, _)) => Self::open_file(path.as_os_str()),
+            None => Task::none(),
+        }
     }
 
-    fn open_file(file: &std::ffi::OsStr) {
+    fn open_file(file: &std::ffi::OsStr) -> Task<ModuleMessage> {
         let text: &str = if cfg!(target_os = "linux") {
             "xdg-open"
         } else if cfg!(target_os = "macos") {
@@ -233,6 +230,6 @@ impl FileSearcher {
         } else {
             panic!("Unknown operating system")
         };
-        crate::util::execute_command_detached(text, vec![file], None).expect("Can launch url")
+        crate::launch::Launch::new(text).args([file]).spawn()
     }
 }
@@ -0,0 +1,148 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{LazyLock, RwLock};
+
+use iced::widget::image::Handle;
+
+pub mod raster_cache;
+pub mod theme;
+
+use crate::config;
+use crate::constants;
+use crate::serworse;
+use crate::widglets;
+
+static RESOLVER: LazyLock<RwLock<theme::IconResolver>> =
+    LazyLock::new(|| RwLock::new(load_resolver()));
+
+fn load_resolver() -> theme::IconResolver {
+    let start = std::time::Instant::now();
+    let resolver = theme::IconResolver::new(&ICON_THEME_NAME, &theme::base_dirs());
+    log::info!("Time to load icon themes: {:#?}", start.elapsed());
+    resolver
+}
+
+/// Reads the icon themes from disk again, after icons were installed or removed
+pub fn reload_themes() {
+    let resolver = load_resolver();
+    *RESOLVER.write().expect("not poisoned") = resolver;
+}
+
+/// The dirs of the icon theme and its parents, which icons are read from
+pub fn theme_dirs() -> Vec<PathBuf> {
+    theme::theme_dirs(&ICON_THEME_NAME, &theme::base_dirs())
+}
+
+/// Window scale factor, rounded up. Set once the window has opened.
+static SCALE_FACTOR: AtomicU32 = AtomicU32::new(1);
+
+pub static ICON_THEME_NAME: LazyLock<String> = LazyLock::new(|| {
+    let config_home = std::env::var("XDG_CONFIG_HOME")
+        .unwrap_or_else(|_| constants::HOME_DIR.clone() + "/.config");
+
+    let from_ini = |path: String, section: &str, key: &str| -> Option<String> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        let ini = serworse::parse_ini_format(&contents);
+        Some(ini.get(section)?.get(key)?.trim().to_string()).filter(|t| !t.is_empty())
+    };
+
+    let get_gtk2 = || -> Option<String> {
+        Some(
+            serworse::parse_xsv::<String>(
+                &std::fs::read_to_string(constants::HOME_DIR.clone() + "/.gtkrc-2.0").ok()?,
+                '=',
+            )
+            .ok()?
+            .get("gtk-icon-theme-name")?
+            .trim()
+            .trim_matches('"')
+            .to_string(),
+        )
+    };
+
+    let theme = config::SETTINGS
+        .lock()
+        .expect("mutex")
+        .icon_theme
+        .clone()
+        .or_else(|| {
+            from_ini(
+                config_home.clone() + "/gtk-3.0/settings.ini",
+                "Settings",
+                "gtk-icon-theme-name",
+            )
+        })
+        .or_else(|| {
+            from_ini(
+                config_home.clone() + "/gtk-4.0/settings.ini",
+                "Settings",
+                "gtk-icon-theme-name",
+            )
+        })
+        .or_else(|| from_ini(config_home.clone() + "/kdeglobals", "Icons", "Theme"))
+        .or_else(get_gtk2)
+        .unwrap_or("Adwaita".to_string());
+    log::info!("Icon theme picked was: {theme}");
+    theme
+});
+
+/// Should be called when the window opens, so icons are picked for the screens scale
+pub fn set_scale_factor(scale: f32) {
+    let scale = (scale.ceil() as u32).max(1);
+    log::debug!("Icon scale factor set to {scale}");
+    SCALE_FACTOR.store(scale, Ordering::Relaxed);
+}
+
+pub fn scale_factor() -> u32 {
+    SCALE_FACTOR.load(Ordering::Relaxed)
+}
+
+/// Finds the path to an icon in the users icon theme. `size` is in logical pixels.
+/// Absolute paths are returned as is, if they exist.
+pub fn find_icon(icon_name: &str, size: u32) -> Option<PathBuf> {
+    RESOLVER
+        .read()
+        .expect("not poisoned")
+        .find_icon(icon_name, size, scale_factor())
+}
+
+/// Decoded pixels of an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn into_handle(self) -> Handle {
+        Handle::from_rgba(self.width, self.height, self.pixels)
+    }
+}
+
+/// Loads an icon at `path` into an image handle.
+/// SVGs are rasterised at `size` logical pixels times the window scale factor, and the pixels
+/// are cached on disk so they only need rendering once.
+pub fn load_image(path: &Path, size: u32) -> Option<Handle> {
+    if path.extension() != Some(std::ffi::OsStr::new("svg")) {
+        return Some(Handle::from_path(path));
+    }
+
+    let size = size * scale_factor();
+
+    if let Some(image) = raster_cache::get(path, size) {
+        log::trace!("Raster cache hit for {path:?}");
+        return Some(image.into_handle());
+    }
+
+    match widglets::render_svg(path, size) {
+        Ok(image) => {
+            raster_cache::insert(path, size, &image);
+            Some(image.into_handle())
+        }
+        Err(e) => {
+            log::warn!("Failed to load SVG at {path:?} with error: {e}");
+            None
+        }
+    }
+}
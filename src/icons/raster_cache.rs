@@ -0,0 +1,226 @@
+// Rendering SVGs with resvg is slow, so the rendered pixels are stored in `CACHE_DIR`.
+// Files are named by a hash of the icon path, size and mtime, so a changed icon gets a new
+// entry, and the least recently used entries are removed once the cache gets too big.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::RgbaImage;
+use crate::constants;
+
+const RASTER_CACHE_RELPATH: &str = "rasterised_icons/";
+
+/// Once the cache is bigger than this, the least recently used entries are deleted
+const MAX_CACHE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Width and height, as little endian u32s
+const HEADER_LEN: usize = 8;
+
+static RASTER_CACHE_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    let dir = PathBuf::from(constants::CACHE_DIR.to_owned() + RASTER_CACHE_RELPATH);
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::error!("Could not create raster cache dir {dir:?}. E: {e}");
+    }
+    dir
+});
+
+/// Only prune once per run, since it has to look at every file in the cache
+static HAS_PRUNED: AtomicBool = AtomicBool::new(false);
+
+pub fn get(path: &Path, size: u32) -> Option<RgbaImage> {
+    get_from(&RASTER_CACHE_DIR, path, size)
+}
+
+pub fn insert(path: &Path, size: u32, image: &RgbaImage) {
+    insert_into(&RASTER_CACHE_DIR, path, size, image);
+
+    if !HAS_PRUNED.swap(true, Ordering::Relaxed) {
+        prune(&RASTER_CACHE_DIR, MAX_CACHE_BYTES);
+    }
+}
+
+fn get_from(cache_dir: &Path, path: &Path, size: u32) -> Option<RgbaImage> {
+    let cache_path = cache_dir.join(cache_file_name(path, size)?);
+    let data = std::fs::read(&cache_path).ok()?;
+    let image = decode(&data)?;
+
+    // atime is often turned off, so the mtime is used to remember when it was last used
+    if let Err(e) = std::fs::File::options()
+        .write(true)
+        .open(&cache_path)
+        .and_then(|file| file.set_modified(SystemTime::now()))
+    {
+        log::debug!("Could not update mtime of {cache_path:?}. E: {e}");
+    }
+    Some(image)
+}
+
+fn insert_into(cache_dir: &Path, path: &Path, size: u32, image: &RgbaImage) {
+    let Some(file_name) = cache_file_name(path, size) else {
+        return;
+    };
+
+    // Write then rename, so another lumin reading at the same time never sees half a file
+    let final_path = cache_dir.join(&file_name);
+    let temp_path = cache_dir.join(format!("{file_name}.{}.tmp", std::process::id()));
+
+    if let Err(e) = std::fs::write(&temp_path, encode(image))
+        .and_then(|_| std::fs::rename(&temp_path, &final_path))
+    {
+        log::warn!("Could not write rasterised icon for {path:?} to cache. E: {e}");
+        let _ = std::fs::remove_file(&temp_path);
+    }
+}
+
+fn cache_file_name(path: &Path, size: u32) -> Option<String> {
+    let mtime = std::fs::metadata(path)
+        .ok()?
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?;
+
+    // DefaultHasher may change between rust versions. That only causes cache misses.
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    size.hash(&mut hasher);
+    mtime.hash(&mut hasher);
+    Some(format!("{:016x}.rgba", hasher.finish()))
+}
+
+fn encode(image: &RgbaImage) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + image.pixels.len());
+    out.extend_from_slice(&image.width.to_le_bytes());
+    out.extend_from_slice(&image.height.to_le_bytes());
+    out.extend_from_slice(&image.pixels);
+    out
+}
+
+fn decode(data: &[u8]) -> Option<RgbaImage> {
+    let width = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?);
+    let height = u32::from_le_bytes(data.get(4..8)?.try_into().ok()?);
+    let pixels = data.get(HEADER_LEN..)?;
+
+    if pixels.len() != width as usize * height as usize * 4 {
+        log::warn!("Rasterised icon in cache had the wrong length. Ignoring it");
+        return None;
+    }
+
+    Some(RgbaImage {
+        width,
+        height,
+        pixels: pixels.to_vec(),
+    })
+}
+
+/// Deletes the least recently used files until the cache is smaller than `max_bytes`
+fn prune(cache_dir: &Path, max_bytes: u64) {
+    let Ok(read_dir) = std::fs::read_dir(cache_dir) else {
+        return;
+    };
+
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = read_dir
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let metadata = e.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), e.path()))
+        })
+        .collect();
+
+    // Most recently used first
+    files.sort_by_key(|(modified, _, _)| std::cmp::Reverse(*modified));
+
+    let mut total = 0;
+    for (_, len, path) in files {
+        total += len;
+        if total > max_bytes {
+            log::debug!("Raster cache is full. Removing {path:?}");
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::warn!("Could not remove {path:?} from raster cache. E: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lumin_{name}_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn can_cache_rasterised_icon() {
+    let dir = test_dir("raster_cache");
+    let icon_path = dir.join("icon.svg");
+    std::fs::write(&icon_path, "<svg/>").unwrap();
+
+    let image = RgbaImage {
+        width: 2,
+        height: 1,
+        pixels: vec![1, 2, 3, 4, 5, 6, 7, 8],
+    };
+
+    assert_eq!(get_from(&dir, &icon_path, 32), None);
+    insert_into(&dir, &icon_path, 32, &image);
+    assert_eq!(get_from(&dir, &icon_path, 32), Some(image));
+    // Different size is a different entry
+    assert_eq!(get_from(&dir, &icon_path, 64), None);
+
+    assert_eq!(decode(&[2, 0, 0, 0, 2, 0, 0, 0, 1]), None);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn prune_removes_oldest() {
+    let dir = test_dir("raster_prune");
+    let set_modified = |path: &Path, time| {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    };
+    let old = dir.join("old.rgba");
+    let new = dir.join("new.rgba");
+    std::fs::write(&old, [0; 10]).unwrap();
+    std::fs::write(&new, [0; 10]).unwrap();
+    set_modified(&old, UNIX_EPOCH);
+
+    prune(&dir, 15);
+    assert!(!old.exists());
+    assert!(new.exists());
+
+    // An entry that was written first but used since is kept
+    let image = RgbaImage {
+        width: 1,
+        height: 1,
+        pixels: vec![0; 4],
+    };
+    // The icons are somewhere else, so only cache entries are in `dir`
+    let icon_dir = test_dir("raster_prune_icons");
+    let icons = ["used.svg", "unused.svg"].map(|name| icon_dir.join(name));
+    for icon in &icons {
+        std::fs::write(icon, "<svg/>").unwrap();
+        insert_into(&dir, icon, 32, &image);
+    }
+    let [used, unused] = icons
+        .each_ref()
+        .map(|icon| dir.join(cache_file_name(icon, 32).unwrap()));
+    set_modified(&used, UNIX_EPOCH);
+    set_modified(&unused, UNIX_EPOCH + std::time::Duration::from_secs(1));
+    std::fs::remove_file(&new).unwrap();
+
+    assert!(get_from(&dir, &icons[0], 32).is_some());
+    prune(&dir, 15);
+    assert!(used.exists());
+    assert!(!unused.exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+    std::fs::remove_dir_all(icon_dir).unwrap();
+}
@@ -0,0 +1,427 @@
+// https://specifications.freedesktop.org/icon-theme-spec/latest/
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::serworse;
+
+/// In order of preference. xpm is in the spec but iced cannot draw it.
+const EXTENSIONS: [&str; 2] = ["png", "svg"];
+
+const FALLBACK_THEME: &str = "hicolor";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DirType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ThemeDir {
+    size: u32,
+    scale: u32,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+    dir_type: DirType,
+}
+
+impl ThemeDir {
+    fn from_section(section: &HashMap<&str, &str>) -> Option<Self> {
+        let get = |key: &str| section.get(key).and_then(|v| v.trim().parse::<u32>().ok());
+
+        let size = get("Size")?;
+        Some(ThemeDir {
+            size,
+            scale: get("Scale").unwrap_or(1),
+            min_size: get("MinSize").unwrap_or(size),
+            max_size: get("MaxSize").unwrap_or(size),
+            threshold: get("Threshold").unwrap_or(2),
+            dir_type: match section.get("Type").map(|t| t.trim()) {
+                Some("Fixed") => DirType::Fixed,
+                Some("Scalable") => DirType::Scalable,
+                _ => DirType::Threshold,
+            },
+        })
+    }
+
+    /// `DirectoryMatchesSize` from the spec
+    fn matches_size(&self, size: u32, scale: u32) -> bool {
+        if self.scale != scale {
+            return false;
+        }
+        match self.dir_type {
+            DirType::Fixed => self.size == size,
+            DirType::Scalable => self.min_size <= size && size <= self.max_size,
+            DirType::Threshold => {
+                self.size.saturating_sub(self.threshold) <= size
+                    && size <= self.size + self.threshold
+            }
+        }
+    }
+
+    /// `DirectorySizeDistance` from the spec
+    fn size_distance(&self, size: u32, scale: u32) -> u32 {
+        let wanted = size * scale;
+        let (min, max) = match self.dir_type {
+            DirType::Fixed => (self.size, self.size),
+            DirType::Scalable => (self.min_size, self.max_size),
+            DirType::Threshold => (
+                self.size.saturating_sub(self.threshold),
+                self.size + self.threshold,
+            ),
+        };
+        let (min, max) = (min * self.scale, max * self.scale);
+
+        if wanted < min {
+            min - wanted
+        } else {
+            wanted.saturating_sub(max)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Theme {
+    parents: Vec<String>,
+    dirs: Vec<ThemeDir>,
+    /// Icon name to every file with that name, in the order of `dirs`
+    icons: HashMap<String, Vec<(usize, PathBuf)>>,
+}
+
+impl Theme {
+    /// Reads `index.theme` and lists every icon in the theme.
+    /// Themes can be split over several base dirs, so all of them are searched.
+    fn load(name: &str, base_dirs: &[PathBuf]) -> Option<Self> {
+        let index_contents = read_index(name, base_dirs)?;
+        let index = serworse::parse_ini_format(&index_contents);
+        let header = index.get("Icon Theme")?;
+
+        let parents = split_list(header.get("Inherits").copied());
+
+        let mut dir_names = split_list(header.get("Directories").copied());
+        dir_names.extend(split_list(header.get("ScaledDirectories").copied()));
+
+        let mut dirs = Vec::new();
+        let mut icons: HashMap<String, Vec<(usize, PathBuf)>> = HashMap::new();
+
+        for dir_name in dir_names {
+            let Some(dir) = index
+                .get(dir_name.as_str())
+                .and_then(ThemeDir::from_section)
+            else {
+                log::debug!("Theme {name} has bad or missing section for {dir_name}");
+                continue;
+            };
+            let dir_idx = dirs.len();
+            dirs.push(dir);
+
+            for base in base_dirs {
+                for (icon_name, path) in list_icons(&base.join(name).join(&dir_name)) {
+                    let paths = icons.entry(icon_name).or_default();
+                    // Same name with a different extension in the same dir
+                    if !paths.iter().any(|(idx, _)| *idx == dir_idx) {
+                        paths.push((dir_idx, path));
+                    }
+                }
+            }
+        }
+
+        log::debug!(
+            "Loaded icon theme {name} with {} dirs and {} icons",
+            dirs.len(),
+            icons.len()
+        );
+
+        Some(Theme {
+            parents,
+            dirs,
+            icons,
+        })
+    }
+
+    /// `LookupIcon` from the spec. Exact size match if there is one, otherwise the closest.
+    fn lookup(&self, icon_name: &str, size: u32, scale: u32) -> Option<&Path> {
+        let candidates = self.icons.get(icon_name)?;
+
+        if let Some((_, path)) = candidates
+            .iter()
+            .find(|(idx, _)| self.dirs[*idx].matches_size(size, scale))
+        {
+            return Some(path);
+        }
+
+        candidates
+            .iter()
+            .min_by_key(|(idx, _)| self.dirs[*idx].size_distance(size, scale))
+            .map(|(_, path)| path.as_path())
+    }
+}
+
+/// Finds icons by name in an icon theme, its parents, hicolor, and then loose icons such as
+/// ones in `/usr/share/pixmaps`.
+#[derive(Debug)]
+pub struct IconResolver {
+    /// The theme followed by everything it inherits from, ending with hicolor
+    themes: Vec<Theme>,
+    /// Icons that are not in a theme, just directly inside a base dir
+    loose_icons: HashMap<String, PathBuf>,
+}
+
+impl IconResolver {
+    pub fn new(theme_name: &str, base_dirs: &[PathBuf]) -> Self {
+        let themes = with_parents(theme_name, |name| {
+            let Some(theme) = Theme::load(name, base_dirs) else {
+                log::warn!("Could not load icon theme {name}");
+                return None;
+            };
+            let parents = theme.parents.clone();
+            Some((theme, parents))
+        });
+
+        let mut loose_icons = HashMap::new();
+        for base in base_dirs {
+            for (name, path) in list_icons(base) {
+                loose_icons.entry(name).or_insert(path);
+            }
+        }
+
+        IconResolver {
+            themes,
+            loose_icons,
+        }
+    }
+
+    /// `size` is in logical pixels, and `scale` is the screen scale factor
+    pub fn find_icon(&self, icon_name: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        if icon_name.starts_with('/') {
+            let path = PathBuf::from(icon_name);
+            return path.exists().then_some(path);
+        }
+
+        // Some apps incorrectly put the extension in the icon name
+        let icon_name = EXTENSIONS
+            .iter()
+            .find_map(|ext| icon_name.strip_suffix(&format!(".{ext}")))
+            .unwrap_or(icon_name);
+
+        self.themes
+            .iter()
+            .find_map(|theme| theme.lookup(icon_name, size, scale))
+            .or_else(|| self.loose_icons.get(icon_name).map(|p| p.as_path()))
+            .map(|p| p.to_path_buf())
+    }
+}
+
+/// `theme_name`, everything it inherits from, and hicolor, in the order they are searched.
+/// `load` gives a theme and the names of its parents.
+/// Depth first through Inherits, like FindIconHelper in the spec
+fn with_parents<T>(
+    theme_name: &str,
+    mut load: impl FnMut(&str) -> Option<(T, Vec<String>)>,
+) -> Vec<T> {
+    let mut themes = Vec::new();
+    let mut visited = HashSet::new();
+    // hicolor is at the bottom of the stack so it is always searched last
+    let mut to_visit = vec![FALLBACK_THEME.to_string(), theme_name.to_string()];
+
+    while let Some(name) = to_visit.pop() {
+        if !visited.insert(name.clone()) {
+            continue;
+        }
+        let Some((theme, parents)) = load(&name) else {
+            continue;
+        };
+        to_visit.extend(parents.into_iter().rev());
+        themes.push(theme);
+    }
+    themes
+}
+
+/// The dirs of `theme_name` and the themes it inherits from, in every base dir.
+/// Only index.theme is read, so this is much quicker than making an `IconResolver`.
+pub fn theme_dirs(theme_name: &str, base_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let names = with_parents(theme_name, |name| {
+        let contents = read_index(name, base_dirs)?;
+        let index = serworse::parse_ini_format(&contents);
+        let parents = split_list(index.get("Icon Theme")?.get("Inherits").copied());
+        Some((name.to_string(), parents))
+    });
+    base_dirs
+        .iter()
+        .flat_map(|base| names.iter().map(|name| base.join(name)))
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+fn read_index(name: &str, base_dirs: &[PathBuf]) -> Option<String> {
+    base_dirs
+        .iter()
+        .find_map(|base| std::fs::read_to_string(base.join(name).join("index.theme")).ok())
+}
+
+/// Base directories to look for themes in, in order of priority
+pub fn base_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from(
+        crate::constants::HOME_DIR.clone() + "/.icons",
+    )];
+    dirs.extend(
+        crate::util::data_dirs()
+            .into_iter()
+            .map(|d| PathBuf::from(d).join("icons")),
+    );
+    dirs.push(PathBuf::from("/usr/share/pixmaps"));
+    dirs
+}
+
+/// Every image in `dir` that iced can draw, as (icon name, path)
+fn list_icons(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut found: Vec<(usize, String, PathBuf)> = read_dir
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let path = e.path();
+            let ext = path.extension()?.to_str()?;
+            let priority = EXTENSIONS.iter().position(|e| *e == ext)?;
+            let name = path.file_stem()?.to_str()?.to_string();
+            Some((priority, name, path))
+        })
+        .collect();
+
+    // So png is picked over svg when both exist
+    found.sort_by_key(|(priority, _, _)| *priority);
+    let mut seen = HashSet::new();
+    found
+        .into_iter()
+        .filter(|(_, name, _)| seen.insert(name.clone()))
+        .map(|(_, name, path)| (name, path))
+        .collect()
+}
+
+fn split_list(input: Option<&str>) -> Vec<String> {
+    input
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[test]
+fn dir_size_matching() {
+    let fixed = ThemeDir {
+        size: 48,
+        scale: 1,
+        min_size: 48,
+        max_size: 48,
+        threshold: 2,
+        dir_type: DirType::Fixed,
+    };
+    assert!(fixed.matches_size(48, 1));
+    assert!(!fixed.matches_size(48, 2));
+    assert!(!fixed.matches_size(47, 1));
+    assert_eq!(fixed.size_distance(32, 1), 16);
+    assert_eq!(fixed.size_distance(32, 2), 16);
+
+    let scalable = ThemeDir {
+        min_size: 8,
+        max_size: 512,
+        dir_type: DirType::Scalable,
+        ..fixed.clone()
+    };
+    assert!(scalable.matches_size(100, 1));
+    assert_eq!(scalable.size_distance(4, 1), 4);
+
+    let threshold = ThemeDir {
+        dir_type: DirType::Threshold,
+        ..fixed
+    };
+    assert!(threshold.matches_size(50, 1));
+    assert!(!threshold.matches_size(51, 1));
+}
+
+#[test]
+fn resolves_through_inherits_and_fallbacks() {
+    let base = std::env::temp_dir().join(format!("lumin_icon_theme_{}", std::process::id()));
+    let pixmaps = base.join("pixmaps");
+    let icons = base.join("icons");
+
+    let write = |path: PathBuf, contents: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    };
+
+    write(
+        icons.join("Child/index.theme"),
+        "[Icon Theme]\nName=Child\nInherits=Parent\nDirectories=16x16/apps,48x48/apps,48x48@2/apps\n\n\
+         [16x16/apps]\nSize=16\nType=Fixed\n\n\
+         [48x48/apps]\nSize=48\nType=Fixed\n\n\
+         [48x48@2/apps]\nSize=48\nScale=2\nType=Fixed\n",
+    );
+    write(icons.join("Child/16x16/apps/small.png"), "");
+    write(icons.join("Child/48x48/apps/small.png"), "");
+    write(icons.join("Child/48x48@2/apps/small.svg"), "");
+    write(icons.join("Child/16x16/apps/only-small.png"), "");
+
+    write(
+        icons.join("Parent/index.theme"),
+        "[Icon Theme]\nName=Parent\nDirectories=scalable/apps\n\n\
+         [scalable/apps]\nSize=64\nMinSize=8\nMaxSize=512\nType=Scalable\n",
+    );
+    write(icons.join("Parent/scalable/apps/from-parent.svg"), "");
+
+    write(
+        icons.join("hicolor/index.theme"),
+        "[Icon Theme]\nName=Hicolor\nDirectories=32x32/apps\n\n\
+         [32x32/apps]\nSize=32\n",
+    );
+    write(icons.join("hicolor/32x32/apps/from-hicolor.png"), "");
+
+    write(pixmaps.join("from-pixmaps.png"), "");
+    write(pixmaps.join("not-an-image.txt"), "");
+
+    let resolver = IconResolver::new("Child", &[icons.clone(), pixmaps.clone()]);
+
+    assert_eq!(
+        resolver.find_icon("small", 48, 1),
+        Some(icons.join("Child/48x48/apps/small.png"))
+    );
+    assert_eq!(
+        resolver.find_icon("small", 48, 2),
+        Some(icons.join("Child/48x48@2/apps/small.svg"))
+    );
+    // Closest size when there is no exact match
+    assert_eq!(
+        resolver.find_icon("only-small", 64, 1),
+        Some(icons.join("Child/16x16/apps/only-small.png"))
+    );
+    assert_eq!(
+        resolver.find_icon("from-parent", 32, 1),
+        Some(icons.join("Parent/scalable/apps/from-parent.svg"))
+    );
+    assert_eq!(
+        resolver.find_icon("from-hicolor", 32, 1),
+        Some(icons.join("hicolor/32x32/apps/from-hicolor.png"))
+    );
+    assert_eq!(
+        resolver.find_icon("from-pixmaps", 32, 1),
+        Some(pixmaps.join("from-pixmaps.png"))
+    );
+    assert_eq!(
+        resolver.find_icon("from-pixmaps.png", 32, 1),
+        Some(pixmaps.join("from-pixmaps.png"))
+    );
+    assert_eq!(resolver.find_icon("not-an-image", 32, 1), None);
+    assert_eq!(resolver.find_icon("missing", 32, 1), None);
+
+    assert_eq!(
+        theme_dirs("Child", &[icons.clone(), pixmaps.clone()]),
+        ["Child", "Parent", "hicolor"].map(|name| icons.join(name))
+    );
+
+    std::fs::remove_dir_all(base).unwrap();
+}
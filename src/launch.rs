@@ -0,0 +1,658 @@
+// Launching processes. Prefixes and environment overrides from `[launch_settings]`, and the
+// launcher side of the startup-notification spec.
+// Optionally each launch gets its own systemd scope, named like
+// https://systemd.io/DESKTOP_ENVIRONMENTS/ says, so it is not counted as part of lumin.
+// https://specifications.freedesktop.org/startup-notification-spec/latest/
+//
+// On X11 a "new:" message is broadcast on the root window, and the child gets the ID in
+// `DESKTOP_STARTUP_ID` so it can mark the sequence as complete once its window is mapped.
+// If the child fails to start, lumin sends the "remove:" message itself.
+// On Wayland the child needs an `XDG_ACTIVATION_TOKEN` from the compositor. iced does not give
+// access to xdg_activation_v1 yet, so no token is requested. Stale ones are still removed.
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use iced::Task;
+
+use crate::config;
+use crate::module::ModuleMessage;
+use crate::util;
+
+const STARTUP_ID_VAR: &str = "DESKTOP_STARTUP_ID";
+const ACTIVATION_TOKEN_VAR: &str = "XDG_ACTIVATION_TOKEN";
+
+/// Counter to keep startup IDs unique within a run
+static LAUNCH_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Changes made to the environment lumin passes on to a launched process
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LaunchEnv {
+    /// `None` removes the variable
+    vars: Vec<(String, Option<String>)>,
+}
+
+impl LaunchEnv {
+    /// The environment every launch starts from.
+    /// Tokens lumin was started with have already been used on lumins own window, so they are
+    /// never passed on.
+    pub fn base() -> Self {
+        let mut env = LaunchEnv::default();
+        env.remove(STARTUP_ID_VAR);
+        env.remove(ACTIVATION_TOKEN_VAR);
+        env
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.vars.retain(|(k, _)| k != key);
+        self.vars.push((key.to_string(), Some(value.to_string())));
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.vars.retain(|(k, _)| k != key);
+        self.vars.push((key.to_string(), None));
+    }
+
+    /// `Some(None)` if the variable is removed, `None` if it is left as inherited
+    #[cfg(test)]
+    pub fn get(&self, key: &str) -> Option<Option<&str>> {
+        self.vars
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_deref())
+    }
+
+    pub fn apply(&self, command: &mut std::process::Command) {
+        for (key, value) in &self.vars {
+            match value {
+                Some(value) => command.env(key, value),
+                None => command.env_remove(key),
+            };
+        }
+    }
+}
+
+/// A process to launch. Prefixes and env overrides from `[launch_settings]` are applied when it
+/// is spawned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Launch {
+    cmd: OsString,
+    args: Vec<OsString>,
+    working_dir: Option<String>,
+    env: LaunchEnv,
+    /// Desktop file ID, for per app overrides
+    app_id: Option<String>,
+    /// Set for apps with `StartupNotify=true`
+    startup: Option<StartupInfo>,
+}
+
+impl Launch {
+    pub fn new(cmd: impl AsRef<OsStr>) -> Self {
+        Launch {
+            cmd: cmd.as_ref().to_os_string(),
+            args: Vec::new(),
+            working_dir: None,
+            env: LaunchEnv::base(),
+            app_id: None,
+            startup: None,
+        }
+    }
+
+    pub fn args<I, A>(mut self, args: I) -> Self
+    where
+        A: AsRef<OsStr>,
+        I: IntoIterator<Item = A>,
+    {
+        self.args = args
+            .into_iter()
+            .map(|a| a.as_ref().to_os_string())
+            .collect();
+        self
+    }
+
+    pub fn working_dir(mut self, working_dir: Option<String>) -> Self {
+        self.working_dir = working_dir;
+        self
+    }
+
+    pub fn app_id(mut self, app_id: Option<String>) -> Self {
+        self.app_id = app_id;
+        self
+    }
+
+    pub fn startup(mut self, startup: Option<StartupInfo>) -> Self {
+        self.startup = startup;
+        self
+    }
+
+    /// The argv and environment after applying `settings`.
+    /// The backend comes first, then the global prefix, then the apps own prefix, then the
+    /// command.
+    fn resolve(
+        mut self,
+        settings: &config::LaunchSettings,
+        systemd: &impl Systemd,
+    ) -> (Vec<OsString>, LaunchEnv) {
+        let app_override = self.app_id.as_deref().and_then(|id| {
+            settings
+                .apps
+                .iter()
+                .find(|(key, _)| id == *key || id.strip_suffix(".desktop") == Some(key))
+                .map(|(_, o)| o)
+        });
+
+        let mut argv: Vec<OsString> = Vec::new();
+        match settings.backend {
+            config::LaunchBackend::SystemdScope if systemd.available() => {
+                let unit = format!(
+                    "--unit=app-lumin-{}-{}",
+                    systemd_escape(&self.unit_id()),
+                    systemd.random_suffix()
+                );
+                argv.extend(
+                    ["systemd-run", "--user", "--scope", "--quiet", &unit, "--"]
+                        .map(OsString::from),
+                );
+            }
+            config::LaunchBackend::SystemdScope => {
+                log::warn!("systemd user instance is not running. Launching with setsid");
+            }
+            config::LaunchBackend::Setsid => {}
+        }
+
+        argv.extend(settings.prefix.iter().map(OsString::from));
+        if let Some(app_override) = app_override {
+            argv.extend(app_override.prefix.iter().map(OsString::from));
+            let mut env: Vec<_> = app_override.env.iter().collect();
+            env.sort();
+            for (key, value) in env {
+                self.env.set(key, value);
+            }
+        }
+        argv.push(self.cmd);
+        argv.extend(self.args);
+
+        (argv, self.env)
+    }
+
+    /// The application ID for the scope name. The desktop file ID, or the programs file name.
+    fn unit_id(&self) -> String {
+        match &self.app_id {
+            Some(id) => id.strip_suffix(".desktop").unwrap_or(id).to_string(),
+            None => Path::new(&self.cmd)
+                .file_name()
+                .unwrap_or(&self.cmd)
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+
+    /// Spawns the process with `util::launch_detached`.
+    /// Starting a startup sequence talks to the X server, so then the whole launch happens on a
+    /// blocking thread.
+    pub fn spawn(self) -> Task<ModuleMessage> {
+        match self.startup.clone() {
+            Some(info) if Session::detect() == Session::X11 => Task::perform(
+                async move {
+                    tokio::task::spawn_blocking(move || self.spawn_with_startup(&info))
+                        .await
+                        .unwrap_or_else(|e| Err(format!("Could not launch: {e}")))
+                },
+                ModuleMessage::Launched,
+            ),
+            // No way to request an activation token on Wayland yet. See the top of this file.
+            _ => {
+                let (argv, working_dir, env) = self.prepare();
+                let Some((cmd, args)) = argv.split_first() else {
+                    return Task::none();
+                };
+                util::launch_detached(cmd, args, working_dir, env)
+            }
+        }
+    }
+
+    /// Begins the startup sequence, then launches and watches the process.
+    /// Blocks until `util::launch_detached_blocking` returns.
+    fn spawn_with_startup(mut self, info: &StartupInfo) -> Result<(), String> {
+        let sequence = x11::Sequence::begin(info)
+            .map_err(|e| {
+                log::warn!(
+                    "Could not start startup notification for {}. E: {e}",
+                    info.name
+                );
+            })
+            .ok();
+        if let Some(sequence) = &sequence {
+            self.env = startup_env(Some(&sequence.id), None);
+        }
+
+        let (argv, working_dir, env) = self.prepare();
+        let Some((cmd, args)) = argv.split_first() else {
+            return Ok(());
+        };
+        let res = util::launch_detached_blocking(cmd, args, working_dir, &env);
+
+        // Otherwise the busy cursor stays until the window manager times the sequence out
+        if let (Err(_), Some(sequence)) = (&res, &sequence)
+            && let Err(e) = sequence.remove()
+        {
+            log::warn!("Could not end startup sequence {}. E: {e}", sequence.id);
+        }
+        res
+    }
+
+    /// The argv, working dir and environment after applying `[launch_settings]`
+    fn prepare(self) -> (Vec<OsString>, Option<String>, LaunchEnv) {
+        let working_dir = self.working_dir.clone();
+        let settings = config::SETTINGS
+            .lock()
+            .expect("mutex")
+            .launch_settings
+            .clone();
+        let (argv, env) = self.resolve(&settings, &HostSystemd);
+        log::debug!("Launching argv {argv:?} with env changes {env:?}");
+        (argv, working_dir, env)
+    }
+}
+
+/// What the systemd backend needs from the system, so tests can pretend
+trait Systemd {
+    /// Is there a systemd user instance to start scopes in
+    fn available(&self) -> bool;
+    /// Makes the scope name unique
+    fn random_suffix(&self) -> String;
+}
+
+struct HostSystemd;
+
+static SYSTEMD_AVAILABLE: LazyLock<bool> = LazyLock::new(|| {
+    let has_manager = std::env::var("XDG_RUNTIME_DIR")
+        .is_ok_and(|dir| Path::new(&dir).join("systemd/private").exists());
+    let has_systemd_run = std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| dir.join("systemd-run").is_file())
+    });
+    has_manager && has_systemd_run
+});
+
+impl Systemd for HostSystemd {
+    fn available(&self) -> bool {
+        *SYSTEMD_AVAILABLE
+    }
+
+    fn random_suffix(&self) -> String {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let count = LAUNCH_COUNT.fetch_add(1, Ordering::Relaxed);
+        format!("{:x}", nanos ^ std::process::id().rotate_left(16) ^ count)
+    }
+}
+
+/// Escapes a string to be part of a unit name, like `systemd-escape`.
+/// `-` is escaped too, since it separates the parts of the name.
+fn systemd_escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for (i, byte) in s.bytes().enumerate() {
+        if byte.is_ascii_alphanumeric() || byte == b':' || byte == b'_' || (byte == b'.' && i != 0)
+        {
+            escaped.push(byte as char);
+        } else {
+            escaped += &format!("\\x{byte:02x}");
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Session {
+    Wayland,
+    X11,
+    Unknown,
+}
+
+impl Session {
+    pub fn detect() -> Self {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Session::Wayland
+        } else if std::env::var_os("DISPLAY").is_some() {
+            Session::X11
+        } else {
+            Session::Unknown
+        }
+    }
+}
+
+/// What the launcher knows about an app with `StartupNotify=true`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StartupInfo {
+    pub name: String,
+    pub bin: String,
+    pub icon: Option<String>,
+    /// `StartupWMClass`, for apps which map a window without reading the startup ID
+    pub wm_class: Option<String>,
+}
+
+impl StartupInfo {
+    /// The spec "new:" message, without the trailing nul
+    fn new_message(&self, id: &str, screen: usize) -> String {
+        let mut message = format!(
+            "new: ID={} NAME={} SCREEN={screen} BIN={}",
+            quote(id),
+            quote(&self.name),
+            quote(&self.bin)
+        );
+        if let Some(icon) = &self.icon {
+            message += &format!(" ICON={}", quote(icon));
+        }
+        if let Some(wm_class) = &self.wm_class {
+            message += &format!(" WMCLASS={}", quote(wm_class));
+        }
+        message += &format!(
+            " DESCRIPTION={}",
+            quote(&format!("Launching {}", self.name))
+        );
+        message
+    }
+}
+
+/// The environment for a child given a startup ID and/or an activation token.
+/// Toolkits that only know `DESKTOP_STARTUP_ID` use it as the activation token on Wayland,
+/// so a token is put in both.
+pub fn startup_env(startup_id: Option<&str>, activation_token: Option<&str>) -> LaunchEnv {
+    let mut env = LaunchEnv::base();
+    if let Some(token) = activation_token {
+        env.set(ACTIVATION_TOKEN_VAR, token);
+        env.set(STARTUP_ID_VAR, token);
+    } else if let Some(id) = startup_id {
+        env.set(STARTUP_ID_VAR, id);
+    }
+    env
+}
+
+/// A unique ID in the form the spec suggests, `unique_TIMEtimestamp`.
+/// `timestamp` is the X server time of the users action.
+fn startup_id(name: &str, timestamp: u32) -> String {
+    let count = LAUNCH_COUNT.fetch_add(1, Ordering::Relaxed);
+    let name: String = name.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    format!(
+        "lumin-{}-{name}-{count}_TIME{timestamp}",
+        std::process::id()
+    )
+}
+
+/// Quotes a value for a startup-notification message
+fn quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+mod x11 {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::Event;
+    use x11rb::protocol::xproto::{self, ConnectionExt};
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::wrapper::ConnectionExt as _;
+
+    use super::StartupInfo;
+
+    type Error = Box<dyn std::error::Error>;
+
+    /// A startup sequence lumin has begun. The window is only used to send messages from.
+    pub struct Sequence {
+        pub id: String,
+        conn: RustConnection,
+        root: xproto::Window,
+        window: xproto::Window,
+        begin_atom: xproto::Atom,
+        info_atom: xproto::Atom,
+    }
+
+    impl Sequence {
+        /// Broadcasts the "new:" message. `id` is the startup ID to give to the child
+        pub fn begin(info: &StartupInfo) -> Result<Self, Error> {
+            let (conn, screen_num) = x11rb::connect(None)?;
+            let root = conn
+                .setup()
+                .roots
+                .get(screen_num)
+                .ok_or("X server has no default screen")?
+                .root;
+
+            let begin_atom = conn
+                .intern_atom(false, b"_NET_STARTUP_INFO_BEGIN")?
+                .reply()?
+                .atom;
+            let info_atom = conn.intern_atom(false, b"_NET_STARTUP_INFO")?.reply()?.atom;
+
+            let window = conn.generate_id()?;
+            conn.create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                -100,
+                -100,
+                1,
+                1,
+                0,
+                xproto::WindowClass::INPUT_ONLY,
+                x11rb::COPY_FROM_PARENT,
+                &xproto::CreateWindowAux::new()
+                    .override_redirect(1)
+                    .event_mask(xproto::EventMask::PROPERTY_CHANGE),
+            )?;
+
+            // There is no user event to take a timestamp from, so get the current server time
+            // from the PropertyNotify of an empty change
+            conn.change_property8(
+                xproto::PropMode::APPEND,
+                window,
+                xproto::AtomEnum::WM_NAME,
+                xproto::AtomEnum::STRING,
+                &[],
+            )?;
+            conn.flush()?;
+            let timestamp = loop {
+                if let Event::PropertyNotify(event) = conn.wait_for_event()? {
+                    break event.time;
+                }
+            };
+
+            let sequence = Sequence {
+                id: super::startup_id(&info.name, timestamp),
+                conn,
+                root,
+                window,
+                begin_atom,
+                info_atom,
+            };
+            sequence.broadcast(&info.new_message(&sequence.id, screen_num))?;
+            log::debug!("Started startup notification sequence {}", sequence.id);
+            Ok(sequence)
+        }
+
+        /// Ends the sequence, for when the child will never do it itself
+        pub fn remove(&self) -> Result<(), Error> {
+            self.broadcast(&format!("remove: ID={}", super::quote(&self.id)))?;
+            log::debug!("Removed startup notification sequence {}", self.id);
+            Ok(())
+        }
+
+        /// Sends `message` to the root window, 20 bytes at a time
+        fn broadcast(&self, message: &str) -> Result<(), Error> {
+            let mut message = message.as_bytes().to_vec();
+            message.push(0);
+
+            for (i, chunk) in message.chunks(20).enumerate() {
+                let mut data = [0u8; 20];
+                data[..chunk.len()].copy_from_slice(chunk);
+                let event = xproto::ClientMessageEvent::new(
+                    8,
+                    self.window,
+                    if i == 0 {
+                        self.begin_atom
+                    } else {
+                        self.info_atom
+                    },
+                    data,
+                );
+                self.conn.send_event(
+                    false,
+                    self.root,
+                    xproto::EventMask::PROPERTY_CHANGE,
+                    event,
+                )?;
+            }
+            self.conn.flush()?;
+            Ok(())
+        }
+    }
+
+    impl Drop for Sequence {
+        fn drop(&mut self) {
+            let _ = self.conn.destroy_window(self.window);
+            let _ = self.conn.flush();
+        }
+    }
+}
+
+#[test]
+fn startup_env_never_leaks_inherited_tokens() {
+    let env = startup_env(None, None);
+    assert_eq!(env.get(STARTUP_ID_VAR), Some(None));
+    assert_eq!(env.get(ACTIVATION_TOKEN_VAR), Some(None));
+
+    let env = startup_env(Some("lumin-1-firefox-0_TIME5"), None);
+    assert_eq!(
+        env.get(STARTUP_ID_VAR),
+        Some(Some("lumin-1-firefox-0_TIME5"))
+    );
+    assert_eq!(env.get(ACTIVATION_TOKEN_VAR), Some(None));
+
+    let env = startup_env(Some("ignored"), Some("token"));
+    assert_eq!(env.get(STARTUP_ID_VAR), Some(Some("token")));
+    assert_eq!(env.get(ACTIVATION_TOKEN_VAR), Some(Some("token")));
+
+    let mut command = std::process::Command::new("env");
+    env.apply(&mut command);
+    let envs: Vec<_> = command.get_envs().collect();
+    assert!(envs.contains(&(
+        std::ffi::OsStr::new(ACTIVATION_TOKEN_VAR),
+        Some(std::ffi::OsStr::new("token"))
+    )));
+}
+
+#[test]
+fn can_make_startup_message() {
+    let info = StartupInfo {
+        name: "Fire \"fox\"".to_string(),
+        bin: "firefox".to_string(),
+        icon: Some("firefox".to_string()),
+        wm_class: Some("Navigator".to_string()),
+    };
+    assert_eq!(
+        info.new_message("abc_TIME1", 0),
+        r#"new: ID="abc_TIME1" NAME="Fire \"fox\"" SCREEN=0 BIN="firefox" ICON="firefox" WMCLASS="Navigator" DESCRIPTION="Launching Fire \"fox\"""#
+    );
+
+    let id = startup_id("Fire fox!", 42);
+    assert!(id.starts_with(&format!("lumin-{}-Firefox-", std::process::id())));
+    assert!(id.ends_with("_TIME42"));
+}
+
+#[test]
+fn launch_applies_prefixes_and_env() {
+    let settings = config::LaunchSettings {
+        prefix: vec!["uwsm".to_string(), "app".to_string(), "--".to_string()],
+        apps: std::collections::HashMap::from([(
+            "org.mozilla.firefox".to_string(),
+            config::AppLaunchOverride {
+                prefix: vec!["prime-run".to_string()],
+                env: std::collections::HashMap::from([(
+                    "MOZ_ENABLE_WAYLAND".to_string(),
+                    "1".to_string(),
+                )]),
+            },
+        )]),
+        ..Default::default()
+    };
+
+    let (argv, env) = Launch::new("firefox")
+        .args(["--new-window"])
+        .app_id(Some("org.mozilla.firefox.desktop".to_string()))
+        .resolve(&settings, &FakeSystemd(true));
+    assert_eq!(
+        argv,
+        ["uwsm", "app", "--", "prime-run", "firefox", "--new-window"].map(OsString::from)
+    );
+    assert_eq!(env.get("MOZ_ENABLE_WAYLAND"), Some(Some("1")));
+    assert_eq!(env.get(STARTUP_ID_VAR), Some(None));
+
+    let (argv, env) = Launch::new("xdg-open")
+        .args(["/tmp"])
+        .resolve(&settings, &FakeSystemd(true));
+    assert_eq!(
+        argv,
+        ["uwsm", "app", "--", "xdg-open", "/tmp"].map(OsString::from)
+    );
+    assert_eq!(env.get("MOZ_ENABLE_WAYLAND"), None);
+}
+
+#[cfg(test)]
+struct FakeSystemd(bool);
+
+#[cfg(test)]
+impl Systemd for FakeSystemd {
+    fn available(&self) -> bool {
+        self.0
+    }
+
+    fn random_suffix(&self) -> String {
+        "abc123".to_string()
+    }
+}
+
+#[test]
+fn systemd_backend_wraps_in_scope() {
+    let settings = config::LaunchSettings {
+        backend: config::LaunchBackend::SystemdScope,
+        prefix: vec!["gamemoderun".to_string()],
+        ..Default::default()
+    };
+
+    let (argv, _) = Launch::new("/usr/bin/steam")
+        .args(["-silent"])
+        .app_id(Some("com.valvesoftware.Steam.desktop".to_string()))
+        .resolve(&settings, &FakeSystemd(true));
+    assert_eq!(
+        argv,
+        [
+            "systemd-run",
+            "--user",
+            "--scope",
+            "--quiet",
+            "--unit=app-lumin-com.valvesoftware.Steam-abc123",
+            "--",
+            "gamemoderun",
+            "/usr/bin/steam",
+            "-silent"
+        ]
+        .map(OsString::from)
+    );
+
+    let (argv, _) = Launch::new("/usr/bin/my-tool").resolve(&settings, &FakeSystemd(true));
+    assert_eq!(argv[4], "--unit=app-lumin-my\\x2dtool-abc123");
+
+    // Falls back to launching directly
+    let (argv, _) = Launch::new("/usr/bin/steam").resolve(&settings, &FakeSystemd(false));
+    assert_eq!(argv, ["gamemoderun", "/usr/bin/steam"].map(OsString::from));
+}
@@ -2,10 +2,15 @@
 pub mod apps;
 pub mod calculator;
 pub mod config;
+pub mod create_launcher;
 pub mod drun;
 pub mod files;
+pub mod icons;
+pub mod launch;
 pub mod module;
+pub mod run;
 pub mod serworse;
+pub mod store;
 pub mod websearch;
 pub mod widglets;
 
@@ -8,6 +8,7 @@ use std::collections::HashMap;
 mod apps;
 
 mod calculator;
+mod create_launcher;
 mod message;
 
 mod websearch;
@@ -17,11 +18,16 @@ use drun::Drun;
 
 mod files;
 
+mod run;
+
 mod config;
 mod constants;
+mod icons;
+mod launch;
 mod module;
 mod serworse;
 mod sorting;
+mod store;
 mod util;
 mod widglets;
 use module::{Module, ModuleEnum, ModuleMessage};
@@ -38,6 +44,8 @@ struct State {
     window_id: Option<iced::window::Id>,
     modules: HashMap<String, LazyCell<Box<dyn Module>>>,
     module_types: Vec<(String, ModuleEnum)>,
+    /// Shown above the results until the user types again. Usually a failed launch.
+    error: Option<String>,
 }
 
 // Startup things
@@ -68,6 +76,7 @@ impl State {
                 has_user_typed: false,
                 modules,
                 module_types,
+                error: None,
             },
             Self::load_font(),
         )
@@ -102,6 +111,7 @@ impl State {
                 has_user_typed: false,
                 modules,
                 module_types: Vec::new(),
+                error: None,
             },
             Self::load_font(),
         )
@@ -123,6 +133,7 @@ impl State {
             Message::TextInputChanged(content) => {
                 self.has_user_typed = true;
                 self.text_value = content;
+                self.error = None;
                 // Lookup module and pass in text
                 let input = self.text_value.clone();
                 if let Some((module, prefix_size)) = self.find_module_mut() {
@@ -140,12 +151,54 @@ impl State {
             }
             Message::WindowOpened(id) => {
                 self.window_id = Some(id);
-                widget::operation::focus(self.text_id.clone())
+                Task::batch([
+                    widget::operation::focus(self.text_id.clone()),
+                    iced::window::scale_factor(id).map(Message::ScaleFactorChanged),
+                ])
+            }
+            Message::ScaleFactorChanged(scale) => {
+                icons::set_scale_factor(scale);
+                Task::none()
             }
             Message::Close => {
                 log::info!("App is exiting");
                 iced::exit()
             }
+            Message::PluginMessage(ModuleMessage::Launched(res)) => match res {
+                Ok(()) => iced::exit(),
+                Err(e) => {
+                    self.error = Some(e);
+                    Task::none()
+                }
+            },
+            Message::PluginMessage(ModuleMessage::Error(e)) => {
+                self.error = Some(e);
+                Task::none()
+            }
+            Message::PluginMessage(ModuleMessage::ResetInput) => {
+                if let Some((_, prefix_size)) = self.find_module() {
+                    self.text_value.truncate(prefix_size);
+                }
+                Task::none()
+            }
+            // Watcher updates always go to the app module, whatever is being shown
+            Message::PluginMessage(
+                msg @ ModuleMessage::AppMessage(
+                    apps::AppMessage::EntriesChanged(_) | apps::AppMessage::IconThemeChanged,
+                ),
+            ) => {
+                let Some((prefix, _)) = self
+                    .module_types
+                    .iter()
+                    .find(|(_, module)| *module == ModuleEnum::AppSearch)
+                else {
+                    return Task::none();
+                };
+                match self.modules.get_mut(prefix) {
+                    Some(module) => module.update(msg).map(Message::PluginMessage),
+                    None => Task::none(),
+                }
+            }
             Message::PluginMessage(a) => {
                 log::trace!("Handling module message {a:?}");
                 if let Some((module, prefix)) = self.find_module_mut() {
@@ -212,11 +265,29 @@ impl State {
 
         let result = self.get_result_to_display();
 
-        let root_continer = widget::container(widget::column![
-            text_input,
-            widget::space().height(8),
-            result
-        ])
+        let error_banner = self.error.as_ref().map(|e| {
+            widget::container(widget::text(e.clone()))
+                .padding(8)
+                .width(iced::Fill)
+                .style(|theme: &iced::Theme| {
+                    let danger = theme.extended_palette().danger;
+                    widget::container::Style {
+                        background: Some(iced::Background::Color(danger.weak.color)),
+                        text_color: Some(danger.weak.text),
+                        border: iced::Border {
+                            radius: 10.0.into(),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    }
+                })
+        });
+
+        let root_continer = widget::container(
+            widget::column![text_input, widget::space().height(8)]
+                .push(error_banner)
+                .push(result),
+        )
         .style(|theme| {
             let mut base_theme = widget::container::bordered_box(theme);
             base_theme.border = iced::Border {
@@ -328,8 +399,8 @@ impl State {
     }
 }
 
-fn subscription(_state: &State) -> iced::Subscription<Message> {
-    iced::Subscription::batch(vec![
+fn subscription(state: &State) -> iced::Subscription<Message> {
+    let mut subscriptions = vec![
         iced::window::open_events().map(Message::WindowOpened),
         // Thank you https://kressle.in/keystrokes
         // iced::keyboard::on_key_press(handle_press_hotkeys),
@@ -347,7 +418,21 @@ fn subscription(_state: &State) -> iced::Subscription<Message> {
             .unwrap_or(Message::DoNothing)
         }), // Todo, work out how to subscribe to mouse movement
             // https://docs.iced.rs/iced/mouse/index.html
-    ])
+    ];
+
+    #[cfg(target_os = "linux")]
+    if state
+        .module_types
+        .iter()
+        .any(|(_, module)| *module == ModuleEnum::AppSearch)
+    {
+        subscriptions.push(
+            apps::watcher::subscription()
+                .map(|msg| Message::PluginMessage(ModuleMessage::AppMessage(msg))),
+        );
+    }
+
+    iced::Subscription::batch(subscriptions)
 }
 
 fn handle_press_hotkeys(key: keyboard::Key, modifier: keyboard::Modifiers) -> Option<Message> {
@@ -360,6 +445,15 @@ fn handle_press_hotkeys(key: keyboard::Key, modifier: keyboard::Modifiers) -> Op
         (kb::Key::Named(kb::key::Named::ArrowDown), _) => Some(Message::KeyboardDown),
         (kb::Key::Named(kb::key::Named::Tab), kmod::SHIFT) => Some(Message::KeyboardUp),
         (kb::Key::Named(kb::key::Named::Tab), _) => Some(Message::KeyboardDown),
+        (kb::Key::Character("p"), kmod::CTRL) => Some(Message::PluginMessage(
+            ModuleMessage::AppMessage(apps::AppMessage::TogglePinSelected),
+        )),
+        (kb::Key::Character("h"), kmod::CTRL) => Some(Message::PluginMessage(
+            ModuleMessage::AppMessage(apps::AppMessage::HideSelected),
+        )),
+        (kb::Key::Character("e"), kmod::CTRL) => Some(Message::PluginMessage(
+            ModuleMessage::AppMessage(apps::AppMessage::EditSelected),
+        )),
         _ => None,
     }
 }
@@ -374,6 +468,19 @@ fn handle_release_hotkeys(key: keyboard::Key, _modifier: keyboard::Modifiers) ->
     }
 }
 
+/// Deletes everything in `CACHE_DIR`. It is all regenerated when needed.
+fn clear_cache_dir() -> std::io::Result<()> {
+    for entry in std::fs::read_dir(constants::CACHE_DIR.as_str())? {
+        let path = entry?.path();
+        if path.is_dir() {
+            std::fs::remove_dir_all(path)?;
+        } else {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), String> {
     pretty_env_logger::init();
 
@@ -392,6 +499,24 @@ fn main() -> Result<(), String> {
                 );
                 return Ok(());
             }
+            "--clear-cache" => {
+                return match clear_cache_dir() {
+                    Ok(()) => {
+                        println!("Cleared cache at {}", constants::CACHE_DIR.as_str());
+                        Ok(())
+                    }
+                    Err(e) => Err(format!("Could not clear cache: {e}")),
+                };
+            }
+            "validate-desktop" => {
+                let Some(path) = args.next() else {
+                    return Err("Missing file or directory after validate-desktop".to_string());
+                };
+                return apps::validate::run(std::path::Path::new(&path));
+            }
+            "update-rates" => {
+                return calculator::currency::update();
+            }
             "--dmenu" => {
                 state = State::new_drun;
             }
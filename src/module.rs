@@ -5,7 +5,9 @@ use crate::message::Message;
 
 use crate::apps;
 use crate::calculator;
+use crate::create_launcher;
 use crate::files;
+use crate::run;
 use crate::websearch;
 
 #[derive(Debug, Clone)]
@@ -17,13 +19,22 @@ pub enum ModuleMessage {
     AppMessage(apps::AppMessage),
     WebMessage(websearch::WebMsg),
     FileMessage(files::FileMsg),
+    CalcMessage(calculator::CalcMsg),
+    LauncherMessage(create_launcher::LauncherMsg),
+    /// A module launched something. Handled by `State`, which exits on success or shows the error
+    Launched(Result<(), String>),
+    /// Shown to the user by `State` until they type again
+    Error(String),
+    /// Clears what the user typed, keeping the module prefix
+    ResetInput,
     DoNothing,
 }
 
 pub trait Module {
     fn update(&mut self, msg: ModuleMessage) -> Task<ModuleMessage>;
     fn view(&self) -> Element<'_, ModuleMessage>;
-    /// Executed when user presses the enter key
+    /// Executed when user presses the enter key.
+    /// Modules that launch something should return the task from `util::launch_detached`
     fn run(&self) -> Task<Message>;
 }
 
@@ -34,6 +45,8 @@ pub enum ModuleEnum {
     FileSearch,
     HelpScreen,
     Calculator,
+    Run,
+    CreateLauncher,
 }
 impl ModuleEnum {
     pub fn description(&self) -> String {
@@ -43,6 +56,8 @@ impl ModuleEnum {
             Self::FileSearch => "Search the files on your computer",
             Self::HelpScreen => "This help screen",
             Self::Calculator => "Basic calculator ",
+            Self::Run => "Run any command on your $PATH",
+            Self::CreateLauncher => "Create a launcher for a script or AppImage",
         }
         .to_string()
     }
@@ -55,6 +70,8 @@ impl From<&ModuleEnum> for fn() -> Box<dyn Module> {
             ModuleEnum::WebSearch => || Box::new(websearch::Web::new()),
             ModuleEnum::Calculator => || Box::new(calculator::Calc::new()),
             ModuleEnum::FileSearch => || Box::new(files::FileSearcher::new()),
+            ModuleEnum::Run => || Box::new(run::Runner::new()),
+            ModuleEnum::CreateLauncher => || Box::new(create_launcher::LauncherCreator::new()),
             ModuleEnum::HelpScreen => unreachable!("Help screen should not be ran as real module"),
         }
     }
@@ -0,0 +1,339 @@
+use iced::{Task, widget};
+use std::{
+    collections::{HashMap, HashSet},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    sync::LazyLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config, constants,
+    module::{Module, ModuleMessage},
+    serworse, sorting,
+    store::Store,
+    util, widglets,
+};
+
+const RUN_HISTORY_RELPATH: &str = "run_history";
+
+static RUN_HISTORY_FILE_PATH: LazyLock<String> =
+    LazyLock::new(|| constants::DATA_DIR.to_owned() + RUN_HISTORY_RELPATH);
+
+/// How many results to show. There can be thousands of executables, so drawing them all is slow
+const MAX_RESULTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Executable {
+    name: String,
+    path: PathBuf,
+}
+
+/// How often and how recently an executable has been ran
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Usage {
+    count: u32,
+    /// Unix timestamp in seconds
+    last_used: u64,
+}
+
+impl std::str::FromStr for Usage {
+    type Err = serworse::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, last_used) = s
+            .split_once(';')
+            .ok_or(serworse::ParseError::MissingSeperator)?;
+        Ok(Usage {
+            count: count
+                .parse()
+                .map_err(|_| serworse::ParseError::IntoFailure)?,
+            last_used: last_used
+                .parse()
+                .map_err(|_| serworse::ParseError::IntoFailure)?,
+        })
+    }
+}
+
+impl std::fmt::Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{};{}", self.count, self.last_used)
+    }
+}
+
+impl Usage {
+    /// Frequency weighted by recency, similar to firefox's "frecency".
+    fn score(&self, now: u64) -> i32 {
+        const HOUR: u64 = 60 * 60;
+        const DAY: u64 = HOUR * 24;
+
+        let age = now.saturating_sub(self.last_used);
+        let recency = match age {
+            a if a < HOUR => 4,
+            a if a < DAY => 2,
+            a if a < DAY * 7 => 1,
+            _ => 0,
+        };
+
+        (self.count as f32).ln().max(0.0).floor() as i32 + recency
+    }
+}
+
+pub struct Runner {
+    executables: Vec<Executable>,
+    history: HashMap<String, Usage>,
+    /// Text typed after the command name
+    args: Vec<String>,
+    selected_index: usize,
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runner {
+    pub fn new() -> Self {
+        Runner {
+            executables: Vec::new(),
+            history: Store::new(RUN_HISTORY_FILE_PATH.as_str()).load(),
+            args: Vec::new(),
+            selected_index: 0,
+        }
+    }
+
+    fn handle_text_change(&mut self, input: String) -> Task<ModuleMessage> {
+        self.selected_index = 0;
+
+        if self.executables.is_empty() {
+            let start = std::time::Instant::now();
+            self.executables = find_executables(&std::env::var("PATH").unwrap_or_default());
+            log::info!(
+                "Time to find #{} executables: {:#?}",
+                self.executables.len(),
+                start.elapsed()
+            );
+        }
+
+        let (cmd, args) = split_input(&input);
+        self.args = args;
+
+        let now = unix_now();
+        self.executables.sort_by_cached_key(|exe| {
+            let mut score = sorting::score_element(&cmd, &exe.name);
+            if exe.name == cmd {
+                score += 2;
+            }
+            if let Some(usage) = self.history.get(&exe.name) {
+                score += usage.score(now);
+            }
+            -score
+        });
+
+        Task::none()
+    }
+
+    fn run_at_index(&self, index: usize) -> Task<ModuleMessage> {
+        let Some(exe) = self.executables.get(index) else {
+            return Task::none();
+        };
+
+        let res = Store::new(RUN_HISTORY_FILE_PATH.as_str()).update(|history| {
+            let usage = history.entry(exe.name.clone()).or_insert(Usage {
+                count: 0,
+                last_used: 0,
+            });
+            usage.count += 1;
+            usage.last_used = unix_now();
+        });
+        if let Err(e) = res {
+            log::error!("Could not write run_history to file!! e: {e}");
+        }
+
+        let cmd = exe.path.to_string_lossy().to_string();
+        let (cmd, args) = if config::SETTINGS
+            .lock()
+            .expect("mutex")
+            .run_settings
+            .use_terminal
+        {
+            util::wrap_in_terminal(cmd, self.args.clone())
+        } else {
+            (cmd, self.args.clone())
+        };
+
+        crate::launch::Launch::new(cmd).args(args).spawn()
+    }
+}
+
+impl Module for Runner {
+    fn view(&self) -> iced::Element<'_, ModuleMessage> {
+        widget::scrollable(widget::column(
+            self.executables
+                .iter()
+                .take(MAX_RESULTS)
+                .enumerate()
+                .map(|(i, exe)| {
+                    widglets::ListRow::new(&exe.name)
+                        .subtext(exe.path.to_string_lossy())
+                        .show_icon(false)
+                        .on_activate(ModuleMessage::ActivatedIndex(i))
+                        .selected(self.selected_index == i)
+                        .into()
+                }),
+        ))
+        .direction(widget::scrollable::Direction::Vertical(
+            widget::scrollable::Scrollbar::hidden(),
+        ))
+        .into()
+    }
+
+    fn update(&mut self, msg: ModuleMessage) -> Task<ModuleMessage> {
+        match msg {
+            ModuleMessage::TextChanged(input) => self.handle_text_change(input),
+            ModuleMessage::ActivatedIndex(i) => self.run_at_index(i),
+            ModuleMessage::SelectionUp => {
+                if self.selected_index >= 1 {
+                    self.selected_index -= 1
+                }
+                Task::none()
+            }
+            ModuleMessage::SelectionDown => {
+                if self.selected_index + 1 < self.executables.len().min(MAX_RESULTS) {
+                    self.selected_index += 1
+                }
+                Task::none()
+            }
+            x => {
+                log::warn!("Run module received irrelevant msg: {x:?}");
+                Task::none()
+            }
+        }
+    }
+
+    fn run(&self) -> Task<crate::message::Message> {
+        self.run_at_index(self.selected_index)
+            .map(crate::message::Message::PluginMessage)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Splits "cmd arg1 arg2" into the command name and its arguments
+fn split_input(input: &str) -> (String, Vec<String>) {
+    let mut parts = input.split_whitespace().map(|s| s.to_string());
+    let cmd = parts.next().unwrap_or_default();
+    (cmd, parts.collect())
+}
+
+#[test]
+fn can_split_input() {
+    assert_eq!(
+        split_input("  notify-send  hello world"),
+        (
+            "notify-send".to_string(),
+            vec!["hello".to_string(), "world".to_string()]
+        )
+    );
+    assert_eq!(split_input(""), (String::new(), vec![]));
+}
+
+/// Finds every executable file in a `$PATH` style string.
+/// If two directories contain the same name, the first one wins, like in a shell.
+fn find_executables(path_var: &str) -> Vec<Executable> {
+    let mut seen_dirs = HashSet::new();
+    let mut seen_names = HashSet::new();
+    let mut out = Vec::new();
+
+    for dir in path_var.split(':').filter(|d| !d.is_empty()) {
+        // Lots of distros have /bin -> /usr/bin, so dont walk the same directory twice
+        let Ok(real_dir) = std::fs::canonicalize(dir) else {
+            continue;
+        };
+        if !seen_dirs.insert(real_dir) {
+            continue;
+        }
+
+        let Ok(read_dir) = std::fs::read_dir(dir) else {
+            log::debug!("Could not read $PATH dir {dir}");
+            continue;
+        };
+
+        for entry in read_dir.filter_map(|e| e.ok()) {
+            // fs::metadata follows symlinks, so broken links are skipped here
+            let Ok(metadata) = std::fs::metadata(entry.path()) else {
+                continue;
+            };
+            if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().to_string();
+            if seen_names.insert(name.clone()) {
+                out.push(Executable {
+                    name,
+                    path: entry.path(),
+                });
+            }
+        }
+    }
+
+    out
+}
+
+#[test]
+fn find_executables_dedupes() {
+    let root = std::env::temp_dir().join(format!("lumin_run_test_{}", std::process::id()));
+    let first = root.join("first");
+    let second = root.join("second");
+    std::fs::create_dir_all(&first).unwrap();
+    std::fs::create_dir_all(&second).unwrap();
+
+    for dir in [&first, &second] {
+        let exe = dir.join("tool");
+        std::fs::write(&exe, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    std::fs::write(first.join("not_executable"), "").unwrap();
+    std::os::unix::fs::symlink(first.join("tool"), second.join("linked")).unwrap();
+
+    let path_var = format!(
+        "{}:{}:{}",
+        first.display(),
+        second.display(),
+        first.display()
+    );
+    let mut names: Vec<(String, PathBuf)> = find_executables(&path_var)
+        .into_iter()
+        .map(|e| (e.name, e.path))
+        .collect();
+    names.sort();
+
+    assert_eq!(
+        names,
+        vec![
+            ("linked".to_string(), second.join("linked")),
+            ("tool".to_string(), first.join("tool")),
+        ]
+    );
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn usage_round_trips_and_scores() {
+    let usage = Usage {
+        count: 10,
+        last_used: 1000,
+    };
+    assert_eq!(usage.to_string().parse::<Usage>().unwrap(), usage);
+
+    // Recently used beats used a long time ago
+    assert!(usage.score(1000 + 60) > usage.score(1000 + 60 * 60 * 24 * 30));
+}
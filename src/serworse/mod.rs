@@ -7,8 +7,64 @@ pub enum ParseError {
     MissingSeperator,
     /// Failed to turn key/value of item into type required
     IntoFailure,
-    /// Bad Header
-    BadHeader,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// 1 based
+    pub line: usize,
+    /// 1 based, in characters
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IniError {
+    pub position: Position,
+    pub kind: IniErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IniErrorKind {
+    /// `[Group` without the closing `]`
+    UnclosedHeader,
+    /// Something other than whitespace after the closing `]`
+    TextAfterHeader,
+    /// `[`, `]` or a control character inside a group name
+    BadHeaderCharacter(char),
+    EmptyGroupName,
+    /// A line that is not a group header, key=value pair or comment
+    MissingSeperator,
+    EmptyKey,
+    /// Only found by `parse_ini_with_errors`, since the tokenizer doesn't keep track of keys
+    DuplicateKey(String),
+}
+
+impl std::fmt::Display for IniError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.position.line, self.position.column, self.kind
+        )
+    }
+}
+
+impl std::fmt::Display for IniErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IniErrorKind::UnclosedHeader => write!(f, "Group header is missing the closing ]"),
+            IniErrorKind::TextAfterHeader => write!(f, "Unexpected text after group header"),
+            IniErrorKind::BadHeaderCharacter(c) => {
+                write!(f, "{c:?} is not allowed in a group name")
+            }
+            IniErrorKind::EmptyGroupName => write!(f, "Group name is empty"),
+            IniErrorKind::MissingSeperator => write!(f, "Expected key=value, group or comment"),
+            IniErrorKind::EmptyKey => write!(f, "Key is empty"),
+            IniErrorKind::DuplicateKey(key) => {
+                write!(f, "Key {key:?} is set more than once. Using the last one")
+            }
+        }
+    }
 }
 
 /// any sort of two item pairing with a single character seperator, e.g x=y, x,y
@@ -36,15 +92,6 @@ pub fn parse_csv<T: std::str::FromStr>(data: &str) -> Result<HashMap<String, T>,
     parse_xsv(data, ',')
 }
 
-/// Inverse function of parse_csv
-pub fn hash_map_to_csv<T: std::string::ToString + Clone>(map: HashMap<String, T>) -> String {
-    let mut out = String::new();
-    for (key, val) in map.iter() {
-        out += &(key.clone() + "," + &val.to_string() + "\n");
-    }
-    out
-}
-
 #[test]
 fn can_parse_csv() {
     let raw_string = r#"one,1
@@ -60,47 +107,146 @@ ten,10"#;
     // assert_eq!(hash_map_to_csv::<u32>(map), raw_string);
 }
 
-// Using lifetimes here may look ugly, but it leads to a 30% performance improvement from reduced
-// heap allocations
-pub fn parse_ini_format<'a>(
-    input: &'a str,
-) -> Result<HashMap<&'a str, HashMap<&'a str, &'a str>>, ParseError> {
-    let mut main_map: HashMap<&'a str, HashMap<&'a str, &'a str>> = HashMap::new();
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IniToken<'a> {
+    /// `[name]`
+    Group(&'a str),
+    /// `key=value`, with the whitespace around both removed
+    Entry { key: &'a str, value: &'a str },
+}
 
-    let mut current_heading = "";
-    let mut current_map: HashMap<&'a str, &'a str> = HashMap::new();
+/// Splits ini files, like .desktop files, into tokens that borrow from the input.
+/// https://specifications.freedesktop.org/desktop-entry-spec/latest/basic-format.html
+///
+/// Comments are lines starting with `#`, after any whitespace. Lines never continue onto the
+/// next one, so a trailing `\` is kept in the value. Escape sequences are left for the caller.
+pub struct IniTokenizer<'a> {
+    lines: std::iter::Enumerate<std::str::Split<'a, char>>,
+}
 
-    for line in input.lines() {
-        if let Some(l) = line.split_once('=') {
-            current_map.insert(l.0, l.1);
-            continue;
+impl<'a> IniTokenizer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        IniTokenizer {
+            lines: input.split('\n').enumerate(),
         }
+    }
+}
 
-        if line.starts_with("#") {
-            continue;
-        }
+impl<'a> Iterator for IniTokenizer<'a> {
+    type Item = Result<(Position, IniToken<'a>), IniError>;
 
-        if line.starts_with("[") {
-            if !current_map.is_empty() {
-                main_map.insert(
-                    std::mem::take(&mut current_heading),
-                    std::mem::take(&mut current_map),
-                );
+    fn next(&mut self) -> Option<Self::Item> {
+        for (i, raw_line) in self.lines.by_ref() {
+            let line = raw_line.strip_suffix('\r').unwrap_or(raw_line);
+            let content = line.trim_start();
+            // Characters, not bytes, so columns line up in editors
+            let column_of = |offset: usize| line[..offset].chars().count() + 1;
+            let start = line.len() - content.len();
+            let position = Position {
+                line: i + 1,
+                column: column_of(start),
+            };
+            let error = |offset: usize, kind: IniErrorKind| {
+                Some(Err(IniError {
+                    position: Position {
+                        line: i + 1,
+                        column: column_of(offset),
+                    },
+                    kind,
+                }))
+            };
+
+            if content.is_empty() || content.starts_with('#') {
+                continue;
             }
 
-            // Get characters between starting and ending []. i.e, the xxx in "[xxx]"
-            current_heading = line.get(1..line.len() - 1).ok_or(ParseError::BadHeader)?;
-            continue;
+            if let Some(header) = content.strip_prefix('[') {
+                let Some(end) = header.find(']') else {
+                    return error(line.len(), IniErrorKind::UnclosedHeader);
+                };
+                let name = &header[..end];
+                // +1 for the opening [
+                let name_start = start + 1;
+                let after = &header[end + 1..];
+                let after_content = after.trim_start();
+                if !after_content.is_empty() {
+                    let offset = name_start + end + 1 + after.len() - after_content.len();
+                    return error(offset, IniErrorKind::TextAfterHeader);
+                }
+                if name.is_empty() {
+                    return error(name_start, IniErrorKind::EmptyGroupName);
+                }
+                if let Some((offset, c)) = name
+                    .char_indices()
+                    .find(|(_, c)| *c == '[' || c.is_control())
+                {
+                    return error(name_start + offset, IniErrorKind::BadHeaderCharacter(c));
+                }
+                return Some(Ok((position, IniToken::Group(name))));
+            }
+
+            let Some((key, value)) = content.split_once('=') else {
+                return error(start, IniErrorKind::MissingSeperator);
+            };
+            let key = key.trim_end();
+            if key.is_empty() {
+                return error(start, IniErrorKind::EmptyKey);
+            }
+            return Some(Ok((
+                position,
+                IniToken::Entry {
+                    key,
+                    value: value.trim(),
+                },
+            )));
         }
+        None
     }
-    if !current_map.is_empty() {
-        main_map.insert(
-            std::mem::take(&mut current_heading),
-            std::mem::take(&mut current_map),
-        );
+}
+
+/// Group name -> key -> value
+pub type IniMap<'a> = HashMap<&'a str, HashMap<&'a str, &'a str>>;
+
+/// `parse_ini_with_errors`, logging the errors as warnings
+pub fn parse_ini_format(input: &str) -> IniMap<'_> {
+    let (map, errors) = parse_ini_with_errors(input);
+    for e in errors {
+        log::warn!("Problem in ini file at {e}");
     }
+    map
+}
+
+// Using lifetimes here may look ugly, but it leads to a 30% performance improvement from reduced
+// heap allocations
+/// Parses an ini file into group name -> key -> value.
+/// Like GKeyFile this is lenient, since one bad line shouldn't lose the whole file. Bad lines are
+/// skipped, keys before the first group go in the group `""`, and when a key is set more than
+/// once the last one wins. Each of these is returned as an error, so callers can show them.
+pub fn parse_ini_with_errors(input: &str) -> (IniMap<'_>, Vec<IniError>) {
+    let mut main_map: IniMap = HashMap::new();
+    let mut errors = Vec::new();
+    let mut current_heading = "";
 
-    Ok(main_map)
+    for token in IniTokenizer::new(input) {
+        match token {
+            Ok((_, IniToken::Group(name))) => {
+                main_map.entry(name).or_default();
+                current_heading = name;
+            }
+            Ok((position, IniToken::Entry { key, value })) => {
+                let group = main_map.entry(current_heading).or_default();
+                if group.insert(key, value).is_some() {
+                    errors.push(IniError {
+                        position,
+                        kind: IniErrorKind::DuplicateKey(key.to_string()),
+                    });
+                }
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (main_map, errors)
 }
 
 #[test]
@@ -116,8 +262,175 @@ fn can_parse_entry_from_str() {
             r#"[Desktop Entry]
 Type=Application
 Categories=System;TerminalEmulator;"#
-        )
-        .unwrap(),
+        ),
         hash
     )
 }
+
+#[cfg(test)]
+fn ini_error(input: &str) -> (usize, usize, IniErrorKind) {
+    match IniTokenizer::new(input).find_map(|token| token.err()) {
+        Some(e) => (e.position.line, e.position.column, e.kind),
+        None => panic!("Expected an ini error for {input:?}"),
+    }
+}
+
+#[test]
+fn ini_handles_comments_and_whitespace() {
+    let map = parse_ini_format(
+        "# Foo=bar\r\n  [Group]  \r\n  # Also=a comment\n\tKey  =  spaced value \nEmpty=\nEq=a=b\n\n",
+    );
+    assert_eq!(map.len(), 1);
+    let group = &map["Group"];
+    assert_eq!(group.len(), 3);
+    assert_eq!(group["Key"], "spaced value");
+    assert_eq!(group["Empty"], "");
+    assert_eq!(group["Eq"], "a=b");
+}
+
+#[test]
+fn ini_merges_duplicate_groups() {
+    let map = parse_ini_format("[A]\none=1\n[B]\ntwo=2\n[A]\nthree=3\n[Empty]\n");
+    assert_eq!(map["A"].len(), 2);
+    assert_eq!(map["A"]["three"], "3");
+    assert_eq!(map["B"]["two"], "2");
+    assert!(map["Empty"].is_empty());
+}
+
+#[test]
+fn ini_skips_bad_lines() {
+    let map = parse_ini_format(
+        "gtk-theme=outside\n[A]\nkey=1\nno equals\n[B] text\nkey=2\n[B]\nkey=3\n[A]\n  key = 4",
+    );
+    assert_eq!(map[""]["gtk-theme"], "outside");
+    // The bad header is skipped, so its key goes in A
+    assert_eq!(map["A"]["key"], "4");
+    assert_eq!(map["B"]["key"], "3");
+    assert_eq!(map.len(), 3);
+
+    let (_, errors) =
+        parse_ini_with_errors("[A]\nkey=1\nno equals\n[B]\nkey=2\n[A]\n  key = 3\nkey=4");
+    let errors: Vec<_> = errors
+        .into_iter()
+        .map(|e| (e.position.line, e.position.column, e.kind))
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            (3, 1, IniErrorKind::MissingSeperator),
+            (7, 3, IniErrorKind::DuplicateKey("key".to_string())),
+            (8, 1, IniErrorKind::DuplicateKey("key".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn ini_errors_have_positions() {
+    assert_eq!(
+        ini_error("[A]\n[Unclosed"),
+        (2, 10, IniErrorKind::UnclosedHeader)
+    );
+    assert_eq!(ini_error("  [A] b"), (1, 7, IniErrorKind::TextAfterHeader));
+    assert_eq!(ini_error("[]"), (1, 2, IniErrorKind::EmptyGroupName));
+    assert_eq!(
+        ini_error("[A[B]"),
+        (1, 3, IniErrorKind::BadHeaderCharacter('['))
+    );
+    assert_eq!(
+        ini_error("[A]\n\n  no equals"),
+        (3, 3, IniErrorKind::MissingSeperator)
+    );
+    assert_eq!(ini_error("[A]\n =value"), (2, 2, IniErrorKind::EmptyKey));
+    // Columns count characters, not bytes
+    assert_eq!(ini_error("[ä]\nää"), (2, 1, IniErrorKind::MissingSeperator));
+    assert_eq!(ini_error("[ää] x"), (1, 6, IniErrorKind::TextAfterHeader));
+}
+
+/// Small xorshift so the fuzz tests are the same every run
+#[cfg(test)]
+struct TestRng(u64);
+
+#[cfg(test)]
+impl TestRng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.next() as usize % items.len()]
+    }
+}
+
+#[test]
+fn ini_fuzz_never_panics() {
+    let pieces = [
+        "[", "]", "=", "#", "\n", "\r\n", " ", "\t", "a", "Key", ";", "\\", "é", "\u{0}", "[A]\n",
+    ];
+    let mut rng = TestRng(0x2545_f491_4f6c_dd1d);
+
+    for _ in 0..5000 {
+        let len = rng.next() % 40;
+        let input: String = (0..len).map(|_| rng.pick(&pieces)).collect();
+
+        let map = parse_ini_format(&input);
+        let range = input.as_ptr() as usize..input.as_ptr() as usize + input.len() + 1;
+        for (group, keys) in &map {
+            for (key, value) in keys {
+                // Zero copy, so everything points into the input. Apart from the `""` group
+                for s in [group, key, value].into_iter().filter(|s| !s.is_empty()) {
+                    assert!(range.contains(&(s.as_ptr() as usize)), "{input:?}");
+                }
+                assert!(!key.is_empty());
+                assert_eq!(key.trim(), *key);
+                assert_eq!(value.trim(), *value);
+                assert!(!value.contains('\n'));
+            }
+        }
+
+        for e in IniTokenizer::new(&input).filter_map(|token| token.err()) {
+            assert!(e.position.line >= 1 && e.position.line <= input.split('\n').count());
+            assert!(e.position.column >= 1);
+        }
+    }
+}
+
+#[test]
+fn ini_fuzz_round_trips() {
+    let names = ["Desktop Entry", "Desktop Action new", "A", "ä b"];
+    let keys = ["Name", "Exec", "Name[de]", "X-Thing", "k"];
+    let values = ["", "a", "a b", "x=y", "s;t;", "\\n", "#not comment", "[x]"];
+    let space = ["", " ", "\t", "  "];
+    let mut rng = TestRng(0x9e37_79b9_7f4a_7c15);
+
+    for _ in 0..1000 {
+        let mut expected: HashMap<&str, HashMap<&str, &str>> = HashMap::new();
+        let mut input = String::new();
+        for _ in 0..rng.next() % 6 {
+            let group = rng.pick(&names);
+            expected.entry(group).or_default();
+            input += &format!("{}[{group}]{}\n", rng.pick(&space), rng.pick(&space));
+            for _ in 0..rng.next() % 4 {
+                let key = rng.pick(&keys);
+                let value = rng.pick(&values);
+                if expected[group].contains_key(key) {
+                    continue;
+                }
+                expected.entry(group).or_default().insert(key, value);
+                input += &format!(
+                    "{}{key}{}={}{value}{}\n",
+                    rng.pick(&space),
+                    rng.pick(&space),
+                    rng.pick(&space),
+                    rng.pick(&space)
+                );
+                if rng.next().is_multiple_of(4) {
+                    input += &format!("{}# {key}=commented\n\n", rng.pick(&space));
+                }
+            }
+        }
+        assert_eq!(parse_ini_format(&input), expected, "{input:?}");
+    }
+}
@@ -0,0 +1,288 @@
+// Small key-value files in `DATA_DIR` and `CACHE_DIR`, like app launch counts and run history.
+//
+// Files start with a version header, then have one `key\tvalue` pair per line. Tabs, newlines
+// and backslashes are escaped, so keys can contain anything.
+// Writes go to a temporary file that is renamed over the old one, while holding an advisory lock
+// on `<file>.lock`, so several lumin instances can't clobber each other.
+// Files without the header are from before this format, when they were `key,value` CSV. They are
+// read as CSV and rewritten in the new format the next time they are saved.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::serworse;
+
+const VERSION: u32 = 1;
+const HEADER_PREFIX: &str = "# lumin store v";
+
+pub struct Store {
+    path: PathBuf,
+}
+
+/// Held while reading or writing. The lock is released when the file is closed.
+struct FileLock(#[allow(dead_code)] File);
+
+impl FileLock {
+    fn new(path: &Path, exclusive: bool) -> io::Result<Self> {
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        let operation = if exclusive {
+            libc::LOCK_EX
+        } else {
+            libc::LOCK_SH
+        };
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(FileLock(file))
+    }
+}
+
+impl Store {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Store { path: path.into() }
+    }
+
+    fn lock(&self, exclusive: bool) -> io::Result<FileLock> {
+        let mut lock_path = self.path.clone().into_os_string();
+        lock_path.push(".lock");
+        FileLock::new(Path::new(&lock_path), exclusive)
+    }
+
+    /// Reads the whole file. Missing files are empty, and lines that can't be read are skipped
+    pub fn load<T: std::str::FromStr>(&self) -> HashMap<String, T> {
+        let _lock = self
+            .lock(false)
+            .map_err(|e| log::warn!("Could not lock {:?} for reading. E: {e}", self.path))
+            .ok();
+        self.read().unwrap_or_default()
+    }
+
+    /// Loads the file, lets `f` change it, then saves it again, all while holding the lock.
+    /// Changes made by other lumin instances since this one loaded the file are kept.
+    /// Returns the map that was saved.
+    pub fn update<T, F>(&self, f: F) -> io::Result<HashMap<String, T>>
+    where
+        T: std::str::FromStr + std::fmt::Display,
+        F: FnOnce(&mut HashMap<String, T>),
+    {
+        let _lock = self.lock(true)?;
+        let mut map = self.read()?;
+        f(&mut map);
+        write_atomic(&self.path, &serialize(&map))?;
+        log::debug!("Saved {} items to {:?}", map.len(), self.path);
+        Ok(map)
+    }
+
+    /// Errors if the file was written by a newer version of lumin, so it isn't overwritten
+    fn read<T: std::str::FromStr>(&self) -> io::Result<HashMap<String, T>> {
+        let data = match std::fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => {
+                log::warn!("Could not read {:?}. E: {e}", self.path);
+                return Err(e);
+            }
+        };
+        parse(&data).map_err(|version| {
+            log::error!(
+                "{:?} is version {version} but only up to {VERSION} is supported. Ignoring it",
+                self.path
+            );
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported store version {version}"),
+            )
+        })
+    }
+}
+
+/// Errors with the version if it is newer than `VERSION`
+fn parse<T: std::str::FromStr>(data: &str) -> Result<HashMap<String, T>, u32> {
+    let mut lines = data.lines().enumerate();
+
+    let version = data
+        .lines()
+        .next()
+        .and_then(|l| l.strip_prefix(HEADER_PREFIX))
+        .and_then(|v| v.trim().parse::<u32>().ok());
+    let Some(version) = version else {
+        return Ok(parse_legacy_csv(data));
+    };
+    if version > VERSION {
+        return Err(version);
+    }
+    lines.next();
+
+    let mut map = HashMap::new();
+    for (i, line) in lines {
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = line.split_once('\t').and_then(|(key, value)| {
+            let value = unescape(value)?.parse::<T>().ok()?;
+            Some((unescape(key)?, value))
+        });
+        match parsed {
+            Some((key, value)) => {
+                map.insert(key, value);
+            }
+            None => log::warn!("Skipping bad line {} in store: {line:?}", i + 1),
+        }
+    }
+    Ok(map)
+}
+
+/// The old format. Bad lines are skipped instead of losing the whole file
+fn parse_legacy_csv<T: std::str::FromStr>(data: &str) -> HashMap<String, T> {
+    log::info!("Migrating csv file to the store format");
+    let mut map = HashMap::new();
+    for line in data.lines() {
+        match serworse::parse_csv::<T>(line) {
+            Ok(parsed) => map.extend(parsed),
+            Err(e) => log::warn!("Skipping bad csv line {line:?}. E: {e:?}"),
+        }
+    }
+    map
+}
+
+fn serialize<T: std::fmt::Display>(map: &HashMap<String, T>) -> String {
+    // Sorted so the file doesn't change when nothing in it did
+    let mut items: Vec<(&String, &T)> = map.iter().collect();
+    items.sort_by_key(|(key, _)| *key);
+
+    let mut out = format!("{HEADER_PREFIX}{VERSION}\n");
+    for (key, value) in items {
+        out += &format!("{}\t{}\n", escape(key), escape(&value.to_string()));
+    }
+    out
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out += "\\\\",
+            '\t' => out += "\\t",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `None` if there is an unknown escape sequence
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => out.push('\\'),
+            't' => out.push('\t'),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+/// Writes to a temporary file next to `path`, then renames it over `path`.
+/// Readers see either the old file or the new one, never half of it.
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".tmp{}", std::process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let res = File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(e) = res.and_then(|()| std::fs::rename(&tmp_path, path)) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[test]
+fn store_escapes_and_migrates() {
+    let dir = std::env::temp_dir().join(format!("lumin_store_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app_lookup");
+
+    // Old csv files are read, skipping bad lines
+    std::fs::write(&path, "Firefox,3\nbad line\nKitty,x\nFiles,1\n").unwrap();
+    let store = Store::new(&path);
+    let map: HashMap<String, u32> = store.load();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["Firefox"], 3);
+
+    let weird = "Comma, tab\t newline\n backslash\\n".to_string();
+    let saved = store
+        .update(|map: &mut HashMap<String, u32>| {
+            *map.entry("Firefox".to_string()).or_default() += 1;
+            map.insert(weird.clone(), 7);
+        })
+        .unwrap();
+    assert_eq!(saved["Firefox"], 4);
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(contents.starts_with("# lumin store v1\n"));
+    assert_eq!(contents.lines().count(), 4);
+
+    let map: HashMap<String, u32> = store.load();
+    assert_eq!(map, saved);
+    assert_eq!(map[&weird], 7);
+
+    // Files from newer versions are left alone
+    std::fs::write(&path, "# lumin store v99\nsomething\n").unwrap();
+    assert!(store.load::<u32>().is_empty());
+    assert!(store.update(|_: &mut HashMap<String, u32>| {}).is_err());
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "# lumin store v99\nsomething\n"
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn store_updates_dont_clobber_each_other() {
+    let dir = std::env::temp_dir().join(format!("lumin_store_race_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("counts");
+
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                // A new `Store` and lock file handle each time, like separate lumin instances
+                for _ in 0..25 {
+                    Store::new(&path)
+                        .update(|map: &mut HashMap<String, u32>| {
+                            *map.entry("count".to_string()).or_default() += 1;
+                        })
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(Store::new(&path).load::<u32>()["count"], 200);
+    std::fs::remove_dir_all(dir).unwrap();
+}
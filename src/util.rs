@@ -2,13 +2,86 @@ use std::convert::AsRef;
 use std::ffi::OsStr;
 use std::io;
 use std::process;
+use std::time::{Duration, Instant};
+
+use iced::Task;
+
+use crate::config;
+use crate::constants;
+use crate::launch::LaunchEnv;
+use crate::module::ModuleMessage;
+
+/// How long a launched process is watched for, so that it failing straight away can be reported
+const LAUNCH_GRACE_PERIOD: Duration = Duration::from_millis(400);
+
+/// Expands a leading `~` to the users home directory
+pub fn expand_tilde(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            constants::HOME_DIR.clone() + rest
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// `$XDG_DATA_HOME`, or `~/.local/share` if it isn't set
+pub fn data_home() -> String {
+    non_empty_var("XDG_DATA_HOME").unwrap_or_else(|| constants::HOME_DIR.clone() + "/.local/share")
+}
+
+/// `data_home` followed by `$XDG_DATA_DIRS`, in order of priority
+pub fn data_dirs() -> Vec<String> {
+    join_data_dirs(data_home(), non_empty_var("XDG_DATA_DIRS"))
+}
+
+/// Uses the default from the XDG base directory spec if `data_dirs` isn't set
+fn join_data_dirs(data_home: String, data_dirs: Option<String>) -> Vec<String> {
+    let data_dirs = data_dirs.unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    std::iter::once(data_home)
+        .chain(
+            data_dirs
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(str::to_string),
+        )
+        .collect()
+}
+
+fn non_empty_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+#[test]
+fn data_dirs_have_defaults() {
+    assert_eq!(
+        join_data_dirs("/home/me/.local/share".to_string(), None),
+        ["/home/me/.local/share", "/usr/local/share", "/usr/share"]
+    );
+    assert_eq!(
+        join_data_dirs(
+            "/data".to_string(),
+            Some("/opt/share::/usr/share".to_string())
+        ),
+        ["/data", "/opt/share", "/usr/share"]
+    );
+}
+
+/// Wraps `cmd` and `args` so they run inside the terminal set in `[run_settings]`
+pub fn wrap_in_terminal(cmd: String, args: Vec<String>) -> (String, Vec<String>) {
+    let settings = config::SETTINGS.lock().expect("mutex").run_settings.clone();
+    let mut full_args = settings.terminal_args;
+    full_args.push(cmd);
+    full_args.extend(args);
+    (settings.terminal, full_args)
+}
 
 #[cfg(unix)]
 pub fn execute_command_detached<S, I, A>(
     cmd: S,
     args: I,
     working_dir_arg: Option<String>,
-) -> io::Result<()>
+    env: &LaunchEnv,
+) -> io::Result<process::Child>
 where
     S: AsRef<OsStr>,
     A: AsRef<OsStr>,
@@ -20,6 +93,7 @@ where
         working_dir_arg.unwrap_or(std::env::var("HOME").unwrap_or("/".into()));
 
     let mut command = process::Command::new(cmd);
+    env.apply(&mut command);
     unsafe {
         command
             .args(args)
@@ -37,7 +111,118 @@ where
                 Ok(())
             });
         log::info!("Executing app {:#?}", command);
-        command.spawn()?;
+        command.spawn()
+    }
+}
+
+/// Runs the command with `execute_command_detached`, then watches it for `LAUNCH_GRACE_PERIOD`.
+/// The task resolves to `ModuleMessage::Launched`, with an error if the command could not be
+/// spawned, or it exited unsuccessfully during that time.
+#[cfg(unix)]
+pub fn launch_detached<S, I, A>(
+    cmd: S,
+    args: I,
+    working_dir: Option<String>,
+    env: LaunchEnv,
+) -> Task<ModuleMessage>
+where
+    S: AsRef<OsStr>,
+    A: AsRef<OsStr>,
+    I: IntoIterator<Item = A>,
+{
+    let name = cmd.as_ref().to_string_lossy().to_string();
+
+    let mut child = match execute_command_detached(cmd, args, working_dir, &env) {
+        Ok(child) => child,
+        Err(e) => {
+            log::error!("Could not launch {name}. E: {e}");
+            return Task::done(ModuleMessage::Launched(Err(format!(
+                "Could not launch {name}: {e}"
+            ))));
+        }
+    };
+
+    Task::perform(
+        async move {
+            tokio::task::spawn_blocking(move || watch_child(&mut child, &name, LAUNCH_GRACE_PERIOD))
+                .await
+                .unwrap_or_else(|e| Err(format!("Could not watch launched process: {e}")))
+        },
+        ModuleMessage::Launched,
+    )
+}
+
+/// Like `launch_detached`, but blocks while the process is watched. For use off the UI thread
+#[cfg(unix)]
+pub fn launch_detached_blocking<S, I, A>(
+    cmd: S,
+    args: I,
+    working_dir: Option<String>,
+    env: &LaunchEnv,
+) -> Result<(), String>
+where
+    S: AsRef<OsStr>,
+    A: AsRef<OsStr>,
+    I: IntoIterator<Item = A>,
+{
+    let name = cmd.as_ref().to_string_lossy().to_string();
+
+    let mut child = execute_command_detached(cmd, args, working_dir, env).map_err(|e| {
+        log::error!("Could not launch {name}. E: {e}");
+        format!("Could not launch {name}: {e}")
+    })?;
+    watch_child(&mut child, &name, LAUNCH_GRACE_PERIOD)
+}
+
+/// Waits up to `grace_period` for `child` to exit.
+/// Still running or exiting successfully counts as a successful launch.
+fn watch_child(
+    child: &mut process::Child,
+    name: &str,
+    grace_period: Duration,
+) -> Result<(), String> {
+    let start = Instant::now();
+    while start.elapsed() < grace_period {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => {
+                log::error!("{name} exited straight away with {status}");
+                return Err(format!("{name} failed to start ({status})"));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(20)),
+            Err(e) => {
+                log::warn!("Could not check if {name} is still running. E: {e}");
+                return Ok(());
+            }
+        }
     }
     Ok(())
 }
+
+#[test]
+fn watch_child_reports_failed_exit() {
+    let mut child =
+        execute_command_detached("false", Vec::<String>::new(), None, &LaunchEnv::base()).unwrap();
+    let res = watch_child(&mut child, "false", Duration::from_secs(5));
+    assert!(res.unwrap_err().starts_with("false failed to start"));
+
+    let mut child =
+        execute_command_detached("true", Vec::<String>::new(), None, &LaunchEnv::base()).unwrap();
+    assert_eq!(
+        watch_child(&mut child, "true", Duration::from_secs(5)),
+        Ok(())
+    );
+
+    let mut child = execute_command_detached("sleep", ["5"], None, &LaunchEnv::base()).unwrap();
+    assert_eq!(
+        watch_child(&mut child, "sleep", Duration::from_millis(50)),
+        Ok(())
+    );
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!(
+        execute_command_detached("lumin-not-a-real-program", ["a"], None, &LaunchEnv::base())
+            .is_err()
+    );
+}
@@ -2,8 +2,9 @@ use iced::{Task, widget};
 use std::collections::HashMap;
 
 use crate::{
+    launch,
     module::{Module, ModuleMessage},
-    util, widglets,
+    widglets,
 };
 
 mod dictionary;
@@ -164,7 +165,7 @@ impl Web {
         Ok(bytes)
     }
 
-    fn launch_url(url: &str) {
+    fn launch_url(url: &str) -> Task<ModuleMessage> {
         let text: &str = if cfg!(target_os = "linux") {
             "xdg-open"
         } else if cfg!(target_os = "macos") {
@@ -172,7 +173,7 @@ impl Web {
         } else {
             panic!("Unknown operating system")
         };
-        util::execute_command_detached(text, vec![url], None).expect("Can launch url")
+        launch::Launch::new(text).args([url]).spawn()
     }
 }
 
@@ -265,8 +266,7 @@ impl Module for Web {
                     }
                     WebMsg::ResultActivated(url) => {
                         log::info!("Launching webresult with URL: {url}");
-                        Self::launch_url(&url);
-                        iced::exit()
+                        Self::launch_url(&url)
                     }
                     WebMsg::FetchedImage((url, image)) => {
                         log::trace!(
@@ -290,8 +290,8 @@ impl Module for Web {
         match self.cached_results.get(&self.input_for_results) {
             Some(v) => {
                 if let Some(search_res) = v.get(self.selected_index) {
-                    Self::launch_url(&search_res.destination_url);
-                    iced::exit()
+                    Self::launch_url(&search_res.destination_url)
+                        .map(crate::message::Message::PluginMessage)
                 } else {
                     log::warn!(
                         "Selected search_result index was invalid. \
@@ -4,7 +4,8 @@ use iced::widget;
 use super::PADDING;
 use super::{HeadingLevel, heading};
 
-const ICON_SIZE: f32 = 32.0;
+/// Height and width of icons in logical pixels
+pub const ICON_SIZE: u32 = 32;
 
 /// A single list item displayed to the user.
 /// Is activatable by clicking
@@ -117,7 +118,7 @@ where
                     .into()
                 })
                 .width(iced::Shrink)
-                .height(iced::Length::Fixed(ICON_SIZE)),
+                .height(iced::Length::Fixed(ICON_SIZE as f32)),
             )
             .style(move |_theme| widget::container::Style {
                 background: Some(iced::Background::Color(background_colour)),
@@ -1,54 +1,62 @@
-use std::{fs, path::PathBuf, sync::LazyLock};
+use std::{fs, path::Path, sync::LazyLock};
 
 use iced::widget;
 
+use crate::icons::RgbaImage;
+
 pub mod list;
 pub use list::ListRow;
 
 const PADDING: f32 = 4.0;
-const SVG_HEIGHT: usize = 64;
-const SVG_WIDTH: usize = 64;
 
-// TODO. Get image from user icon theme
+/// Shared between every SVG render, so the system fonts are only loaded once
+static USVG_OPTIONS: LazyLock<resvg::usvg::Options<'static>> = LazyLock::new(|| {
+    let mut options = resvg::usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    options
+});
+
+/// The icon themes `image-missing`, or a bundled one if the theme doesnt have it
 static MISSING_IMAGE: LazyLock<iced::widget::image::Handle> = LazyLock::new(|| {
-    widget::image::Handle::from_bytes(
-        include_bytes!("../../assets/image-missing-symbolic.png").to_vec(),
-    )
+    crate::icons::find_icon("image-missing", list::ICON_SIZE)
+        .and_then(|path| crate::icons::load_image(&path, list::ICON_SIZE))
+        .unwrap_or_else(|| {
+            widget::image::Handle::from_bytes(
+                include_bytes!("../../assets/image-missing-symbolic.png").to_vec(),
+            )
+        })
 });
 
-/// Uses resvg library to convert `path` to an image, with scaling to fit in bounds.
+/// Uses resvg library to render `path` into a `size` by `size` pixel image.
+/// The aspect ratio is kept, and the SVG is centred in the square.
 /// # Errors
 /// Either, could not load from file, or there are errors in the SVG.
-pub fn svg_path_to_handle(path: PathBuf) -> Result<iced::widget::image::Handle, String> {
+pub fn render_svg(path: &Path, size: u32) -> Result<RgbaImage, String> {
     let contents = fs::read_to_string(path).map_err(|_e| "couldnt read path to string")?;
-    let tree = resvg::usvg::Tree::from_str(&contents, &resvg::usvg::Options::default())
+    let tree = resvg::usvg::Tree::from_str(&contents, &USVG_OPTIONS)
         .map_err(|_e| "Could not turn contents to tree")?;
 
-    let mut data: Box<[u8]> = vec![0; SVG_HEIGHT * SVG_WIDTH * 4].into_boxed_slice();
-
-    let mut pixmap = resvg::tiny_skia::PixmapMut::from_bytes(
-        &mut data,
-        SVG_WIDTH.try_into().expect("Cant fail"),
-        SVG_HEIGHT.try_into().expect("Cant fail"),
-    )
-    .ok_or("Can create pixmap")?;
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(size, size).ok_or("Can create pixmap")?;
 
     let svg_size = tree.size();
     if svg_size.width() == 0.0 || svg_size.height() == 0.0 {
         return Err(String::from("Svg size or width was zero! Cannot scale"));
     }
 
-    let transform = resvg::tiny_skia::Transform::from_scale(
-        SVG_WIDTH as f32 / svg_size.width(),
-        SVG_HEIGHT as f32 / svg_size.height(),
-    );
-    resvg::render(&tree, transform, &mut pixmap);
-
-    Ok(iced::widget::image::Handle::from_rgba(
-        SVG_WIDTH.try_into().expect("cant fail"),
-        SVG_HEIGHT.try_into().expect("cant fail"),
-        data,
-    ))
+    let size_f = size as f32;
+    let scale = (size_f / svg_size.width()).min(size_f / svg_size.height());
+    let offset_x = (size_f - svg_size.width() * scale) / 2.0;
+    let offset_y = (size_f - svg_size.height() * scale) / 2.0;
+
+    let transform =
+        resvg::tiny_skia::Transform::from_scale(scale, scale).post_translate(offset_x, offset_y);
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+    Ok(RgbaImage {
+        width: size,
+        height: size,
+        pixels: pixmap.take(),
+    })
 }
 
 #[allow(dead_code)]
@@ -92,3 +100,37 @@ pub fn heading<'a>(
         })
         .font(font)
 }
+
+#[cfg(test)]
+fn alpha_at(image: &RgbaImage, x: u32, y: u32) -> u8 {
+    image.pixels[((y * image.width + x) * 4 + 3) as usize]
+}
+
+#[test]
+fn wide_svg_keeps_aspect_ratio() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/test/wide.svg");
+    let image = render_svg(&path, 64).unwrap();
+    assert_eq!((image.width, image.height), (64, 64));
+    assert_eq!(image.pixels.len(), 64 * 64 * 4);
+
+    // 200x100 scales to 64x32, centred vertically
+    assert_eq!(alpha_at(&image, 32, 4), 0);
+    assert_eq!(alpha_at(&image, 32, 59), 0);
+    assert_eq!(alpha_at(&image, 32, 32), 255);
+    assert_eq!(alpha_at(&image, 0, 20), 255);
+    assert_eq!(alpha_at(&image, 63, 44), 255);
+}
+
+#[test]
+fn tall_svg_keeps_aspect_ratio_at_any_size() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/test/tall.svg");
+    let image = render_svg(&path, 128).unwrap();
+    assert_eq!((image.width, image.height), (128, 128));
+
+    // 50x100 scales to 64x128, centred horizontally
+    assert_eq!(alpha_at(&image, 8, 64), 0);
+    assert_eq!(alpha_at(&image, 120, 64), 0);
+    assert_eq!(alpha_at(&image, 64, 0), 255);
+    assert_eq!(alpha_at(&image, 33, 127), 255);
+    assert_eq!(alpha_at(&image, 94, 64), 255);
+}